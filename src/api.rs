@@ -1,24 +1,87 @@
-use crate::domain::SqlSchema;
-use serde::{Deserialize, Serialize};
-use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
+use crate::{session::Sessions, store::StateDir, vocab::VocabTrie};
+use axum::{
+    extract::rejection::JsonRejection,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_macros::FromRequest;
+use rusty_picard::{
+    cache::PartialParseCache,
+    decoding::FeedError,
+    domain::{Qpl, SqlSchema, Table},
+    Mode, ParseError, PartialStatus,
 };
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, io};
 use tokenizers::Tokenizer;
 
+/// Name of the tokenizer used when a request does not pick one.
+pub(crate) const DEFAULT_TOKENIZER: &str = "default";
+
 #[derive(Debug, Default)]
 pub(crate) struct ServerState {
     // pub(crate) counter: usize,
-    pub(crate) tokenizer: Arc<Mutex<Option<Tokenizer>>>,
+    pub(crate) tokenizers: HashMap<String, RegisteredTokenizer>,
     pub(crate) schemas: HashMap<String, SqlSchema>,
-    // pub(crate) partial_parses: HashMap<Vec<u32>, PartialParse>,
-    pub(crate) with_type_checking: bool,
+    pub(crate) mode: Mode,
+    pub(crate) state_dir: Option<StateDir>,
+    pub(crate) sessions: Sessions,
+}
+
+impl ServerState {
+    pub(crate) fn tokenizer(&self, name: Option<&str>) -> Result<&RegisteredTokenizer, ApiError> {
+        let name = name.unwrap_or(DEFAULT_TOKENIZER);
+        self.tokenizers
+            .get(name)
+            .ok_or_else(|| ApiError::UnknownTokenizer(name.to_owned()))
+    }
+
+    pub(crate) fn schema(&self, db_id: &str) -> Result<&SqlSchema, ApiError> {
+        self.schemas
+            .get(db_id)
+            .ok_or_else(|| ApiError::UnknownSchema(db_id.to_owned()))
+    }
+
+    pub(crate) fn clear_partial_parses(&self) {
+        for tokenizer in self.tokenizers.values() {
+            tokenizer.partial_parses.clear();
+        }
+        self.sessions.clear_partial_parses();
+    }
+}
+
+/// A tokenizer along with its vocabulary trie and the parser snapshots of its token ids.
+#[derive(Debug)]
+pub(crate) struct RegisteredTokenizer {
+    pub(crate) tokenizer: Tokenizer,
+    pub(crate) vocab_trie: VocabTrie,
+    pub(crate) partial_parses: PartialParseCache,
+}
+
+impl RegisteredTokenizer {
+    pub(crate) fn new(tokenizer: Tokenizer) -> Self {
+        Self {
+            vocab_trie: VocabTrie::from_tokenizer(&tokenizer),
+            tokenizer,
+            partial_parses: PartialParseCache::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct Config {
+    pub(crate) mode: Mode,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub(crate) struct BatchParseRequest {
     pub(crate) input_ids: Vec<Vec<u32>>,
     pub(crate) top_tokens: Vec<Vec<u32>>,
+    #[serde(default)]
+    pub(crate) mode: Option<Mode>,
+    #[serde(default)]
+    pub(crate) tokenizer: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -28,6 +91,62 @@ pub(crate) struct BatchFeedResult {
     pub(crate) feed_result: FeedResult,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct BatchMaskRequest {
+    pub(crate) input_ids: Vec<Vec<u32>>,
+    #[serde(default)]
+    pub(crate) mode: Option<Mode>,
+    #[serde(default)]
+    pub(crate) tokenizer: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct BatchMaskResult {
+    pub(crate) batch_id: u32,
+    pub(crate) valid_tokens: Vec<u32>,
+}
+
+/// Every field is optional, so unknown ones are rejected rather than a misspelt field silently
+/// falling back to its default.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SessionRequest {
+    #[serde(default)]
+    pub(crate) tokenizer: Option<String>,
+    #[serde(default)]
+    pub(crate) db_id: Option<String>,
+    #[serde(default)]
+    pub(crate) mode: Option<Mode>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct SessionResponse {
+    pub(crate) session_id: u64,
+    pub(crate) beam_id: u32,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct SessionFeedRequest {
+    pub(crate) tokens: Vec<BeamToken>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub(crate) struct BeamToken {
+    pub(crate) beam_id: u32,
+    pub(crate) token: u32,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct BeamFeedResult {
+    pub(crate) beam_id: u32,
+    pub(crate) feed_result: FeedResult,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct ForkResult {
+    pub(crate) beam_id: u32,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase", tag = "tag")]
 pub(crate) enum FeedResult {
@@ -36,14 +155,166 @@ pub(crate) enum FeedResult {
     Failure,
 }
 
+impl From<PartialStatus> for FeedResult {
+    fn from(status: PartialStatus) -> Self {
+        match status {
+            PartialStatus::Complete => FeedResult::Complete,
+            PartialStatus::Incomplete => FeedResult::Partial,
+            PartialStatus::Invalid => FeedResult::Failure,
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub(crate) struct ValidationRequest {
     pub(crate) qpl: String,
+    #[serde(default)]
+    pub(crate) mode: Option<Mode>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase", tag = "tag")]
 pub(crate) enum ValidationResult {
     Valid,
-    Invalid { reason: String },
+    Invalid(ValidationError),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase", tag = "tag")]
+pub(crate) enum AnalysisResult {
+    Valid { qpl: Qpl, lines: Vec<LineSchema> },
+    Invalid(ValidationError),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct LineSchema {
+    pub(crate) idx: usize,
+    pub(crate) output: Table,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub(crate) struct ValidationError {
+    pub(crate) reason: String,
+    pub(crate) offset: usize,
+    pub(crate) line: Option<usize>,
+    pub(crate) operator: Option<String>,
+    pub(crate) expected: Vec<String>,
 }
+
+impl From<ParseError> for ValidationError {
+    fn from(error: ParseError) -> Self {
+        Self {
+            reason: error.to_string(),
+            offset: error.offset(),
+            line: error.line(),
+            operator: error.operator().map(|op| op.to_owned()),
+            expected: error.expected().to_vec(),
+        }
+    }
+}
+
+/// Why a request could not be handled, answered with its status and a JSON
+/// `{"error": reason}` body.
+///
+/// Invalid QPL is not an error here, it is reported in the body of a successful response.
+#[derive(Debug)]
+pub(crate) enum ApiError {
+    Json(JsonRejection),
+    UnknownSchema(String),
+    UnknownTokenizer(String),
+    UnknownSession(u64),
+    TooManySessions(usize),
+    UnknownBeam(u32),
+    DuplicateBeam(u32),
+    InvalidSchema(String),
+    InvalidTokenizer(String),
+    /// Name of a schema or tokenizer that cannot be persisted.
+    InvalidName(String),
+    BatchSizeMismatch {
+        input_ids: usize,
+        top_tokens: usize,
+    },
+    Decode(String),
+    Persistence(String, io::Error),
+}
+
+impl ApiError {
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
+            ApiError::Json(rejection) => rejection.status(),
+            ApiError::UnknownSchema(_)
+            | ApiError::UnknownTokenizer(_)
+            | ApiError::UnknownSession(_)
+            | ApiError::UnknownBeam(_) => StatusCode::NOT_FOUND,
+            ApiError::DuplicateBeam(_)
+            | ApiError::InvalidSchema(_)
+            | ApiError::InvalidTokenizer(_)
+            | ApiError::InvalidName(_)
+            | ApiError::BatchSizeMismatch { .. }
+            | ApiError::Decode(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::TooManySessions(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Persistence(..) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Json(rejection) => write!(f, "{}", rejection.body_text()),
+            ApiError::UnknownSchema(db_id) => write!(f, "Schema {db_id} not registered"),
+            ApiError::UnknownTokenizer(name) => write!(f, "Tokenizer {name} not registered"),
+            ApiError::UnknownSession(id) => write!(f, "Session {id} not open"),
+            ApiError::TooManySessions(max) => {
+                write!(f, "{max} sessions already open, close one first")
+            }
+            ApiError::UnknownBeam(id) => write!(f, "Beam {id} not in session"),
+            ApiError::DuplicateBeam(id) => write!(f, "Beam {id} fed more than once"),
+            ApiError::InvalidSchema(reason) => write!(f, "Invalid schema: {reason}"),
+            ApiError::InvalidTokenizer(reason) => write!(f, "Invalid tokenizer: {reason}"),
+            ApiError::InvalidName(reason) => write!(f, "Invalid name: {reason}"),
+            ApiError::BatchSizeMismatch {
+                input_ids,
+                top_tokens,
+            } => write!(
+                f,
+                "{input_ids} input_ids sequences for {top_tokens} top_tokens lists"
+            ),
+            ApiError::Decode(reason) => write!(f, "Cannot decode input_ids: {reason}"),
+            ApiError::Persistence(what, e) => write!(f, "Cannot persist {what}: {e}"),
+        }
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::Json(rejection)
+    }
+}
+
+impl From<FeedError> for ApiError {
+    fn from(error: FeedError) -> Self {
+        match error {
+            FeedError::BatchSizeMismatch {
+                input_ids,
+                top_tokens,
+            } => ApiError::BatchSizeMismatch {
+                input_ids,
+                top_tokens,
+            },
+            FeedError::Decode(reason) => ApiError::Decode(reason),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({ "error": self.to_string() });
+        (self.status(), Json(body)).into_response()
+    }
+}
+
+/// `Json` extractor whose rejections are answered like every other [`ApiError`].
+#[derive(FromRequest)]
+#[from_request(via(Json), rejection(ApiError))]
+pub(crate) struct ApiJson<T>(pub(crate) T);
@@ -0,0 +1,280 @@
+use rusty_picard::{
+    domain::{SqlSchema, Table},
+    parse_prefixed, suggest, Mode, ParseError, Suggestion,
+};
+use std::collections::{BTreeMap, HashMap};
+use tower_lsp::lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionTextEdit, Diagnostic, DiagnosticSeverity,
+    Position, Range, TextEdit,
+};
+
+/// A QPL file, holding one `<db_id> | <qpl>` query per line.
+#[derive(Debug)]
+pub(crate) struct Document {
+    lines: Vec<Query>,
+}
+
+#[derive(Debug)]
+struct Query {
+    text: String,
+    /// Output tables of the lines parsed before the first error, if any.
+    outputs: BTreeMap<usize, Table>,
+    error: Option<ParseError>,
+}
+
+impl Document {
+    pub(crate) fn new(text: &str, schemas: &HashMap<String, SqlSchema>, mode: Mode) -> Self {
+        let lines = text
+            .lines()
+            .map(|text| Query::new(text, schemas, mode))
+            .collect();
+        Self { lines }
+    }
+
+    pub(crate) fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.lines
+            .iter()
+            .zip(0..)
+            .filter_map(|(query, line)| {
+                let error = query.error.as_ref()?;
+                let start = error.offset();
+                let end = query.text[start..]
+                    .find(char::is_whitespace)
+                    .map_or(query.text.len(), |len| start + len);
+                Some(Diagnostic {
+                    range: Range::new(query.position(line, start), query.position(line, end)),
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    source: Some("qpl".to_owned()),
+                    message: error.to_string(),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    /// Output columns of the line whose id is under `position`, as Markdown.
+    pub(crate) fn hover(&self, position: Position) -> Option<(Range, String)> {
+        let (query, offset) = self.query_at(position)?;
+        let (start, end, idx) = query.reference_at(offset)?;
+        let output = query.outputs.get(&idx)?;
+        let columns = output
+            .columns()
+            .iter()
+            .map(|column| format!("- `{}`: {:?}", column.name(), column.typ()))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let range = Range::new(
+            query.position(position.line, start),
+            query.position(position.line, end),
+        );
+        Some((range, format!("`#{idx}` outputs:\n{columns}")))
+    }
+
+    /// Range of the `#<n>` starting the line whose id is under `position`.
+    pub(crate) fn definition(&self, position: Position) -> Option<Range> {
+        let (query, offset) = self.query_at(position)?;
+        let (_, _, idx) = query.reference_at(offset)?;
+        let id = format!("#{idx}");
+        let start = query.text.find(&format!("{id} = "))?;
+        Some(Range::new(
+            query.position(position.line, start),
+            query.position(position.line, start + id.len()),
+        ))
+    }
+
+    pub(crate) fn completions(
+        &self,
+        position: Position,
+        schemas: &HashMap<String, SqlSchema>,
+        mode: Mode,
+    ) -> Vec<CompletionItem> {
+        let Some((query, offset)) = self.query_at(position) else {
+            return vec![];
+        };
+        let suggestions = suggest(&query.text[..offset], schemas, mode);
+        let range = Range::new(
+            query.position(position.line, suggestions.start),
+            query.position(position.line, offset),
+        );
+        suggestions
+            .suggestions
+            .iter()
+            .filter_map(|suggestion| {
+                let text = suggestion.text()?;
+                Some(CompletionItem {
+                    label: text.clone(),
+                    kind: Some(completion_kind(suggestion)),
+                    text_edit: Some(CompletionTextEdit::Edit(TextEdit::new(range, text))),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    /// The query on the line of `position`, along with the byte offset of `position` in it.
+    fn query_at(&self, position: Position) -> Option<(&Query, usize)> {
+        let query = self.lines.get(usize::try_from(position.line).ok()?)?;
+        Some((query, query.offset(position.character)))
+    }
+}
+
+impl Query {
+    fn new(text: &str, schemas: &HashMap<String, SqlSchema>, mode: Mode) -> Self {
+        let text = text.to_owned();
+        if text.trim().is_empty() {
+            return Self {
+                text,
+                outputs: BTreeMap::new(),
+                error: None,
+            };
+        }
+        match parse_prefixed(&text, schemas, mode) {
+            Ok(parsed) => Self {
+                text,
+                outputs: parsed.outputs,
+                error: None,
+            },
+            Err(error) => {
+                // The lines before the failing one still have outputs to show.
+                let outputs = text[..error.offset()]
+                    .rfind(" ; ")
+                    .and_then(|end| parse_prefixed(&text[..end], schemas, mode).ok())
+                    .map(|parsed| parsed.outputs)
+                    .unwrap_or_default();
+                Self {
+                    text,
+                    outputs,
+                    error: Some(error),
+                }
+            }
+        }
+    }
+
+    /// Byte range and id of the `#<n>` reference around `offset`, as in `#3` or `#3.Name`.
+    fn reference_at(&self, offset: usize) -> Option<(usize, usize, usize)> {
+        let start = self.text[..offset].rfind(|c: char| c.is_whitespace() || c == '[')?;
+        let start = start + 1;
+        let digits = self.text[start..].strip_prefix('#')?;
+        let len = digits
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(digits.len());
+        let end = start + 1 + len;
+        if offset > end {
+            return None;
+        }
+        Some((start, end, digits[..len].parse().ok()?))
+    }
+
+    /// Byte offset of the UTF-16 `character` of the line.
+    fn offset(&self, character: u32) -> usize {
+        let mut units = 0;
+        for (offset, c) in self.text.char_indices() {
+            if units >= character as usize {
+                return offset;
+            }
+            units += c.len_utf16();
+        }
+        self.text.len()
+    }
+
+    fn position(&self, line: u32, offset: usize) -> Position {
+        let character = self.text[..offset].encode_utf16().count();
+        Position::new(line, character as u32)
+    }
+}
+
+fn completion_kind(suggestion: &Suggestion) -> CompletionItemKind {
+    match suggestion {
+        Suggestion::Database(_) => CompletionItemKind::MODULE,
+        Suggestion::Keyword(_) => CompletionItemKind::KEYWORD,
+        Suggestion::Reference(_) => CompletionItemKind::REFERENCE,
+        Suggestion::Table(_) => CompletionItemKind::CLASS,
+        Suggestion::Column(_) => CompletionItemKind::FIELD,
+        Suggestion::Aggregate(_) => CompletionItemKind::FUNCTION,
+        Suggestion::ComparisonOp(_) => CompletionItemKind::OPERATOR,
+        Suggestion::Literal(_) => CompletionItemKind::VALUE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::read_schemas;
+    use std::fs;
+
+    const TABLES: &str = r#"[{
+        "db_id": "concert_singer",
+        "table_names_original": ["singer"],
+        "column_names_original": [[-1, "*"], [0, "Singer_ID"], [0, "Name"], [0, "Age"]],
+        "column_types": ["text", "number", "text", "number"],
+        "primary_keys": [1]
+    }]"#;
+
+    const TEXT: &str = "concert_singer | #1 = Scan Table [ singer ] Output [ Singer_ID , Age ] ; #2 = Aggregate [ #1 ] Output [ MAX(Age) AS Max_Age ]
+
+concert_singer | #1 = Scan Table [ singer ] Output [ Name ] ; #2 = Filter [ #1 ] Predicate [ Age = 1 ] Output [ Name ]";
+
+    fn document() -> (Document, HashMap<String, SqlSchema>) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tables.json");
+        fs::write(&path, TABLES).unwrap();
+        let schemas = read_schemas(&path).unwrap();
+        (
+            Document::new(TEXT, &schemas, Mode::ParseWithGuards),
+            schemas,
+        )
+    }
+
+    fn position_of(line: u32, pattern: &str) -> Position {
+        let text = TEXT.lines().nth(line as usize).unwrap();
+        Position::new(line, text.find(pattern).unwrap() as u32)
+    }
+
+    #[test]
+    fn test_diagnostics_point_at_failing_token() {
+        let (document, _) = document();
+        let diagnostics = document.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        let start = position_of(2, "Age = 1");
+        assert_eq!(diagnostics[0].range.start, start);
+        assert_eq!(diagnostics[0].range.end.character, start.character + 3);
+        assert!(diagnostics[0].message.contains("line #2"));
+    }
+
+    #[test]
+    fn test_hover_and_definition_of_references() {
+        let (document, _) = document();
+        let (_, hover) = document.hover(position_of(0, "#1 ]")).unwrap();
+        assert_eq!(
+            hover,
+            "`#1` outputs:\n- `Singer_ID`: Number\n- `Age`: Number"
+        );
+        let position = position_of(0, "#2 =");
+        assert!(document.hover(position).unwrap().1.contains("Max_Age"));
+
+        // Lines before an invalid one keep their outputs.
+        let (_, hover) = document.hover(position_of(2, "#1 ]")).unwrap();
+        assert_eq!(hover, "`#1` outputs:\n- `Name`: Text");
+        assert_eq!(document.hover(position_of(2, "#2 =")), None);
+
+        let definition = document.definition(position_of(0, "#1 ]")).unwrap();
+        assert_eq!(definition.start, position_of(0, "#1 ="));
+        assert_eq!(definition.end.character, definition.start.character + 2);
+        assert_eq!(document.definition(position_of(0, "Scan")), None);
+    }
+
+    #[test]
+    fn test_completions_replace_current_word() {
+        let (document, schemas) = document();
+        let mut position = position_of(0, "singer ]");
+        position.character += 3;
+        let completions = document.completions(position, &schemas, Mode::ParseWithGuards);
+        assert_eq!(completions.len(), 1);
+        assert_eq!(completions[0].label, "singer");
+        let Some(CompletionTextEdit::Edit(edit)) = &completions[0].text_edit else {
+            panic!("expected a text edit");
+        };
+        assert_eq!(edit.range.start, position_of(0, "singer ]"));
+        assert_eq!(edit.range.end, position);
+    }
+}
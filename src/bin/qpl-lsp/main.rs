@@ -0,0 +1,217 @@
+//! Language server for QPL files, speaking LSP over the standard input and output.
+//!
+//! Each line of a QPL file is a `<db_id> | <qpl>` query, checked against the schemas of the
+//! workspace's Spider-style tables.json.
+
+use document::Document;
+use rusty_picard::{domain::SqlSchema, spider::SpiderSchema, Mode};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+use tower_lsp::{
+    jsonrpc::Result,
+    lsp_types::{
+        CompletionOptions, CompletionParams, CompletionResponse, DidChangeTextDocumentParams,
+        DidCloseTextDocumentParams, DidOpenTextDocumentParams, GotoDefinitionParams,
+        GotoDefinitionResponse, Hover, HoverContents, HoverParams, HoverProviderCapability,
+        InitializeParams, InitializeResult, InitializedParams, Location, MarkupContent, MarkupKind,
+        MessageType, OneOf, ServerCapabilities, ServerInfo, TextDocumentSyncCapability,
+        TextDocumentSyncKind, Url,
+    },
+    Client, LanguageServer, LspService, Server,
+};
+
+mod document;
+
+/// Where the schemas are looked for in the workspace, unless the client says otherwise.
+const TABLES_FILE: &str = "tables.json";
+
+/// Options the client can send along with the `initialize` request.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct InitializationOptions {
+    /// Path of the tables.json, relative to the workspace root.
+    tables: Option<PathBuf>,
+    mode: Option<Mode>,
+}
+
+struct Backend {
+    client: Client,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    schemas: HashMap<String, SqlSchema>,
+    mode: Mode,
+    documents: HashMap<Url, Document>,
+}
+
+impl Backend {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    async fn update(&self, uri: Url, text: &str, version: i32) {
+        let diagnostics = {
+            let mut state = self.state();
+            let document = Document::new(text, &state.schemas, state.mode);
+            let diagnostics = document.diagnostics();
+            state.documents.insert(uri.clone(), document);
+            diagnostics
+        };
+        self.client
+            .publish_diagnostics(uri, diagnostics, Some(version))
+            .await;
+    }
+}
+
+#[tower_lsp::async_trait]
+impl LanguageServer for Backend {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        let options: InitializationOptions = params
+            .initialization_options
+            .and_then(|options| serde_json::from_value(options).ok())
+            .unwrap_or_default();
+        let root = params
+            .workspace_folders
+            .and_then(|folders| folders.into_iter().next())
+            .and_then(|folder| folder.uri.to_file_path().ok())
+            .unwrap_or_default();
+        let tables = root.join(options.tables.as_deref().unwrap_or(Path::new(TABLES_FILE)));
+        let schemas = match read_schemas(&tables) {
+            Ok(schemas) => schemas,
+            Err(e) => {
+                self.client.show_message(MessageType::ERROR, e).await;
+                HashMap::new()
+            }
+        };
+        {
+            let mut state = self.state();
+            state.schemas = schemas;
+            state.mode = options.mode.unwrap_or_default();
+        }
+
+        Ok(InitializeResult {
+            capabilities: ServerCapabilities {
+                text_document_sync: Some(TextDocumentSyncCapability::Kind(
+                    TextDocumentSyncKind::FULL,
+                )),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                definition_provider: Some(OneOf::Left(true)),
+                completion_provider: Some(CompletionOptions {
+                    trigger_characters: Some(vec![" ".to_owned(), "#".to_owned()]),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+            server_info: Some(ServerInfo {
+                name: env!("CARGO_BIN_NAME").to_owned(),
+                version: Some(env!("CARGO_PKG_VERSION").to_owned()),
+            }),
+        })
+    }
+
+    async fn initialized(&self, _: InitializedParams) {
+        let schemas = self.state().schemas.len();
+        self.client
+            .log_message(MessageType::INFO, format!("Loaded {schemas} schemas"))
+            .await;
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn did_open(&self, params: DidOpenTextDocumentParams) {
+        let document = params.text_document;
+        self.update(document.uri, &document.text, document.version)
+            .await;
+    }
+
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        // Full sync, the last change holds the whole text.
+        if let Some(change) = params.content_changes.into_iter().last() {
+            let document = params.text_document;
+            self.update(document.uri, &change.text, document.version)
+                .await;
+        }
+    }
+
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri;
+        self.state().documents.remove(&uri);
+        self.client.publish_diagnostics(uri, vec![], None).await;
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let params = params.text_document_position_params;
+        let state = self.state();
+        let hover = state
+            .documents
+            .get(&params.text_document.uri)
+            .and_then(|document| document.hover(params.position))
+            .map(|(range, value)| Hover {
+                contents: HoverContents::Markup(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value,
+                }),
+                range: Some(range),
+            });
+        Ok(hover)
+    }
+
+    async fn goto_definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>> {
+        let params = params.text_document_position_params;
+        let uri = params.text_document.uri;
+        let state = self.state();
+        let definition = state
+            .documents
+            .get(&uri)
+            .and_then(|document| document.definition(params.position))
+            .map(|range| GotoDefinitionResponse::Scalar(Location::new(uri.clone(), range)));
+        Ok(definition)
+    }
+
+    async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        let params = params.text_document_position;
+        let state = self.state();
+        let completions = state
+            .documents
+            .get(&params.text_document.uri)
+            .map(|document| document.completions(params.position, &state.schemas, state.mode))
+            .map(CompletionResponse::Array);
+        Ok(completions)
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let (service, socket) = LspService::new(|client| Backend {
+        client,
+        state: Mutex::default(),
+    });
+    Server::new(tokio::io::stdin(), tokio::io::stdout(), socket)
+        .serve(service)
+        .await;
+}
+
+fn read_schemas(path: &Path) -> std::result::Result<HashMap<String, SqlSchema>, String> {
+    let contents = fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let tables: Vec<SpiderSchema> =
+        serde_json::from_slice(&contents).map_err(|e| format!("{}: {e}", path.display()))?;
+    tables
+        .into_iter()
+        .map(|table| {
+            let schema = SqlSchema::try_from(table)?;
+            Ok((schema.db_id.clone(), schema))
+        })
+        .collect()
+}
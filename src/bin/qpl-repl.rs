@@ -0,0 +1,208 @@
+//! Reads QPL lines one at a time against a Spider schema, printing the output of each line.
+
+use rusty_picard::{
+    domain::{SqlSchema, Table},
+    spider::SpiderSchema,
+    LineParser, Mode,
+};
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter,
+    history::DefaultHistory, validate::Validator, Context, Editor, Helper,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(clap::Parser, Debug)]
+struct Args {
+    /// Spider-style tables.json holding the schema of the database.
+    #[arg(long)]
+    tables: PathBuf,
+    /// Database the lines are checked against.
+    db_id: String,
+    /// How strictly lines are checked, e.g. `parse_with_guards_and_type_checks`.
+    #[arg(long, default_value = "parse_with_guards", value_parser = parse_mode)]
+    mode: Mode,
+}
+
+/// Completes the line being typed against the lines entered so far.
+struct QplHelper {
+    parser: LineParser,
+}
+
+impl Helper for QplHelper {}
+
+impl Completer for QplHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete(&self.parser, &line[..pos]))
+    }
+}
+
+impl Hinter for QplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for QplHelper {}
+
+impl Validator for QplHelper {}
+
+fn main() {
+    let args = <Args as clap::Parser>::parse();
+    if let Err(e) = run(args) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let schema = read_schema(&args.tables, &args.db_id)?;
+    let mut editor = Editor::<QplHelper, DefaultHistory>::new().map_err(|e| e.to_string())?;
+    editor.set_helper(Some(QplHelper {
+        parser: LineParser::new(&schema, args.mode),
+    }));
+
+    loop {
+        let parser = &editor.helper().expect("helper is set").parser;
+        let prompt = line_start(parser.next_idx());
+        let typed = match editor.readline(&prompt) {
+            Ok(typed) => typed,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(e) => return Err(e.to_string()),
+        };
+        if typed.trim().is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(typed.as_str());
+
+        let parser = &mut editor.helper_mut().expect("helper is set").parser;
+        let source = with_line_start(parser.next_idx(), &typed);
+        match parser.push(&source) {
+            Ok(line) => {
+                if let Some(output) = parser.output(line.idx) {
+                    println!("{}", format_output(line.idx, output));
+                }
+            }
+            Err(error) => {
+                // The prompt reads like the line start, so the caret lines up either way.
+                let column = prompt.len() + error.offset() - (source.len() - typed.len());
+                eprintln!("{}^", " ".repeat(column));
+                eprintln!("{error}");
+            }
+        }
+    }
+}
+
+fn parse_mode(mode: &str) -> Result<Mode, String> {
+    serde_json::from_value(serde_json::Value::String(mode.to_owned())).map_err(|e| e.to_string())
+}
+
+fn read_schema(path: &Path, db_id: &str) -> Result<SqlSchema, String> {
+    let contents = fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let tables: Vec<SpiderSchema> =
+        serde_json::from_slice(&contents).map_err(|e| format!("{}: {e}", path.display()))?;
+    for table in tables {
+        let schema = SqlSchema::try_from(table)?;
+        if schema.db_id == db_id {
+            return Ok(schema);
+        }
+    }
+    Err(format!("{}: no database {db_id}", path.display()))
+}
+
+fn line_start(idx: usize) -> String {
+    format!("#{idx} = ")
+}
+
+/// Lines can be typed with or without their `#<n> = ` start, which the prompt shows.
+fn with_line_start(idx: usize, typed: &str) -> String {
+    if typed.trim_start().starts_with('#') {
+        typed.to_owned()
+    } else {
+        format!("{}{typed}", line_start(idx))
+    }
+}
+
+/// Completions of the last word of `typed`, with the offset of that word in `typed`.
+fn complete(parser: &LineParser, typed: &str) -> (usize, Vec<String>) {
+    let source = with_line_start(parser.next_idx(), typed);
+    let (start, candidates) = parser.completions(&source);
+    (start - (source.len() - typed.len()), candidates)
+}
+
+fn format_output(idx: usize, output: &Table) -> String {
+    let columns = output
+        .columns()
+        .iter()
+        .map(|column| format!("{} {:?}", column.name(), column.typ()))
+        .collect::<Vec<_>>()
+        .join(" , ");
+    format!("#{idx} -> [ {columns} ]")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLES: &str = r#"[{
+        "db_id": "concert_singer",
+        "table_names_original": ["singer"],
+        "column_names_original": [[-1, "*"], [0, "Singer_ID"], [0, "Name"], [0, "Age"]],
+        "column_types": ["text", "number", "text", "number"],
+        "primary_keys": [1]
+    }]"#;
+
+    fn parser() -> LineParser {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tables.json");
+        fs::write(&path, TABLES).unwrap();
+        assert!(read_schema(&path, "world_1").is_err());
+        LineParser::new(
+            &read_schema(&path, "concert_singer").unwrap(),
+            parse_mode("parse_with_guards").unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_lines_are_typed_with_or_without_line_start() {
+        let mut parser = parser();
+        let line = with_line_start(1, "Scan Table [ singer ] Output [ Singer_ID , Age ]");
+        let line = parser.push(&line).unwrap();
+        assert_eq!(
+            format_output(line.idx, parser.output(line.idx).unwrap()),
+            "#1 -> [ Singer_ID Number , Age Number ]"
+        );
+
+        let line = with_line_start(
+            2,
+            "#2 = Aggregate [ #1 ] Output [ countstar AS Count_Star ]",
+        );
+        assert!(parser.push(&line).is_ok());
+    }
+
+    #[test]
+    fn test_complete_offsets_are_relative_to_typed_text() {
+        let mut parser = parser();
+        assert_eq!(complete(&parser, "Sc"), (0, vec!["Scan".to_owned()]));
+        assert_eq!(
+            complete(&parser, "#1 = Scan Table [ si"),
+            (18, vec!["singer".to_owned()])
+        );
+
+        parser
+            .push("#1 = Scan Table [ singer ] Output [ Age ]")
+            .unwrap();
+        assert_eq!(
+            complete(&parser, "Aggregate [ #1 ] Output [ MI"),
+            (26, vec!["MIN(Age) AS Min_Age".to_owned()])
+        );
+    }
+}
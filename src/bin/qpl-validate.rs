@@ -0,0 +1,287 @@
+//! Checks a dataset of QPL queries against their Spider schemas and reports what fails.
+
+use rusty_picard::{domain::SqlSchema, parse_prefixed, spider::SpiderSchema, Mode, ParseError};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Modes every query is checked with, from the most to the least permissive.
+const MODES: [Mode; 2] = [Mode::ParseWithGuards, Mode::ParseWithGuardsAndTypeChecks];
+
+#[derive(clap::Parser, Debug)]
+struct Args {
+    /// Spider-style tables.json holding the schemas of the dataset's databases.
+    #[arg(long)]
+    tables: PathBuf,
+    /// Dataset of `db_id` and `qpl` pairs, as JSON lines or, for a `.csv` file, CSV with a
+    /// header row.
+    dataset: PathBuf,
+    /// Where to write the JSON report instead of the standard output.
+    #[arg(long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Example {
+    db_id: String,
+    qpl: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Report {
+    modes: Vec<ModeReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct ModeReport {
+    mode: Mode,
+    total: Counts,
+    databases: BTreeMap<String, Counts>,
+    /// Accepted queries using each operator, and rejected queries failing within it.
+    operators: BTreeMap<String, Counts>,
+    failures: Vec<Failure>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Counts {
+    passed: usize,
+    failed: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct Failure {
+    /// Position of the query in the dataset, starting from 1.
+    example: usize,
+    db_id: String,
+    /// Byte offset in the query where parsing failed.
+    offset: usize,
+    line: Option<usize>,
+    operator: Option<String>,
+    reason: String,
+}
+
+fn main() {
+    let args = <Args as clap::Parser>::parse();
+    if let Err(e) = run(args) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let schemas = read_schemas(&args.tables)?;
+    let examples = read_dataset(&args.dataset)?;
+    let report = validate(&examples, &schemas);
+    for mode_report in &report.modes {
+        let ModeReport { mode, total, .. } = mode_report;
+        eprintln!("{mode:?}: {} passed, {} failed", total.passed, total.failed);
+    }
+
+    let report = serde_json::to_string_pretty(&report).map_err(|e| e.to_string())?;
+    match &args.output {
+        Some(path) => fs::write(path, report).map_err(|e| format!("{}: {e}", path.display())),
+        None => writeln!(io::stdout(), "{report}").map_err(|e| e.to_string()),
+    }
+}
+
+fn read_schemas(path: &Path) -> Result<HashMap<String, SqlSchema>, String> {
+    let contents = fs::read(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    let tables: Vec<SpiderSchema> =
+        serde_json::from_slice(&contents).map_err(|e| format!("{}: {e}", path.display()))?;
+    tables
+        .into_iter()
+        .map(|table| {
+            let schema = SqlSchema::try_from(table)?;
+            Ok((schema.db_id.clone(), schema))
+        })
+        .collect()
+}
+
+fn read_dataset(path: &Path) -> Result<Vec<Example>, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("{}: {e}", path.display()))?;
+    if path.extension().is_some_and(|ext| ext == "csv") {
+        csv::Reader::from_reader(contents.as_bytes())
+            .deserialize()
+            .enumerate()
+            .map(|(i, example)| {
+                example.map_err(|e| format!("{} row {}: {e}", path.display(), i + 1))
+            })
+            .collect()
+    } else {
+        contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line)
+                    .map_err(|e| format!("{} line {}: {e}", path.display(), i + 1))
+            })
+            .collect()
+    }
+}
+
+fn validate(examples: &[Example], schemas: &HashMap<String, SqlSchema>) -> Report {
+    let modes = MODES
+        .into_iter()
+        .map(|mode| {
+            let mut report = ModeReport {
+                mode,
+                total: Counts::default(),
+                databases: BTreeMap::new(),
+                operators: BTreeMap::new(),
+                failures: vec![],
+            };
+            for (i, example) in examples.iter().enumerate() {
+                report.add(i + 1, example, check(example, schemas, mode));
+            }
+            report
+        })
+        .collect();
+    Report { modes }
+}
+
+/// Returns the operators of an accepted query, or the error of a rejected one with its offset
+/// made relative to the query.
+fn check(
+    example: &Example,
+    schemas: &HashMap<String, SqlSchema>,
+    mode: Mode,
+) -> Result<BTreeSet<&'static str>, (usize, ParseError)> {
+    let prefix = format!("{} | ", example.db_id);
+    let source = format!("{prefix}{}", example.qpl);
+    match parse_prefixed(&source, schemas, mode) {
+        Ok(parsed) => Ok(parsed
+            .qpl
+            .iter()
+            .map(|line| line.operation.name())
+            .collect()),
+        Err(error) => Err((error.offset().saturating_sub(prefix.len()), error)),
+    }
+}
+
+impl ModeReport {
+    fn add(
+        &mut self,
+        example_idx: usize,
+        example: &Example,
+        result: Result<BTreeSet<&'static str>, (usize, ParseError)>,
+    ) {
+        let database = self.databases.entry(example.db_id.clone()).or_default();
+        match result {
+            Ok(operators) => {
+                self.total.passed += 1;
+                database.passed += 1;
+                for operator in operators {
+                    self.operators
+                        .entry(operator.to_owned())
+                        .or_default()
+                        .passed += 1;
+                }
+            }
+            Err((offset, error)) => {
+                self.total.failed += 1;
+                database.failed += 1;
+                if let Some(operator) = error.operator() {
+                    self.operators
+                        .entry(operator.to_owned())
+                        .or_default()
+                        .failed += 1;
+                }
+                self.failures.push(Failure {
+                    example: example_idx,
+                    db_id: example.db_id.clone(),
+                    offset,
+                    line: error.line(),
+                    operator: error.operator().map(|op| op.to_owned()),
+                    reason: error.to_string(),
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLES: &str = r#"[{
+        "db_id": "concert_singer",
+        "table_names_original": ["singer"],
+        "column_names_original": [[-1, "*"], [0, "Singer_ID"], [0, "Name"], [0, "Age"]],
+        "column_types": ["text", "number", "text", "number"],
+        "primary_keys": [1]
+    }]"#;
+
+    fn schemas() -> HashMap<String, SqlSchema> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tables.json");
+        fs::write(&path, TABLES).unwrap();
+        read_schemas(&path).unwrap()
+    }
+
+    #[test]
+    fn test_read_dataset_accepts_json_lines_and_csv() {
+        let dir = tempfile::tempdir().unwrap();
+        let jsonl = dir.path().join("dev.jsonl");
+        fs::write(
+            &jsonl,
+            "{\"db_id\": \"concert_singer\", \"qpl\": \"#1 = Scan Table [ singer ] Output [ Name ]\"}\n\n",
+        )
+        .unwrap();
+        let csv = dir.path().join("dev.csv");
+        fs::write(
+            &csv,
+            "db_id,qpl\nconcert_singer,\"#1 = Scan Table [ singer ] Output [ Name , Age ]\"\n",
+        )
+        .unwrap();
+
+        let examples = read_dataset(&jsonl).unwrap();
+        assert_eq!(examples.len(), 1);
+        let examples = read_dataset(&csv).unwrap();
+        assert_eq!(
+            examples[0].qpl,
+            "#1 = Scan Table [ singer ] Output [ Name , Age ]"
+        );
+    }
+
+    #[test]
+    fn test_validate_counts_per_database_and_operator() {
+        let example = |db_id: &str, qpl: &str| Example {
+            db_id: db_id.to_owned(),
+            qpl: qpl.to_owned(),
+        };
+        let examples = [
+            example(
+                "concert_singer",
+                "#1 = Scan Table [ singer ] Output [ Age ] ; #2 = Aggregate [ #1 ] Output [ MAX(Age) AS Max_Age ]",
+            ),
+            example(
+                "concert_singer",
+                "#1 = Scan Table [ singer ] Predicate [ Age = 'old' ] Output [ Name ]",
+            ),
+            example("concert_singer", "#1 = Scan Table [ singer ] Output [ Foo ]"),
+            example("world_1", "#1 = Scan Table [ city ] Output [ Name ]"),
+        ];
+        let report = validate(&examples, &schemas());
+
+        let [with_guards, with_type_checks] = &report.modes[..] else {
+            panic!("expected a report per mode");
+        };
+        assert_eq!(with_guards.total.passed, 2);
+        assert_eq!(with_guards.databases["concert_singer"].failed, 1);
+        assert_eq!(with_guards.databases["world_1"].failed, 1);
+        assert_eq!(with_guards.operators["Scan"].passed, 2);
+        assert_eq!(with_guards.operators["Scan"].failed, 1);
+        assert_eq!(with_guards.operators["Aggregate"].passed, 1);
+
+        assert_eq!(with_type_checks.total.passed, 1);
+        let failure = &with_type_checks.failures[0];
+        assert_eq!(failure.example, 2);
+        assert_eq!(failure.offset, examples[1].qpl.find("'old'").unwrap());
+        assert_eq!(failure.operator.as_deref(), Some("Scan"));
+    }
+}
@@ -0,0 +1,50 @@
+use crate::{Mode, PartialParse};
+use lru::LruCache;
+use std::{
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+};
+
+const DEFAULT_CAPACITY: usize = 4096;
+
+type CacheKey = (Mode, Vec<u32>);
+
+/// Line-boundary parser snapshots keyed by the parsing mode and the token ids decoded so far,
+/// evicted LRU.
+#[derive(Debug)]
+pub struct PartialParseCache {
+    entries: Mutex<LruCache<CacheKey, Arc<PartialParse>>>,
+}
+
+impl PartialParseCache {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            entries: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    pub fn get(&self, mode: Mode, input_ids: &[u32]) -> Option<Arc<PartialParse>> {
+        let key = (mode, input_ids.to_vec());
+        self.entries().get(&key).cloned()
+    }
+
+    pub fn put(&self, mode: Mode, input_ids: Vec<u32>, partial_parse: Arc<PartialParse>) {
+        self.entries().put((mode, input_ids), partial_parse);
+    }
+
+    pub fn clear(&self) {
+        self.entries().clear();
+    }
+
+    /// A snapshot is only ever inserted whole, so a panic while the lock was held cannot
+    /// leave the cache inconsistent.
+    fn entries(&self) -> MutexGuard<'_, LruCache<CacheKey, Arc<PartialParse>>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for PartialParseCache {
+    fn default() -> Self {
+        Self::new(NonZeroUsize::new(DEFAULT_CAPACITY).unwrap())
+    }
+}
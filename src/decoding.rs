@@ -0,0 +1,137 @@
+//! Checks the tokens a model proposes at each decoding step, PICARD-style.
+
+use crate::{
+    cache::PartialParseCache, domain::SqlSchema, parse_partial, Mode, PartialParse, PartialStatus,
+};
+use rayon::prelude::*;
+use std::{collections::HashMap, fmt, sync::Arc};
+use tokenizers::Tokenizer;
+
+/// Marks the end of the generated text, after which the QPL has to be complete.
+pub const EOS: &str = "</s>";
+
+#[derive(Debug)]
+pub enum FeedError {
+    BatchSizeMismatch { input_ids: usize, top_tokens: usize },
+    Decode(String),
+}
+
+impl fmt::Display for FeedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FeedError::BatchSizeMismatch {
+                input_ids,
+                top_tokens,
+            } => write!(
+                f,
+                "{input_ids} input_ids sequences for {top_tokens} top_tokens lists"
+            ),
+            FeedError::Decode(reason) => write!(f, "Cannot decode input_ids: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for FeedError {}
+
+/// Status of the `batch_id`-th sequence of a batch once `top_token` is appended to it.
+#[derive(Debug)]
+pub struct FedToken {
+    pub batch_id: u32,
+    pub top_token: u32,
+    pub status: PartialStatus,
+}
+
+/// Appends each of `top_tokens[i]` to `input_ids[i]` and checks the decoded texts in parallel.
+///
+/// Parser snapshots are looked up in and added to `partial_parses`, so that the next step
+/// only parses from the last line boundary. Only the parse is resumed: each candidate is
+/// still detokenized whole, which sessions avoid by decoding their beams incrementally.
+pub fn batch_feed(
+    input_ids: &[Vec<u32>],
+    top_tokens: &[Vec<u32>],
+    tokenizer: &Tokenizer,
+    partial_parses: &PartialParseCache,
+    schemas: &HashMap<String, SqlSchema>,
+    mode: Mode,
+) -> Result<Vec<FedToken>, FeedError> {
+    if input_ids.len() != top_tokens.len() {
+        return Err(FeedError::BatchSizeMismatch {
+            input_ids: input_ids.len(),
+            top_tokens: top_tokens.len(),
+        });
+    }
+    let triplets = top_tokens
+        .iter()
+        .zip(input_ids.iter())
+        .zip(0..)
+        .flat_map(|((tokens, inputs), batch_id)| tokens.iter().map(move |t| (batch_id, inputs, *t)))
+        .collect::<Vec<_>>();
+
+    triplets
+        .into_par_iter()
+        .map(|(batch_id, input_ids, top_token)| {
+            let status = feed(
+                input_ids,
+                top_token,
+                tokenizer,
+                partial_parses,
+                schemas,
+                mode,
+            )?;
+            Ok(FedToken {
+                batch_id,
+                top_token,
+                status,
+            })
+        })
+        .collect()
+}
+
+/// Status of `input_ids` once `token` is appended, see [`batch_feed`]. The whole sequence is
+/// detokenized again, so the cost of a step grows with the length of `input_ids`.
+pub fn feed(
+    input_ids: &[u32],
+    token: u32,
+    tokenizer: &Tokenizer,
+    partial_parses: &PartialParseCache,
+    schemas: &HashMap<String, SqlSchema>,
+    mode: Mode,
+) -> Result<PartialStatus, FeedError> {
+    let mut tokenizer_input = Vec::from(input_ids);
+    tokenizer_input.push(token);
+
+    let decoded = detokenize(&tokenizer_input, tokenizer)?;
+    let resume_from = partial_parses.get(mode, input_ids);
+
+    let (status, partial_parse) = feed_decoded(&decoded, schemas, mode, resume_from.as_deref());
+
+    if status != PartialStatus::Invalid {
+        if let Some(partial_parse) = partial_parse.map(Arc::new).or(resume_from) {
+            partial_parses.put(mode, tokenizer_input, partial_parse);
+        }
+    }
+
+    Ok(status)
+}
+
+/// Checks generated text, which is complete once it ends with [`EOS`].
+pub fn feed_decoded(
+    decoded: &str,
+    schemas: &HashMap<String, SqlSchema>,
+    mode: Mode,
+    resume_from: Option<&PartialParse>,
+) -> (PartialStatus, Option<PartialParse>) {
+    parse_partial(
+        decoded.strip_suffix(EOS).unwrap_or(decoded),
+        schemas,
+        mode,
+        decoded.ends_with(EOS),
+        resume_from,
+    )
+}
+
+pub fn detokenize(input_ids: &[u32], tokenizer: &Tokenizer) -> Result<String, FeedError> {
+    tokenizer
+        .decode(input_ids, false)
+        .map_err(|e| FeedError::Decode(e.to_string()))
+}
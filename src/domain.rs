@@ -1,9 +1,14 @@
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    fmt,
+    ops::Deref,
+};
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
-pub(crate) enum ColumnType {
+pub enum ColumnType {
     Number,
     Boolean,
     Text,
@@ -11,52 +16,115 @@ pub(crate) enum ColumnType {
     Others,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub(crate) struct SqlSchema {
-    pub(crate) db_id: String,
-    pub(crate) table_names: Vec<String>,
-    pub(crate) column_names: Vec<String>,
-    pub(crate) column_types: Vec<ColumnType>,
-    pub(crate) column_to_table: Vec<usize>,
-    pub(crate) table_to_columns: HashMap<String, Vec<usize>>,
-    pub(crate) foreign_keys: Vec<(usize, usize)>,
-    pub(crate) primary_keys: Vec<usize>,
+/// Tables and columns of a database. Names are kept as the database spells them, which is the
+/// spelling the parser resolves any casing or quoting of a name to, and the one [`spelling`]
+/// prints back.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct SqlSchema {
+    pub db_id: String,
+    pub table_names: Vec<String>,
+    pub column_names: Vec<String>,
+    pub column_types: Vec<ColumnType>,
+    pub column_to_table: Vec<usize>,
+    pub table_to_columns: HashMap<String, Vec<usize>>,
+    pub foreign_keys: Vec<(usize, usize)>,
+    pub primary_keys: Vec<usize>,
 }
 
 impl SqlSchema {
-    #[cfg(test)]
-    #[allow(clippy::too_many_arguments)]
-    pub(crate) fn new(
-        db_id: String,
-        table_names: Vec<String>,
-        column_names: Vec<String>,
-        column_types: Vec<ColumnType>,
-        column_to_table: Vec<usize>,
-        table_to_columns: HashMap<String, Vec<usize>>,
-        foreign_keys: Vec<(usize, usize)>,
-        primary_keys: Vec<usize>,
-    ) -> Self {
-        Self {
-            db_id,
-            table_names,
-            column_names,
-            column_types,
-            column_to_table,
-            table_to_columns,
-            foreign_keys,
-            primary_keys,
+    /// Checks that the per-column arrays line up and that every index points at an
+    /// existing table or column, since the parser indexes them without bounds checks.
+    pub fn validate(&self) -> Result<(), String> {
+        let db_id = &self.db_id;
+        let columns = self.column_names.len();
+        if self.column_types.len() != columns || self.column_to_table.len() != columns {
+            return Err(format!(
+                "{db_id}: {columns} column names, {} column types and {} column tables",
+                self.column_types.len(),
+                self.column_to_table.len()
+            ));
+        }
+        if let Some(t) = self
+            .column_to_table
+            .iter()
+            .find(|t| **t >= self.table_names.len())
+        {
+            return Err(format!("{db_id}: column references unknown table {t}"));
         }
+        let key_columns = self
+            .foreign_keys
+            .iter()
+            .flat_map(|(a, b)| [a, b])
+            .chain(&self.primary_keys)
+            .chain(self.table_to_columns.values().flatten());
+        for c in key_columns {
+            if *c >= columns {
+                return Err(format!("{db_id}: key references unknown column {c}"));
+            }
+        }
+        Ok(())
     }
 }
 
-#[derive(Clone, Debug, PartialEq, PartialOrd, Ord, Eq)]
-pub(crate) enum KeyType {
+/// Words of QPL, and of the SQL it compiles to, that a name is quoted to be told apart from.
+const RESERVED: [&str; 25] = [
+    "AND",
+    "AS",
+    "ASC",
+    "BETWEEN",
+    "BY",
+    "DESC",
+    "DISTINCT",
+    "EXCEPT",
+    "FROM",
+    "GROUP",
+    "HAVING",
+    "IN",
+    "INTERSECT",
+    "IS",
+    "JOIN",
+    "LIKE",
+    "LIMIT",
+    "NOT",
+    "NULL",
+    "ON",
+    "OR",
+    "ORDER",
+    "SELECT",
+    "UNION",
+    "WHERE",
+];
+
+/// Whether `name` is a word of letters, digits and underscores, not starting with a digit.
+/// Such names are read bare wherever QPL expects a name, even the ones printed quoted.
+pub fn is_word(name: &str) -> bool {
+    name.chars().next().is_some_and(|c| !c.is_ascii_digit())
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Whether `name` is written as is in QPL: a word that is not a reserved word.
+pub fn is_bare_identifier(name: &str) -> bool {
+    is_word(name) && !RESERVED.iter().any(|word| word.eq_ignore_ascii_case(name))
+}
+
+/// `name` as written in QPL: as is when it is a bare identifier, and otherwise between double
+/// quotes with its double quotes doubled, as in `"Home Town"` or `"Order"`.
+pub fn spelling(name: &str) -> Cow<'_, str> {
+    if is_bare_identifier(name) {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(format!("\"{}\"", name.replace('"', "\"\"")))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, PartialOrd, Ord, Eq, Deserialize, Serialize)]
+pub enum KeyType {
     PrimaryKey { table: String },
     ForeignKey { table: String },
 }
 
-#[derive(Clone, Debug)]
-pub(crate) enum Column {
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum Column {
     Dummy,
     Plain {
         name: String,
@@ -71,21 +139,21 @@ pub(crate) enum Column {
 }
 
 impl Column {
-    pub(crate) fn name(&self) -> &str {
+    pub fn name(&self) -> &str {
         match self {
             Column::Dummy => "1 AS One",
             Column::Plain { name, .. } | Column::Aliased { name, .. } => name,
         }
     }
 
-    pub(crate) fn typ(&self) -> &ColumnType {
+    pub fn typ(&self) -> &ColumnType {
         match self {
             Column::Dummy => &ColumnType::Number,
             Column::Plain { typ, .. } | Column::Aliased { typ, .. } => typ,
         }
     }
 
-    pub(crate) fn keys(&self) -> &[KeyType] {
+    pub fn keys(&self) -> &[KeyType] {
         match self {
             Column::Dummy => &[],
             Column::Plain { keys, .. } | Column::Aliased { keys, .. } => keys,
@@ -93,15 +161,15 @@ impl Column {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[allow(dead_code)]
-pub(crate) enum Table {
+pub enum Table {
     Named { name: String, columns: Vec<Column> },
     Indexed { idx: usize, columns: Vec<Column> },
 }
 
 impl Table {
-    pub(crate) fn columns(&self) -> &[Column] {
+    pub fn columns(&self) -> &[Column] {
         match self {
             Table::Named { columns, .. } => columns,
             Table::Indexed { columns, .. } => columns,
@@ -109,17 +177,146 @@ impl Table {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
-pub(crate) enum Comparable {
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Comparable {
     Number(f64),
     Str(String),
     Boolean(bool),
     Null,
     Column(String),
+    /// Column of one of the inputs, as in `#2.Name`.
+    IndexedColumn(usize, String),
+    /// Arithmetic over other comparables, as in `Highest - Lowest`.
+    Expression(Box<Expression>),
+}
+
+impl From<Expression> for Comparable {
+    fn from(expression: Expression) -> Self {
+        Comparable::Expression(Box::new(expression))
+    }
+}
+
+impl fmt::Display for Comparable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Comparable::Number(n) => write!(f, "{n}"),
+            Comparable::Str(s) => write!(f, "'{s}'"),
+            Comparable::Boolean(b) => write!(f, "{}", u8::from(*b)),
+            Comparable::Null => write!(f, "NULL"),
+            Comparable::Column(column) => write!(f, "{}", spelling(column)),
+            Comparable::IndexedColumn(idx, column) => write!(f, "#{idx}.{}", spelling(column)),
+            Comparable::Expression(expression) => write!(f, "{expression}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ArithmeticOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl ArithmeticOp {
+    /// `*` and `/` bind tighter than `+` and `-`.
+    fn precedence(self) -> u8 {
+        match self {
+            ArithmeticOp::Add | ArithmeticOp::Subtract => 1,
+            ArithmeticOp::Multiply | ArithmeticOp::Divide => 2,
+        }
+    }
+}
+
+impl fmt::Display for ArithmeticOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArithmeticOp::Add => write!(f, "+"),
+            ArithmeticOp::Subtract => write!(f, "-"),
+            ArithmeticOp::Multiply => write!(f, "*"),
+            ArithmeticOp::Divide => write!(f, "/"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum NumericFunction {
+    Abs,
+    Round,
+}
+
+impl NumericFunction {
+    pub(crate) fn values() -> [NumericFunction; 2] {
+        [NumericFunction::Abs, NumericFunction::Round]
+    }
+}
+
+impl fmt::Display for NumericFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NumericFunction::Abs => write!(f, "ABS"),
+            NumericFunction::Round => write!(f, "ROUND"),
+        }
+    }
+}
+
+/// Arithmetic over numbers, always of type `Number`. The operands are comparables, so that
+/// expressions nest through `Comparable::Expression`.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Expression {
+    /// `- Age`.
+    Negate(Comparable),
+    /// `Highest - Lowest`.
+    Binary(ArithmeticOp, Comparable, Comparable),
+    /// `ABS(Highest - Lowest)`.
+    Function(NumericFunction, Comparable),
 }
 
-#[derive(Debug, PartialEq)]
-pub(crate) enum Comparison {
+/// Expressions are printed with only the parentheses the parser needs to rebuild the same
+/// tree, given that `*` and `/` bind tighter than `+` and `-` and all of them fold left.
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::Negate(operand) => {
+                f.write_str("- ")?;
+                write_arithmetic_operand(f, operand, precedence(operand) < u8::MAX)
+            }
+            Expression::Binary(op, lhs, rhs) => {
+                write_arithmetic_operand(f, lhs, precedence(lhs) < op.precedence())?;
+                write!(f, " {op} ")?;
+                write_arithmetic_operand(f, rhs, precedence(rhs) <= op.precedence())
+            }
+            Expression::Function(function, argument) => write!(f, "{function}({argument})"),
+        }
+    }
+}
+
+/// How tightly `comparable` holds together as an operand, with anything but a binary
+/// operation holding together the most.
+fn precedence(comparable: &Comparable) -> u8 {
+    match comparable {
+        Comparable::Expression(expression) => match **expression {
+            Expression::Binary(op, ..) => op.precedence(),
+            _ => u8::MAX,
+        },
+        _ => u8::MAX,
+    }
+}
+
+fn write_arithmetic_operand(
+    f: &mut fmt::Formatter<'_>,
+    operand: &Comparable,
+    grouped: bool,
+) -> fmt::Result {
+    if grouped {
+        write!(f, "( {operand} )")
+    } else {
+        write!(f, "{operand}")
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub enum Comparison {
     Equal(Comparable, Comparable),
     NotEqual(Comparable, Comparable),
     GreaterThan(Comparable, Comparable),
@@ -130,12 +327,17 @@ pub(crate) enum Comparison {
     IsNot(Comparable, Comparable),
     Like(Comparable, Comparable),
     NotLike(Comparable, Comparable),
+    /// `Name IN ( 'a' , 'b' )`, against at least one value.
+    In(Comparable, Vec<Comparable>),
+    NotIn(Comparable, Vec<Comparable>),
+    /// `Age BETWEEN 20 AND 30`, both bounds included.
+    Between(Comparable, Comparable, Comparable),
 }
 
 impl Comparison {
-    pub(crate) fn from_string(op: &str, lhs: Comparable, rhs: Comparable) -> Comparison {
+    pub(crate) fn from_string(op: &str, lhs: Comparable, rhs: Comparable) -> Option<Comparison> {
         use Comparison::*;
-        match op {
+        let comparison = match op {
             "=" => Equal(lhs, rhs),
             "<>" => NotEqual(lhs, rhs),
             ">" => GreaterThan(lhs, rhs),
@@ -146,13 +348,56 @@ impl Comparison {
             "IS NOT" => IsNot(lhs, rhs),
             "LIKE" => Like(lhs, rhs),
             "NOT LIKE" => NotLike(lhs, rhs),
-            _ => panic!("Operation \"{}\" is not supported.", op),
-        }
+            _ => return None,
+        };
+        Some(comparison)
+    }
+
+    /// Operator and operands of a comparison between two values, with the operator as written
+    /// in QPL. `IN`, `NOT IN` and `BETWEEN` have no such parts.
+    pub fn parts(&self) -> Option<(&'static str, &Comparable, &Comparable)> {
+        use Comparison::*;
+        let parts = match self {
+            Equal(lhs, rhs) => ("=", lhs, rhs),
+            NotEqual(lhs, rhs) => ("<>", lhs, rhs),
+            GreaterThan(lhs, rhs) => (">", lhs, rhs),
+            GreaterThanOrEqual(lhs, rhs) => (">=", lhs, rhs),
+            LessThan(lhs, rhs) => ("<", lhs, rhs),
+            LessThanOrEqual(lhs, rhs) => ("<=", lhs, rhs),
+            Is(lhs, rhs) => ("IS", lhs, rhs),
+            IsNot(lhs, rhs) => ("IS NOT", lhs, rhs),
+            Like(lhs, rhs) => ("LIKE", lhs, rhs),
+            NotLike(lhs, rhs) => ("NOT LIKE", lhs, rhs),
+            In(..) | NotIn(..) | Between(..) => return None,
+        };
+        Some(parts)
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (op, lhs, values) = match self {
+            Comparison::In(lhs, values) => ("IN", lhs, values),
+            Comparison::NotIn(lhs, values) => ("NOT IN", lhs, values),
+            Comparison::Between(lhs, low, high) => {
+                return write!(f, "{lhs} BETWEEN {low} AND {high}")
+            }
+            comparison => {
+                let (op, lhs, rhs) = comparison.parts().expect("comparison of two values");
+                return write!(f, "{lhs} {op} {rhs}");
+            }
+        };
+        let values = values
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(" , ");
+        write!(f, "{lhs} {op} ( {values} )")
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub(crate) enum Predicate {
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub enum Predicate {
     Single {
         comparison: Comparison,
     },
@@ -166,65 +411,344 @@ pub(crate) enum Predicate {
     },
 }
 
-#[derive(Debug, PartialEq)]
-pub(crate) enum ExceptOperator {
+/// Predicates are printed with only the parentheses the parser needs to rebuild the same tree,
+/// given that `AND` binds tighter than `OR` and both fold left.
+impl fmt::Display for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Predicate::Single { comparison } => write!(f, "{comparison}"),
+            Predicate::And { lhs, rhs } => {
+                write_operand(f, lhs, matches!(**lhs, Predicate::Or { .. }))?;
+                f.write_str(" AND ")?;
+                write_operand(f, rhs, !matches!(**rhs, Predicate::Single { .. }))
+            }
+            Predicate::Or { lhs, rhs } => {
+                write!(f, "{lhs} OR ")?;
+                write_operand(f, rhs, matches!(**rhs, Predicate::Or { .. }))
+            }
+        }
+    }
+}
+
+fn write_operand(f: &mut fmt::Formatter<'_>, predicate: &Predicate, grouped: bool) -> fmt::Result {
+    if grouped {
+        write!(f, "( {predicate} )")
+    } else {
+        write!(f, "{predicate}")
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub enum ExceptOperator {
     Predicate(Predicate),
-    ExceptColum(String),
+    /// Column of one of the inputs, as in `ExceptColumns [ #2.Name ]`.
+    ExceptColum(usize, String),
 }
 
-#[derive(Debug, PartialEq)]
-pub(crate) enum Operation {
+/// One entry of an `Output [ ... ]` list.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum OutputColumn {
+    /// The `1 AS One` placeholder of lines that only check that rows exist.
+    One,
+    /// `countstar AS Count_Star`.
+    CountStar,
+    /// Column of the single input, as in `Name`, or in scans `Name AS Singer_Name`.
+    Column { name: String, alias: Option<String> },
+    /// Column of one of the inputs, as in `#2.Name`.
+    Indexed { input: usize, name: String },
+    /// Aggregated column of the input, as in `MAX(DISTINCT Age) AS Max_Dist_Age`.
+    Aggregate {
+        agg: Agg,
+        is_distinct: bool,
+        column: String,
+        alias: String,
+    },
+    /// Arithmetic over the columns of a scanned table, as in `Capacity / 2 AS Half_Capacity`.
+    Expression {
+        expression: Expression,
+        alias: String,
+    },
+}
+
+impl OutputColumn {
+    /// Name later lines refer to the column by.
+    pub fn name(&self) -> &str {
+        match self {
+            OutputColumn::One => "1 AS One",
+            OutputColumn::CountStar => "Count_Star",
+            OutputColumn::Column {
+                alias: Some(alias), ..
+            } => alias,
+            OutputColumn::Column { name, .. } | OutputColumn::Indexed { name, .. } => name,
+            OutputColumn::Aggregate { alias, .. } | OutputColumn::Expression { alias, .. } => alias,
+        }
+    }
+}
+
+impl fmt::Display for OutputColumn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputColumn::One => write!(f, "1 AS One"),
+            OutputColumn::CountStar => write!(f, "countstar AS Count_Star"),
+            OutputColumn::Column { name, alias: None } => write!(f, "{}", spelling(name)),
+            OutputColumn::Column {
+                name,
+                alias: Some(alias),
+            } => write!(f, "{} AS {}", spelling(name), spelling(alias)),
+            OutputColumn::Indexed { input, name } => write!(f, "#{input}.{}", spelling(name)),
+            OutputColumn::Aggregate {
+                agg,
+                is_distinct,
+                column,
+                alias,
+            } => {
+                let agg = agg.to_string().to_uppercase();
+                let distinct = if *is_distinct { "DISTINCT " } else { "" };
+                let (column, alias) = (spelling(column), spelling(alias));
+                write!(f, "{agg}({distinct}{column}) AS {alias}")
+            }
+            OutputColumn::Expression { expression, alias } => {
+                write!(f, "{expression} AS {}", spelling(alias))
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub enum Operation {
     Aggregate {
         input: usize,
         group_by: Vec<String>,
+        outputs: Vec<OutputColumn>,
     },
     Except {
         inputs: Vec<usize>,
         operator: ExceptOperator,
         is_distinct: bool,
+        outputs: Vec<OutputColumn>,
     },
     Filter {
         input: usize,
         predicate: Option<Predicate>,
         is_distinct: bool,
+        outputs: Vec<OutputColumn>,
     },
     Intersect {
         inputs: Vec<usize>,
         predicate: Option<Predicate>,
         is_distinct: bool,
+        outputs: Vec<OutputColumn>,
     },
     Join {
         inputs: Vec<usize>,
         predicate: Option<Predicate>,
         is_distinct: bool,
+        outputs: Vec<OutputColumn>,
     },
     Scan {
         table: String,
         predicate: Option<Predicate>,
         is_distinct: bool,
+        outputs: Vec<OutputColumn>,
     },
     Top {
         input: usize,
         rows: usize,
+        outputs: Vec<OutputColumn>,
     },
     Sort {
         input: usize,
         order_by: Vec<String>,
         is_distinct: bool,
+        outputs: Vec<OutputColumn>,
     },
     TopSort {
         input: usize,
         rows: usize,
         order_by: Vec<String>,
         with_ties: bool,
+        outputs: Vec<OutputColumn>,
     },
     Union {
         inputs: Vec<usize>,
+        outputs: Vec<OutputColumn>,
     },
 }
 
-#[derive(Clone)]
-pub(crate) enum Agg {
+impl Operation {
+    /// Name of the operator as written in QPL.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Aggregate { .. } => "Aggregate",
+            Operation::Except { .. } => "Except",
+            Operation::Filter { .. } => "Filter",
+            Operation::Intersect { .. } => "Intersect",
+            Operation::Join { .. } => "Join",
+            Operation::Scan { .. } => "Scan",
+            Operation::Top { .. } => "Top",
+            Operation::Sort { .. } => "Sort",
+            Operation::TopSort { .. } => "TopSort",
+            Operation::Union { .. } => "Union",
+        }
+    }
+
+    /// Entries of the `Output [ ... ]` list.
+    pub fn outputs(&self) -> &[OutputColumn] {
+        match self {
+            Operation::Aggregate { outputs, .. }
+            | Operation::Except { outputs, .. }
+            | Operation::Filter { outputs, .. }
+            | Operation::Intersect { outputs, .. }
+            | Operation::Join { outputs, .. }
+            | Operation::Scan { outputs, .. }
+            | Operation::Top { outputs, .. }
+            | Operation::Sort { outputs, .. }
+            | Operation::TopSort { outputs, .. }
+            | Operation::Union { outputs, .. } => outputs,
+        }
+    }
+}
+
+/// Prints the operation in the canonical format the parser accepts, from the operator name to
+/// the closing bracket of the outputs.
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ", self.name())?;
+        match self {
+            Operation::Scan {
+                table,
+                predicate,
+                is_distinct,
+                ..
+            } => {
+                write!(f, "Table [ {} ] ", spelling(table))?;
+                write_predicate(f, predicate.as_ref())?;
+                write_distinct(f, *is_distinct)?;
+            }
+            Operation::Aggregate {
+                input, group_by, ..
+            } => {
+                write!(f, "[ #{input} ] ")?;
+                if !group_by.is_empty() {
+                    write!(f, "GroupBy [ {} ] ", names(group_by))?;
+                }
+            }
+            Operation::Filter {
+                input,
+                predicate,
+                is_distinct,
+                ..
+            } => {
+                write!(f, "[ #{input} ] ")?;
+                write_predicate(f, predicate.as_ref())?;
+                write_distinct(f, *is_distinct)?;
+            }
+            Operation::Top { input, rows, .. } => write!(f, "[ #{input} ] Rows [ {rows} ] ")?,
+            Operation::Sort {
+                input,
+                order_by,
+                is_distinct,
+                ..
+            } => {
+                write!(f, "[ #{input} ] OrderBy [ {} ] ", order_by_list(order_by))?;
+                write_distinct(f, *is_distinct)?;
+            }
+            Operation::TopSort {
+                input,
+                rows,
+                order_by,
+                with_ties,
+                ..
+            } => {
+                write!(f, "[ #{input} ] Rows [ {rows} ] ")?;
+                write!(f, "OrderBy [ {} ] ", order_by_list(order_by))?;
+                if *with_ties {
+                    write!(f, "WithTies [ true ] ")?;
+                }
+            }
+            Operation::Join {
+                inputs,
+                predicate,
+                is_distinct,
+                ..
+            }
+            | Operation::Intersect {
+                inputs,
+                predicate,
+                is_distinct,
+                ..
+            } => {
+                write_inputs(f, inputs)?;
+                write_predicate(f, predicate.as_ref())?;
+                write_distinct(f, *is_distinct)?;
+            }
+            Operation::Except {
+                inputs,
+                operator,
+                is_distinct,
+                ..
+            } => {
+                write_inputs(f, inputs)?;
+                match operator {
+                    ExceptOperator::Predicate(predicate) => write_predicate(f, Some(predicate))?,
+                    ExceptOperator::ExceptColum(input, column) => {
+                        write!(f, "ExceptColumns [ #{input}.{} ] ", spelling(column))?
+                    }
+                }
+                write_distinct(f, *is_distinct)?;
+            }
+            Operation::Union { inputs, .. } => write_inputs(f, inputs)?,
+        }
+        let outputs = self
+            .outputs()
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        write!(f, "Output [ {} ]", outputs.join(" , "))
+    }
+}
+
+fn names(names: &[String]) -> String {
+    let names = names.iter().map(|name| spelling(name)).collect::<Vec<_>>();
+    names.join(" , ")
+}
+
+/// Columns along with their direction, as in `Age DESC`.
+fn order_by_list(order_by: &[String]) -> String {
+    let order_by = order_by
+        .iter()
+        .map(|by| match by.rsplit_once(' ') {
+            Some((column, dir)) => format!("{} {dir}", spelling(column)),
+            None => spelling(by).into_owned(),
+        })
+        .collect::<Vec<_>>();
+    order_by.join(" , ")
+}
+
+fn write_inputs(f: &mut fmt::Formatter<'_>, inputs: &[usize]) -> fmt::Result {
+    let inputs = inputs
+        .iter()
+        .map(|input| format!("#{input}"))
+        .collect::<Vec<_>>();
+    write!(f, "[ {} ] ", inputs.join(" , "))
+}
+
+fn write_predicate(f: &mut fmt::Formatter<'_>, predicate: Option<&Predicate>) -> fmt::Result {
+    match predicate {
+        Some(predicate) => write!(f, "Predicate [ {predicate} ] "),
+        None => Ok(()),
+    }
+}
+
+fn write_distinct(f: &mut fmt::Formatter<'_>, is_distinct: bool) -> fmt::Result {
+    if is_distinct {
+        write!(f, "Distinct [ true ] ")?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Agg {
     Sum,
     Min,
     Max,
@@ -240,8 +764,8 @@ impl Agg {
     }
 }
 
-impl std::fmt::Display for Agg {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl fmt::Display for Agg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Agg::Sum => write!(f, "Sum"),
             Agg::Min => write!(f, "Min"),
@@ -252,13 +776,43 @@ impl std::fmt::Display for Agg {
     }
 }
 
-#[derive(Debug, PartialEq)]
-pub(crate) struct Line {
-    pub(crate) idx: usize,
-    pub(crate) operation: Operation,
+#[derive(Debug, PartialEq, Deserialize, Serialize)]
+pub struct Line {
+    pub idx: usize,
+    pub operation: Operation,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} = {}", self.idx, self.operation)
+    }
+}
+
+/// Lines of a QPL, in order. Its `Display` is the canonical format the parser accepts.
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct Qpl(pub Vec<Line>);
+
+impl Deref for Qpl {
+    type Target = [Line];
+
+    fn deref(&self) -> &[Line] {
+        &self.0
+    }
+}
+
+impl From<Vec<Line>> for Qpl {
+    fn from(lines: Vec<Line>) -> Self {
+        Self(lines)
+    }
 }
 
-pub(crate) type Qpl = Vec<Line>;
+impl fmt::Display for Qpl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lines = self.0.iter().map(ToString::to_string).collect::<Vec<_>>();
+        write!(f, "{}", lines.join(" ; "))
+    }
+}
 
 #[derive(Clone, Debug, Default)]
 pub(crate) struct QplState {
@@ -0,0 +1,316 @@
+//! Runs a parsed QPL against a SQLite database, for evaluating predicted QPL by its results.
+//!
+//! Lines run one at a time: each is compiled with [`crate::sql`] and materialised into a
+//! temporary table named after the line, as in `"#2"`, which later lines read. The temporary
+//! tables are dropped once the QPL has run.
+
+use crate::{
+    domain::{Column, Table},
+    sql::{line_table, select, SqlError},
+    ParsedQpl,
+};
+use rusqlite::{types::ValueRef, Connection, OpenFlags, Statement};
+use serde::Serialize;
+use std::{fmt, path::Path};
+
+#[derive(Debug)]
+pub enum ExecuteError {
+    Sql(SqlError),
+    Sqlite(rusqlite::Error),
+    /// A line returned other columns than the ones the parser inferred for it.
+    Columns {
+        line: usize,
+        inferred: Vec<String>,
+        returned: Vec<String>,
+    },
+}
+
+impl fmt::Display for ExecuteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecuteError::Sql(e) => write!(f, "{e}"),
+            ExecuteError::Sqlite(e) => write!(f, "SQLite error: {e}"),
+            ExecuteError::Columns {
+                line,
+                inferred,
+                returned,
+            } => write!(
+                f,
+                "Line #{line} returned [{}] instead of the inferred [{}]",
+                returned.join(", "),
+                inferred.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ExecuteError {}
+
+impl From<SqlError> for ExecuteError {
+    fn from(e: SqlError) -> Self {
+        ExecuteError::Sql(e)
+    }
+}
+
+impl From<rusqlite::Error> for ExecuteError {
+    fn from(e: rusqlite::Error) -> Self {
+        ExecuteError::Sqlite(e)
+    }
+}
+
+/// Value of a cell, with SQLite's storage classes.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+/// Rows of the last line of a QPL, in the order it returned them.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ResultSet {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// Connection to the SQLite database QPL runs against.
+pub struct Executor {
+    connection: Connection,
+}
+
+impl Executor {
+    /// Opens the database at `path` read-only. Temporary tables can still be created.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ExecuteError> {
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        Ok(Self { connection })
+    }
+
+    /// Runs the lines of `parsed` in order and returns the rows of the last one.
+    ///
+    /// Lines with an output table, which only the modes with guards infer, have to return
+    /// the columns of that table.
+    pub fn execute(&self, parsed: &ParsedQpl) -> Result<ResultSet, ExecuteError> {
+        let Some((last, lines)) = parsed.qpl.split_last() else {
+            return Err(SqlError::EmptyQpl.into());
+        };
+        // Rolling back on drop also drops the temporary tables.
+        let transaction = self.connection.unchecked_transaction()?;
+        for line in lines {
+            let sql = select(line)?;
+            check_columns(
+                line.idx,
+                &transaction.prepare(&sql)?,
+                parsed.outputs.get(&line.idx),
+            )?;
+            let table = line_table(line.idx);
+            transaction.execute(&format!("CREATE TEMP TABLE {table} AS {sql}"), [])?;
+        }
+        let mut statement = transaction.prepare(&select(last)?)?;
+        let columns = check_columns(last.idx, &statement, parsed.outputs.get(&last.idx))?;
+        let rows = statement
+            .query_map([], |row| {
+                (0..columns.len())
+                    .map(|i| row.get_ref(i).map(value))
+                    .collect()
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(ResultSet { columns, rows })
+    }
+}
+
+fn value(value: ValueRef<'_>) -> Value {
+    match value {
+        ValueRef::Null => Value::Null,
+        ValueRef::Integer(i) => Value::Integer(i),
+        ValueRef::Real(r) => Value::Real(r),
+        ValueRef::Text(text) => Value::Text(String::from_utf8_lossy(text).into_owned()),
+        ValueRef::Blob(blob) => Value::Blob(blob.to_vec()),
+    }
+}
+
+/// Names of the columns `statement` returns, checked against the table inferred for the line.
+fn check_columns(
+    line: usize,
+    statement: &Statement<'_>,
+    table: Option<&Table>,
+) -> Result<Vec<String>, ExecuteError> {
+    let returned = statement
+        .column_names()
+        .into_iter()
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    let Some(table) = table else {
+        return Ok(returned);
+    };
+    let inferred = table
+        .columns()
+        .iter()
+        .map(|column| match column {
+            Column::Dummy => "One".to_owned(),
+            column => column.name().to_owned(),
+        })
+        .collect::<Vec<_>>();
+    let is_same = inferred.len() == returned.len()
+        && inferred
+            .iter()
+            .zip(&returned)
+            .all(|(inferred, returned)| inferred.eq_ignore_ascii_case(returned));
+    if !is_same {
+        return Err(ExecuteError::Columns {
+            line,
+            inferred,
+            returned,
+        });
+    }
+    Ok(returned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, schemas::concert_singer, sql::from_sql, Mode};
+    use tempfile::TempDir;
+
+    const DATABASE: &str = "
+        CREATE TABLE stadium (Stadium_ID int, Location text, Name text, Capacity int,
+            Highest int, Lowest int, Average int);
+        CREATE TABLE singer (Singer_ID int, Name text, Country text, Song_Name text,
+            Song_release_year text, Age int, Is_male bool);
+        CREATE TABLE concert (concert_ID int, concert_Name text, Theme text, Stadium_ID text,
+            Year text);
+        CREATE TABLE singer_in_concert (concert_ID int, Singer_ID text);
+        INSERT INTO stadium VALUES (1, 'Raith Rovers', 'Stark''s Park', 10104, 4812, 1294, 2106),
+            (2, 'Ayr United', 'Somerset Park', 11998, 2363, 1057, 1477);
+        INSERT INTO singer VALUES (1, 'Joe Sharp', 'Netherlands', 'You', '1992', 52, 'F'),
+            (2, 'Timbaland', 'United States', 'Dangerous', '2008', 32, 'T'),
+            (3, 'Justin Brown', 'France', 'Hey Oh', '2013', 29, 'T');
+        INSERT INTO concert VALUES (1, 'Auditions', 'Free choice', '1', '2014'),
+            (2, 'Super bootcamp', 'Free choice 2', '1', '2014'),
+            (3, 'Home Visits', 'Bleeding Love', '2', '2015');
+        INSERT INTO singer_in_concert VALUES (1, '2'), (1, '3'), (2, '3'), (3, '1');
+    ";
+
+    fn executor() -> (TempDir, Executor) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("concert_singer.sqlite");
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(DATABASE)
+            .unwrap();
+        let executor = Executor::open(&path).unwrap();
+        (dir, executor)
+    }
+
+    fn parsed(qpl: &str) -> ParsedQpl {
+        parse(qpl, &concert_singer(), Mode::ParseWithGuards).unwrap()
+    }
+
+    fn text(s: &str) -> Value {
+        Value::Text(s.to_owned())
+    }
+
+    #[test]
+    fn test_lines_run_in_order() {
+        let (_dir, executor) = executor();
+        let result = executor
+            .execute(&parsed(
+                "#1 = Scan Table [ singer ] Predicate [ Age > 30 ] Output [ Country ] ; \
+                 #2 = Aggregate [ #1 ] Output [ countstar AS Count_Star ]",
+            ))
+            .unwrap();
+        assert_eq!(result.columns, vec!["Count_Star"]);
+        assert_eq!(result.rows, vec![vec![Value::Integer(2)]]);
+
+        let result = executor
+            .execute(&parsed(
+                "#1 = Scan Table [ singer_in_concert ] Output [ concert_ID , Singer_ID ] ; \
+                 #2 = Scan Table [ singer ] Output [ Name , Singer_ID ] ; \
+                 #3 = Join [ #1 , #2 ] Predicate [ #1.Singer_ID = #2.Singer_ID ] \
+                 Output [ #1.concert_ID , #2.Name ] ; \
+                 #4 = Sort [ #3 ] OrderBy [ Name ASC ] Output [ concert_ID , Name ]",
+            ))
+            .unwrap();
+        assert_eq!(result.columns, vec!["concert_ID", "Name"]);
+        assert_eq!(
+            result.rows,
+            vec![
+                vec![Value::Integer(3), text("Joe Sharp")],
+                vec![Value::Integer(1), text("Justin Brown")],
+                vec![Value::Integer(2), text("Justin Brown")],
+                vec![Value::Integer(1), text("Timbaland")],
+            ]
+        );
+
+        let result = executor
+            .execute(&parsed(
+                "#1 = Scan Table [ stadium ] Predicate [ Highest - Lowest > 2000 ] \
+                 Output [ Name , Capacity / 2 AS Half ] ; \
+                 #2 = Filter [ #1 ] Predicate [ Half > 5000 ] Output [ Half ]",
+            ))
+            .unwrap();
+        assert_eq!(result.columns, vec!["Half"]);
+        assert_eq!(result.rows, vec![vec![Value::Integer(5052)]]);
+
+        // The temporary tables of a run are gone before the next one.
+        assert!(executor
+            .execute(&parsed("#1 = Scan Table [ stadium ] Output [ 1 AS One ]"))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_converted_sql_returns_the_same_rows() {
+        let (dir, executor) = executor();
+        let connection = Connection::open(dir.path().join("concert_singer.sqlite")).unwrap();
+        for sql in [
+            "SELECT T2.name, count(*) FROM concert AS T1 JOIN stadium AS T2 \
+             ON T1.stadium_id = T2.stadium_id GROUP BY T1.stadium_id ORDER BY T2.name",
+            "SELECT name FROM stadium WHERE stadium_id NOT IN (SELECT stadium_id FROM concert \
+             WHERE year = '2015')",
+            "SELECT country FROM singer WHERE age > 40 UNION SELECT country FROM singer \
+             WHERE age < 30 ORDER BY country",
+            "SELECT name FROM singer WHERE age BETWEEN 30 AND 45 \
+             AND country NOT IN ('France', 'Spain') ORDER BY name",
+        ] {
+            let qpl = from_sql(sql, &concert_singer()).unwrap();
+            let result = executor.execute(&parsed(&qpl.to_string())).unwrap();
+            let mut statement = connection.prepare(sql).unwrap();
+            let width = statement.column_count();
+            let expected = statement
+                .query_map([], |row| {
+                    (0..width)
+                        .map(|i| row.get_ref(i).map(value))
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            assert_eq!(result.rows, expected, "{sql}");
+        }
+    }
+
+    #[test]
+    fn test_columns_have_to_match_inferred_tables() {
+        let (_dir, executor) = executor();
+        let mut parsed = parsed(
+            "#1 = Scan Table [ singer ] Output [ Name , Age ] ; \
+             #2 = Filter [ #1 ] Predicate [ Age > 30 ] Output [ Name ]",
+        );
+        let filter_output = parsed.outputs[&2].clone();
+        parsed.outputs.insert(1, filter_output);
+        match executor.execute(&parsed) {
+            Err(ExecuteError::Columns {
+                line,
+                inferred,
+                returned,
+            }) => {
+                assert_eq!(line, 1);
+                assert_eq!(inferred, vec!["Name"]);
+                assert_eq!(returned, vec!["Name", "Age"]);
+            }
+            result => panic!("expected a column mismatch, got {result:?}"),
+        }
+    }
+}
@@ -0,0 +1,29 @@
+//! Parser and checker for QPL, the query plan language of the Spider text-to-SQL benchmark.
+//!
+//! A QPL is checked against a [`SqlSchema`](domain::SqlSchema) with [`parse`], or with
+//! [`parse_prefixed`] when it starts with the `<db_id> |` prefix that picks its schema.
+//! [`parse_partial`] checks a QPL that is still being generated, resuming from where the
+//! previous call left off. [`LineParser`] checks a QPL one line at a time, as typed.
+//! [`suggest`] lists what can follow a QPL cut short at the cursor. [`sql::to_sql`] compiles a
+//! parsed QPL into the SQLite query it stands for, and [`sql::from_sql`] converts a SQLite
+//! query into QPL. [`execute::Executor`] runs a parsed QPL against a SQLite database.
+
+pub mod cache;
+pub mod decoding;
+pub mod domain;
+pub mod execute;
+mod parser;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(test)]
+mod schemas;
+pub mod spider;
+pub mod sql;
+
+pub use parser::{
+    api::{parse, parse_partial, parse_prefixed, ParsedQpl, PartialParse, PartialStatus},
+    error::ParseError,
+    lines::LineParser,
+    suggest::{suggest, LiteralKind, Suggestion, Suggestions},
+    Mode,
+};
@@ -1,49 +1,210 @@
 use api::{
-    BatchFeedResult, BatchParseRequest, FeedResult, ServerState, ValidationRequest,
-    ValidationResult,
+    AnalysisResult, ApiError, ApiJson, BatchFeedResult, BatchMaskRequest, BatchMaskResult,
+    BatchParseRequest, BeamFeedResult, Config, ForkResult, LineSchema, RegisteredTokenizer,
+    ServerState, SessionFeedRequest, SessionRequest, SessionResponse, ValidationError,
+    ValidationRequest, ValidationResult, DEFAULT_TOKENIZER,
 };
 use axum::{
-    response::IntoResponse,
-    routing::{get, post},
+    extract::Path,
+    http::StatusCode,
+    routing::{delete, get, post},
     Extension, Json, Router,
 };
-use domain::{QplEnvironment, QplState, SqlSchema};
-use parser::{api::prefixed_qpl, shared::Stream};
 use rayon::prelude::*;
-use std::{
-    str::FromStr,
-    sync::{Arc, Mutex},
+#[cfg(test)]
+use rusty_picard::domain;
+use rusty_picard::{
+    cache::PartialParseCache,
+    decoding::{self, detokenize, feed_decoded},
+    domain::SqlSchema,
+    parse_partial, parse_prefixed,
+    spider::SpiderSchema,
+    suggest, Mode, ParsedQpl, Suggestions,
 };
+use session::Session;
+use std::{collections::HashMap, fs, path::PathBuf, str::FromStr, sync::Arc};
+use store::{check_name, StateDir};
 use tokenizers::Tokenizer;
 use tokio::sync::RwLock;
 use tower_http::trace::TraceLayer;
-use tracing::debug;
-use winnow::{error::ErrMode, stream::StreamIsPartial, Parser, Partial};
+use tracing::{debug, error};
+use vocab::VocabTrie;
 
 mod api;
-pub(crate) mod domain;
-mod parser;
+#[cfg(test)]
 mod schemas;
+mod session;
+mod store;
+mod vocab;
+
+#[derive(clap::Parser, Debug)]
+struct Args {
+    /// Directory where registered schemas and the tokenizer are persisted and reloaded from.
+    #[arg(long)]
+    state_dir: Option<PathBuf>,
+    /// Spider-style tables.json whose schemas are registered at startup, can be repeated.
+    #[arg(long)]
+    tables: Vec<PathBuf>,
+    /// Tokenizer JSON registered at startup, as `NAME=PATH` or just `PATH` for the default
+    /// tokenizer, can be repeated.
+    #[arg(long)]
+    tokenizer: Vec<String>,
+}
 
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
 
-    let app = Router::new()
+    let args = <Args as clap::Parser>::parse();
+    let state = match load_state(args) {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+    };
+
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:8081").await.unwrap();
+    axum::serve(listener, app(state)).await.unwrap();
+}
+
+type SharedState = Arc<RwLock<ServerState>>;
+
+fn app(state: ServerState) -> Router {
+    Router::new()
         .route("/health", get(health))
         .route("/debug", get(log_state))
         .route("/schema", post(register_schema))
-        .route("/tokenizer", post(register_tokenizer))
+        .route("/schema/:db_id", get(get_schema).delete(delete_schema))
+        .route("/schemas", get(list_schemas))
+        .route("/schemas/spider", post(import_spider_schemas))
+        .route("/tokenizer", post(register_default_tokenizer))
+        .route("/tokenizer/:name", post(register_tokenizer))
+        .route("/tokenizers", get(list_tokenizers))
+        .route("/config", get(get_config).post(set_config))
         .route("/validate", post(validate_qpl))
+        .route("/analyze", post(analyze_qpl))
+        .route("/suggest", post(suggest_qpl))
         .route("/parse", post(parse_qpl))
+        .route("/mask", post(mask_qpl))
+        .route("/session", post(open_session))
+        .route("/session/:session_id", delete(close_session))
+        .route("/session/:session_id/feed", post(feed_session))
+        .route(
+            "/session/:session_id/beam/:beam_id",
+            delete(drop_session_beam),
+        )
+        .route(
+            "/session/:session_id/beam/:beam_id/fork",
+            post(fork_session_beam),
+        )
         .layer(TraceLayer::new_for_http())
-        .layer(Extension(SharedState::default()));
+        .layer(Extension(Arc::new(RwLock::new(state))))
+}
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:8081").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+fn load_state(args: Args) -> Result<ServerState, String> {
+    let mut state = ServerState::default();
+    if let Some(dir) = args.state_dir {
+        let state_dir = StateDir::open(&dir)
+            .map_err(|e| format!("Cannot open state directory {}: {e}", dir.display()))?;
+        let schemas = state_dir
+            .load_schemas()
+            .map_err(|e| format!("Cannot load schemas from {}: {e}", dir.display()))?;
+        for schema in schemas {
+            schema.validate()?;
+            state.schemas.insert(schema.db_id.clone(), schema);
+        }
+        let tokenizers = state_dir
+            .load_tokenizers(DEFAULT_TOKENIZER)
+            .map_err(|e| format!("Cannot load tokenizers from {}: {e}", dir.display()))?;
+        for (name, tokenizer_repr) in tokenizers {
+            set_tokenizer(&mut state, &name, &tokenizer_repr).map_err(|e| e.to_string())?;
+        }
+        debug!(
+            "Loaded {} schemas from {}",
+            state.schemas.len(),
+            dir.display()
+        );
+        state.state_dir = Some(state_dir);
+    }
+
+    for path in args.tables {
+        let contents = fs::read(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+        let tables: Vec<SpiderSchema> =
+            serde_json::from_slice(&contents).map_err(|e| format!("{}: {e}", path.display()))?;
+        let schemas = tables
+            .into_iter()
+            .map(SqlSchema::try_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        add_schemas(&mut state, schemas).map_err(|e| e.to_string())?;
+    }
+    for arg in args.tokenizer {
+        let (name, path) = arg.split_once('=').unwrap_or((DEFAULT_TOKENIZER, &arg));
+        let tokenizer_repr = fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        set_tokenizer(&mut state, name, &tokenizer_repr).map_err(|e| e.to_string())?;
+    }
+    Ok(state)
 }
 
-type SharedState = Arc<RwLock<ServerState>>;
+fn add_schema(state: &mut ServerState, schema: SqlSchema) -> Result<(), ApiError> {
+    add_schemas(state, vec![schema])
+}
+
+/// Registers `schemas`, persisting them first when there is a state directory. Either all of
+/// them are registered or none: every schema is checked before anything is written, and the
+/// files already written are restored when a later write fails.
+fn add_schemas(state: &mut ServerState, schemas: Vec<SqlSchema>) -> Result<(), ApiError> {
+    for schema in &schemas {
+        schema.validate().map_err(ApiError::InvalidSchema)?;
+        if state.state_dir.is_some() {
+            check_name(&schema.db_id).map_err(ApiError::InvalidName)?;
+        }
+    }
+    if let Some(state_dir) = &state.state_dir {
+        for (i, schema) in schemas.iter().enumerate() {
+            if let Err(e) = state_dir.save_schema(schema) {
+                for saved in schemas[..i].iter().rev() {
+                    let restored = match state.schemas.get(&saved.db_id) {
+                        Some(previous) => state_dir.save_schema(previous),
+                        None => state_dir.remove_schema(&saved.db_id),
+                    };
+                    if let Err(e) = restored {
+                        error!("Cannot restore schema {}: {e}", saved.db_id);
+                    }
+                }
+                return Err(ApiError::Persistence(format!("schema {}", schema.db_id), e));
+            }
+        }
+    }
+    for schema in schemas {
+        debug!("Added schema {}", schema.db_id);
+        state.schemas.insert(schema.db_id.clone(), schema);
+    }
+    state.clear_partial_parses();
+    Ok(())
+}
+
+/// Registers a tokenizer, persisting it first when there is a state directory, so that a
+/// tokenizer that cannot be persisted is not registered either.
+fn set_tokenizer(
+    state: &mut ServerState,
+    name: &str,
+    tokenizer_repr: &str,
+) -> Result<(), ApiError> {
+    let tokenizer = Tokenizer::from_str(tokenizer_repr)
+        .map_err(|e| ApiError::InvalidTokenizer(e.to_string()))?;
+    if let Some(state_dir) = &state.state_dir {
+        check_name(name).map_err(ApiError::InvalidName)?;
+        state_dir
+            .save_tokenizer(name, tokenizer_repr)
+            .map_err(|e| ApiError::Persistence(format!("tokenizer {name}"), e))?;
+    }
+    debug!("Setting tokenizer {}", name);
+    state
+        .tokenizers
+        .insert(name.to_owned(), RegisteredTokenizer::new(tokenizer));
+    Ok(())
+}
 
 async fn health() {}
 
@@ -51,125 +212,782 @@ async fn log_state(Extension(state): Extension<SharedState>) {
     debug!(?state)
 }
 
-async fn register_schema(Extension(state): Extension<SharedState>, Json(schema): Json<SqlSchema>) {
+async fn register_schema(
+    Extension(state): Extension<SharedState>,
+    ApiJson(schema): ApiJson<SqlSchema>,
+) -> Result<(), ApiError> {
+    let mut state = state.write().await;
+    add_schema(&mut state, schema)
+}
+
+async fn get_schema(
+    Extension(state): Extension<SharedState>,
+    Path(db_id): Path<String>,
+) -> Result<Json<SqlSchema>, ApiError> {
+    let state = state.read().await;
+    state.schema(&db_id).cloned().map(Json)
+}
+
+async fn delete_schema(
+    Extension(state): Extension<SharedState>,
+    Path(db_id): Path<String>,
+) -> Result<StatusCode, ApiError> {
     let mut state = state.write().await;
-    debug!("Added schema {}", schema.db_id);
-    state.schemas.insert(schema.db_id.clone(), schema);
+    state.schema(&db_id)?;
+    if let Some(state_dir) = &state.state_dir {
+        state_dir
+            .remove_schema(&db_id)
+            .map_err(|e| ApiError::Persistence(format!("removal of schema {db_id}"), e))?;
+    }
+    state.schemas.remove(&db_id);
+    debug!("Removed schema {}", db_id);
+    state.clear_partial_parses();
+    Ok(StatusCode::NO_CONTENT)
 }
 
-async fn register_tokenizer(Extension(state): Extension<SharedState>, tokenizer_repr: String) {
-    let state = state.write().await;
-    let tokenizer = Tokenizer::from_str(&tokenizer_repr).unwrap();
-    debug!("Setting tokenizer");
-    let mut mutex = state.tokenizer.lock().unwrap();
-    *mutex = Some(tokenizer);
+async fn list_schemas(Extension(state): Extension<SharedState>) -> Json<Vec<String>> {
+    let state = state.read().await;
+    let mut db_ids = state.schemas.keys().cloned().collect::<Vec<_>>();
+    db_ids.sort_unstable();
+    Json(db_ids)
+}
+
+async fn import_spider_schemas(
+    Extension(state): Extension<SharedState>,
+    ApiJson(tables): ApiJson<Vec<SpiderSchema>>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    let schemas = tables
+        .into_iter()
+        .map(SqlSchema::try_from)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(ApiError::InvalidSchema)?;
+    let db_ids = schemas
+        .iter()
+        .map(|schema| schema.db_id.clone())
+        .collect::<Vec<_>>();
+    let mut state = state.write().await;
+    add_schemas(&mut state, schemas)?;
+    debug!("Imported {} schemas", db_ids.len());
+    Ok(Json(db_ids))
+}
+
+async fn register_default_tokenizer(
+    state: Extension<SharedState>,
+    tokenizer_repr: String,
+) -> Result<(), ApiError> {
+    register_tokenizer(state, Path(DEFAULT_TOKENIZER.to_owned()), tokenizer_repr).await
+}
+
+async fn register_tokenizer(
+    Extension(state): Extension<SharedState>,
+    Path(name): Path<String>,
+    tokenizer_repr: String,
+) -> Result<(), ApiError> {
+    let mut state = state.write().await;
+    set_tokenizer(&mut state, &name, &tokenizer_repr)
+}
+
+async fn list_tokenizers(Extension(state): Extension<SharedState>) -> Json<Vec<String>> {
+    let state = state.read().await;
+    let mut names = state.tokenizers.keys().cloned().collect::<Vec<_>>();
+    names.sort_unstable();
+    Json(names)
+}
+
+async fn get_config(Extension(state): Extension<SharedState>) -> Json<Config> {
+    let state = state.read().await;
+    Json(Config { mode: state.mode })
+}
+
+async fn set_config(Extension(state): Extension<SharedState>, ApiJson(config): ApiJson<Config>) {
+    let mut state = state.write().await;
+    debug!("Setting mode {:?}", config.mode);
+    state.mode = config.mode;
 }
 
 async fn validate_qpl(
     Extension(state): Extension<SharedState>,
-    Json(req): Json<ValidationRequest>,
-) -> impl IntoResponse {
+    ApiJson(req): ApiJson<ValidationRequest>,
+) -> Json<ValidationResult> {
     let state = state.read().await;
-    let schemas = &state.schemas;
-    let with_type_checking = state.with_type_checking;
-    let mut input = Stream {
-        input: Partial::new(&req.qpl),
-        state: QplEnvironment {
-            state: QplState::default(),
-            schema: None,
-        },
-    };
-    let _ = input.complete();
-    let result = prefixed_qpl::<()>(schemas, with_type_checking).parse_next(&mut input);
-    let response = match result {
+    let mode = req.mode.unwrap_or(state.mode);
+    let response = match analyze(&req.qpl, mode, &state) {
         Ok(_) => ValidationResult::Valid,
-        Err(_) => ValidationResult::Invalid {
-            reason: "Failed to parse".to_owned(),
-        },
+        Err(error) => ValidationResult::Invalid(error),
     };
     Json(response)
 }
 
-async fn parse_qpl(
+async fn analyze_qpl(
     Extension(state): Extension<SharedState>,
-    Json(req): Json<BatchParseRequest>,
-) -> Result<Json<Vec<BatchFeedResult>>, String> {
+    ApiJson(req): ApiJson<ValidationRequest>,
+) -> Json<AnalysisResult> {
     let state = state.read().await;
-    {
-        let tokenizer = state.tokenizer.lock().unwrap();
-        if tokenizer.is_none() {
-            return Err("Tokenizer not registered".into());
+    let mode = req.mode.unwrap_or(state.mode);
+    let response = match analyze(&req.qpl, mode, &state) {
+        Ok(ParsedQpl { qpl, outputs }) => {
+            let lines = qpl
+                .iter()
+                .filter_map(|line| {
+                    outputs.get(&line.idx).map(|table| LineSchema {
+                        idx: line.idx,
+                        output: table.clone(),
+                    })
+                })
+                .collect();
+            AnalysisResult::Valid { qpl, lines }
         }
-    }
-    let result = batch_feed(&req.input_ids, &req.top_tokens, &state);
+        Err(error) => AnalysisResult::Invalid(error),
+    };
+    Json(response)
+}
+
+/// Lists what can follow the QPL, which ends at the cursor.
+async fn suggest_qpl(
+    Extension(state): Extension<SharedState>,
+    ApiJson(req): ApiJson<ValidationRequest>,
+) -> Json<Suggestions> {
+    let state = state.read().await;
+    let mode = req.mode.unwrap_or(state.mode);
+    Json(suggest(&req.qpl, &state.schemas, mode))
+}
+
+fn analyze(qpl: &str, mode: Mode, state: &ServerState) -> Result<ParsedQpl, ValidationError> {
+    parse_prefixed(qpl, &state.schemas, mode).map_err(ValidationError::from)
+}
+
+async fn parse_qpl(
+    Extension(state): Extension<SharedState>,
+    ApiJson(req): ApiJson<BatchParseRequest>,
+) -> Result<Json<Vec<BatchFeedResult>>, ApiError> {
+    let state = state.read().await;
+    let tokenizer = state.tokenizer(req.tokenizer.as_deref())?;
+    let mode = req.mode.unwrap_or(state.mode);
+    let result = batch_feed(&req.input_ids, &req.top_tokens, tokenizer, mode, &state)?;
     Ok(Json(result))
 }
 
 fn batch_feed(
     input_ids: &[Vec<u32>],
     top_tokens: &[Vec<u32>],
+    tokenizer: &RegisteredTokenizer,
+    mode: Mode,
     state: &ServerState,
-) -> Vec<BatchFeedResult> {
-    let triplets = top_tokens
-        .iter()
-        .zip(input_ids.iter())
-        .zip(0..)
-        .flat_map(|((tokens, inputs), batch_id)| tokens.iter().map(move |t| (batch_id, inputs, *t)))
-        .collect::<Vec<_>>();
+) -> Result<Vec<BatchFeedResult>, ApiError> {
+    let fed = decoding::batch_feed(
+        input_ids,
+        top_tokens,
+        &tokenizer.tokenizer,
+        &tokenizer.partial_parses,
+        &state.schemas,
+        mode,
+    )?;
+    let result = fed
+        .into_iter()
+        .map(|fed| BatchFeedResult {
+            batch_id: fed.batch_id,
+            top_token: fed.top_token,
+            feed_result: fed.status.into(),
+        })
+        .collect();
+    Ok(result)
+}
 
-    let mut result = Vec::with_capacity(triplets.len());
-    triplets
-        .into_par_iter()
-        .map(|(batch_id, input_ids, top_token)| {
-            let feed_result = feed(input_ids, top_token, state);
-            BatchFeedResult {
+async fn mask_qpl(
+    Extension(state): Extension<SharedState>,
+    ApiJson(req): ApiJson<BatchMaskRequest>,
+) -> Result<Json<Vec<BatchMaskResult>>, ApiError> {
+    let state = state.read().await;
+    let tokenizer = state.tokenizer(req.tokenizer.as_deref())?;
+    let mode = req.mode.unwrap_or(state.mode);
+    let result = batch_mask(&req.input_ids, tokenizer, mode, &state)?;
+    Ok(Json(result))
+}
+
+fn batch_mask(
+    input_ids: &[Vec<u32>],
+    tokenizer: &RegisteredTokenizer,
+    mode: Mode,
+    state: &ServerState,
+) -> Result<Vec<BatchMaskResult>, ApiError> {
+    input_ids
+        .par_iter()
+        .zip(0..input_ids.len() as u32)
+        .map(|(input_ids, batch_id)| {
+            let decoded = detokenize(input_ids, &tokenizer.tokenizer)?;
+            let valid_tokens = mask(
+                input_ids,
+                &decoded,
+                &tokenizer.vocab_trie,
+                &tokenizer.partial_parses,
+                mode,
+                &state.schemas,
+            );
+            Ok(BatchMaskResult {
                 batch_id,
-                top_token,
-                feed_result,
-            }
+                valid_tokens,
+            })
         })
-        .collect_into_vec(&mut result);
+        .collect()
+}
 
-    result
+fn mask(
+    input_ids: &[u32],
+    decoded: &str,
+    vocab_trie: &VocabTrie,
+    partial_parses: &PartialParseCache,
+    mode: Mode,
+    schemas: &HashMap<String, SqlSchema>,
+) -> Vec<u32> {
+    // Every extension shares the beam's own prefix, so resume all of them from its last
+    // line boundary.
+    let cached = partial_parses.get(mode, input_ids);
+    let (_, partial_parse) = parse_partial(decoded, schemas, mode, false, cached.as_deref());
+    let resume_from = partial_parse.map(Arc::new).or(cached);
+    if let Some(resume_from) = &resume_from {
+        partial_parses.put(mode, input_ids.to_vec(), resume_from.clone());
+    }
+
+    vocab_trie.valid_tokens(decoded, |text| {
+        feed_decoded(text, schemas, mode, resume_from.as_deref())
+            .0
+            .into()
+    })
 }
 
-fn feed(input_ids: &[u32], token: u32, state: &ServerState) -> FeedResult {
-    let ServerState {
-        tokenizer,
-        schemas,
-        with_type_checking,
-    } = state;
+async fn open_session(
+    Extension(state): Extension<SharedState>,
+    ApiJson(req): ApiJson<SessionRequest>,
+) -> Result<Json<SessionResponse>, ApiError> {
+    let state = state.read().await;
+    let tokenizer = req.tokenizer.as_deref().unwrap_or(DEFAULT_TOKENIZER);
+    state.tokenizer(Some(tokenizer))?;
+    if let Some(db_id) = &req.db_id {
+        state.schema(db_id)?;
+    }
+    let mode = req.mode.unwrap_or(state.mode);
+    let session = Session::new(tokenizer.to_owned(), mode, req.db_id.as_deref());
+    let session_id = state.sessions.open(session)?;
+    debug!("Opened session {}", session_id);
+    Ok(Json(SessionResponse {
+        session_id,
+        beam_id: 0,
+    }))
+}
 
-    let mut tokenizer_input = Vec::from(input_ids);
-    tokenizer_input.push(token);
+async fn close_session(
+    Extension(state): Extension<SharedState>,
+    Path(session_id): Path<u64>,
+) -> Result<StatusCode, ApiError> {
+    let state = state.read().await;
+    state.sessions.close(session_id)?;
+    debug!("Closed session {}", session_id);
+    Ok(StatusCode::NO_CONTENT)
+}
 
-    let decoded = detokenize(&tokenizer_input, tokenizer);
+async fn feed_session(
+    Extension(state): Extension<SharedState>,
+    Path(session_id): Path<u64>,
+    ApiJson(req): ApiJson<SessionFeedRequest>,
+) -> Result<Json<Vec<BeamFeedResult>>, ApiError> {
+    let state = state.read().await;
+    let session = state.sessions.get(session_id)?;
+    let mut session = session::lock(&session);
+    let tokenizer = state.tokenizer(Some(&session.tokenizer))?;
+    let mode = session.mode;
+    let result = session.feed(&req.tokens, &tokenizer.tokenizer, |text, resume_from| {
+        let (status, partial_parse) = feed_decoded(text, &state.schemas, mode, resume_from);
+        (status.into(), partial_parse)
+    })?;
+    Ok(Json(result))
+}
 
-    let mut parser_input = Stream {
-        input: Partial::new(decoded.strip_suffix("</s>").unwrap_or(&decoded)),
-        state: QplEnvironment {
-            state: QplState::default(),
-            schema: None,
-        },
+async fn fork_session_beam(
+    Extension(state): Extension<SharedState>,
+    Path((session_id, beam_id)): Path<(u64, u32)>,
+) -> Result<Json<ForkResult>, ApiError> {
+    let state = state.read().await;
+    let session = state.sessions.get(session_id)?;
+    let beam_id = session::lock(&session).fork(beam_id)?;
+    Ok(Json(ForkResult { beam_id }))
+}
+
+async fn drop_session_beam(
+    Extension(state): Extension<SharedState>,
+    Path((session_id, beam_id)): Path<(u64, u32)>,
+) -> Result<StatusCode, ApiError> {
+    let state = state.read().await;
+    let session = state.sessions.get(session_id)?;
+    session::lock(&session).drop_beam(beam_id)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{api::FeedResult, domain::Column, schemas::concert_singer};
+    use axum::{
+        body::{self, Body},
+        http::{header, Request},
     };
+    use serde_json::{json, Value};
+    use tower::ServiceExt;
 
-    if decoded.ends_with("</s>") {
-        let _ = parser_input.complete();
+    fn state() -> ServerState {
+        let mut state = ServerState::default();
+        let schema = concert_singer();
+        state.schemas.insert(schema.db_id.clone(), schema);
+        state
     }
 
-    match prefixed_qpl::<()>(schemas, *with_type_checking).parse_next(&mut parser_input) {
-        Ok(_) => FeedResult::Complete,
-        Err(ErrMode::Incomplete(_)) => FeedResult::Partial,
-        Err(_) => FeedResult::Failure,
+    /// A word-level tokenizer without decoder, so decoding joins tokens with spaces.
+    fn word_level_tokenizer(words: &[&str]) -> String {
+        let vocab = words
+            .iter()
+            .zip(0..)
+            .map(|(word, id)| (word.to_string(), serde_json::Value::from(id)))
+            .collect::<serde_json::Map<_, _>>();
+        serde_json::json!({
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": null,
+            "post_processor": null,
+            "decoder": null,
+            "model": { "type": "WordLevel", "vocab": vocab, "unk_token": words[0] }
+        })
+        .to_string()
     }
-}
 
-fn detokenize(input_ids: &[u32], tokenizer: &Arc<Mutex<Option<Tokenizer>>>) -> String {
-    tokenizer
-        .lock()
-        .unwrap()
-        .as_ref()
-        .unwrap()
-        .decode(input_ids, false)
-        .unwrap()
+    #[test]
+    fn test_feed_detokenizes_with_requested_tokenizer() {
+        let words = ["concert_singer", "|", "#1", "=", "Scan", "Table", "["];
+        let reversed = words.iter().rev().copied().collect::<Vec<_>>();
+        let mut state = state();
+        set_tokenizer(&mut state, DEFAULT_TOKENIZER, &word_level_tokenizer(&words)).unwrap();
+        set_tokenizer(&mut state, "reversed", &word_level_tokenizer(&reversed)).unwrap();
+
+        let input_ids = vec![vec![0, 1, 2, 3, 4]];
+        let top_tokens = vec![vec![5]];
+        let feed_with = |name: Option<&str>| {
+            let tokenizer = state.tokenizer(name).unwrap();
+            batch_feed(
+                &input_ids,
+                &top_tokens,
+                tokenizer,
+                Mode::ParseWithGuards,
+                &state,
+            )
+            .unwrap()
+        };
+        assert!(matches!(
+            feed_with(None)[0].feed_result,
+            FeedResult::Partial
+        ));
+        assert!(matches!(
+            feed_with(Some("reversed"))[0].feed_result,
+            FeedResult::Failure
+        ));
+        assert!(state.tokenizer(Some("codellama")).is_err());
+    }
+
+    #[test]
+    fn test_analyze_returns_ast_and_line_schemas() {
+        let ParsedQpl { qpl, outputs } = analyze(
+            "concert_singer | #1 = Scan Table [ singer ] Output [ Singer_ID , Age ] ; #2 = Aggregate [ #1 ] Output [ MAX(Age) AS Max_Age ]",
+            Mode::ParseWithGuards,
+            &state(),
+        )
+        .unwrap();
+        assert_eq!(qpl.len(), 2);
+        let columns = outputs[&1].columns();
+        assert_eq!(columns.len(), 2);
+        assert_eq!(columns[0].name(), "Singer_ID");
+        assert!(!columns[0].keys().is_empty());
+        assert!(matches!(
+            &outputs[&2].columns()[0],
+            Column::Aliased { name, .. } if name == "Max_Age"
+        ));
+    }
+
+    #[test]
+    fn test_analyze_reports_invalid_qpl() {
+        let qpl = "concert_singer | #1 = Scan Table [ singer ] Output [ Foo ]";
+        let error = analyze(qpl, Mode::ParseWithGuards, &state()).unwrap_err();
+        assert_eq!(error.line, Some(1));
+        assert_eq!(error.offset, qpl.find("Foo").unwrap());
+        assert_eq!(error.operator.as_deref(), Some("Scan"));
+    }
+
+    #[test]
+    fn test_analyze_checks_according_to_mode() {
+        let qpl =
+            "concert_singer | #1 = Scan Table [ singer ] Predicate [ Age = 'old' ] Output [ Foo ]";
+        let state = state();
+        assert!(analyze(qpl, Mode::Lex, &state).is_ok());
+        assert!(analyze(qpl, Mode::ParseWithoutGuards, &state).is_ok());
+        assert!(analyze(qpl, Mode::ParseWithGuards, &state).is_err());
+
+        let qpl = qpl.replace("Foo", "Name");
+        assert!(analyze(&qpl, Mode::ParseWithGuards, &state).is_ok());
+        let error = analyze(&qpl, Mode::ParseWithGuardsAndTypeChecks, &state).unwrap_err();
+        assert_eq!(error.offset, qpl.find("'old'").unwrap());
+
+        assert!(analyze("concert_singer | #1 = Scan Table [ ! ]", Mode::Lex, &state).is_err());
+    }
+
+    #[test]
+    fn test_mask_returns_tokens_accepted_by_parser() {
+        let vocab_trie = VocabTrie::from_surfaces(
+            [
+                " singer", " stadium", " sing", " Scan", "er ]", " ]", "</s>",
+            ]
+            .into_iter()
+            .zip(0..)
+            .map(|(surface, id)| (id, surface.to_owned())),
+        );
+        let state = state();
+        let decoded = "concert_singer | #1 = Scan Table [";
+        assert_eq!(
+            mask(
+                &[],
+                decoded,
+                &vocab_trie,
+                &PartialParseCache::default(),
+                Mode::ParseWithGuards,
+                &state.schemas
+            ),
+            vec![0, 1, 2]
+        );
+        let decoded = "concert_singer | #1 = Scan Table [ sing";
+        assert_eq!(
+            mask(
+                &[],
+                decoded,
+                &vocab_trie,
+                &PartialParseCache::default(),
+                Mode::ParseWithGuards,
+                &state.schemas
+            ),
+            vec![4]
+        );
+    }
+
+    /// Sends `body` as JSON and returns the status along with the JSON body, if any.
+    async fn send(state: ServerState, method: &str, uri: &str, body: &str) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_owned()))
+            .unwrap();
+        let response = app(state).oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (
+            status,
+            serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        )
+    }
+
+    fn assert_error(response: (StatusCode, Value), status: StatusCode) {
+        assert_eq!(response.0, status, "{}", response.1);
+        assert!(response.1["error"].is_string(), "{}", response.1);
+    }
+
+    const JSON_ROUTES: [&str; 10] = [
+        "/schema",
+        "/schemas/spider",
+        "/config",
+        "/validate",
+        "/analyze",
+        "/suggest",
+        "/parse",
+        "/mask",
+        "/session",
+        "/session/0/feed",
+    ];
+
+    #[tokio::test]
+    async fn test_routes_reject_malformed_json() {
+        for uri in JSON_ROUTES {
+            let response = send(state(), "POST", uri, "not json").await;
+            assert_error(response, StatusCode::BAD_REQUEST);
+            let response = send(state(), "POST", uri, r#"{"foo": 1}"#).await;
+            assert_error(response, StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_routes_reject_unknown_mode() {
+        let response = send(state(), "POST", "/config", r#"{"mode": "fast"}"#).await;
+        assert_error(response, StatusCode::UNPROCESSABLE_ENTITY);
+        for uri in ["/validate", "/analyze"] {
+            let body = r#"{"qpl": "concert_singer |", "mode": "fast"}"#;
+            let response = send(state(), "POST", uri, body).await;
+            assert_error(response, StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        for uri in ["/parse", "/mask"] {
+            let body = r#"{"input_ids": [], "top_tokens": [], "mode": "fast"}"#;
+            let response = send(state(), "POST", uri, body).await;
+            assert_error(response, StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_routes_reject_unknown_tokenizer() {
+        for uri in ["/parse", "/mask"] {
+            let body = r#"{"input_ids": [[0]], "top_tokens": [[0]]}"#;
+            let response = send(state(), "POST", uri, body).await;
+            assert_error(response, StatusCode::NOT_FOUND);
+
+            let body = r#"{"input_ids": [[0]], "top_tokens": [[0]], "tokenizer": "t5"}"#;
+            let mut with_default = state();
+            set_tokenizer(
+                &mut with_default,
+                DEFAULT_TOKENIZER,
+                &word_level_tokenizer(&["a"]),
+            )
+            .unwrap();
+            let response = send(with_default, "POST", uri, body).await;
+            assert_error(response, StatusCode::NOT_FOUND);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_routes_reject_unknown_schema() {
+        let response = send(state(), "GET", "/schema/world_1", "").await;
+        assert_error(response, StatusCode::NOT_FOUND);
+        let response = send(state(), "DELETE", "/schema/world_1", "").await;
+        assert_error(response, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_routes_reject_invalid_tokenizer() {
+        for uri in ["/tokenizer", "/tokenizer/t5"] {
+            let response = send(state(), "POST", uri, "{}").await;
+            assert_error(response, StatusCode::UNPROCESSABLE_ENTITY);
+        }
+    }
+
+    #[test]
+    fn test_schema_batch_failing_midway_changes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = state();
+        let state_dir = StateDir::open(dir.path()).unwrap();
+        state_dir.save_schema(&concert_singer()).unwrap();
+        state.state_dir = Some(state_dir);
+        let schema = |db_id: &str| {
+            let mut schema = concert_singer();
+            schema.db_id = db_id.to_owned();
+            schema.table_names[0] = "arena".to_owned();
+            schema
+        };
+        let unchanged = |state: &ServerState| {
+            let mut db_ids = state.schemas.keys().collect::<Vec<_>>();
+            db_ids.sort_unstable();
+            assert_eq!(db_ids, ["concert_singer"]);
+            assert_eq!(state.schemas["concert_singer"], concert_singer());
+            let saved = state.state_dir.as_ref().unwrap().load_schemas().unwrap();
+            assert_eq!(saved, vec![concert_singer()]);
+        };
+
+        let batch = vec![schema("concert_singer"), schema("a b"), schema("c")];
+        let result = add_schemas(&mut state, batch);
+        assert!(matches!(result, Err(ApiError::InvalidName(_))));
+        unchanged(&state);
+
+        // A directory in the way of its temporary file makes the write of `b` fail.
+        fs::create_dir(dir.path().join("schemas/b.json.tmp")).unwrap();
+        let batch = vec![schema("a"), schema("concert_singer"), schema("b")];
+        let result = add_schemas(&mut state, batch);
+        assert!(matches!(result, Err(ApiError::Persistence(..))));
+        unchanged(&state);
+    }
+
+    #[tokio::test]
+    async fn test_unpersistable_tokenizer_is_not_registered() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut state = state();
+        state.state_dir = Some(StateDir::open(dir.path()).unwrap());
+        let tokenizer = word_level_tokenizer(&["a"]);
+        assert!(matches!(
+            set_tokenizer(&mut state, "t 5", &tokenizer),
+            Err(ApiError::InvalidName(_))
+        ));
+        assert!(state.tokenizers.is_empty());
+
+        let response = send(state, "POST", "/tokenizer/t%205", &tokenizer).await;
+        assert_error(response, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_parse_rejects_mismatched_batch() {
+        let mut state = state();
+        set_tokenizer(&mut state, DEFAULT_TOKENIZER, &word_level_tokenizer(&["a"])).unwrap();
+        let body = r#"{"input_ids": [[0], [0]], "top_tokens": [[0]]}"#;
+        let response = send(state, "POST", "/parse", body).await;
+        assert_error(response, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_routes_reject_inconsistent_schemas() {
+        let mut schema = serde_json::to_value(concert_singer()).unwrap();
+        schema["column_to_table"][0] = json!(9);
+        let response = send(state(), "POST", "/schema", &schema.to_string()).await;
+        assert_error(response, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let mut schema = serde_json::to_value(concert_singer()).unwrap();
+        schema["column_types"] = json!(["text"]);
+        let response = send(state(), "POST", "/schema", &schema.to_string()).await;
+        assert_error(response, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let mut schema = serde_json::to_value(concert_singer()).unwrap();
+        schema["foreign_keys"] = json!([[0, 100]]);
+        let response = send(state(), "POST", "/schema", &schema.to_string()).await;
+        assert_error(response, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let tables = json!([{
+            "db_id": "db",
+            "table_names_original": ["a"],
+            "column_names_original": [[-1, "*"], [0, "x"]],
+            "column_types": ["text", "number"],
+            "primary_keys": [7]
+        }]);
+        let response = send(state(), "POST", "/schemas/spider", &tables.to_string()).await;
+        assert_error(response, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_routes_report_invalid_qpl_without_panicking() {
+        let qpls = [
+            "#1 = Scan Table [ singer ] Output [ Name ]",
+            "concert_singer | #1 = Scan Table [ singer ] Output [ Name ] ; #2 = Filter [ #3 ] Predicate [ Name = 'a' ] Output [ Name ]",
+            "concert_singer | #1 = Scan Table [ singer ] Output [ Name ] ; #2 = Sort [ #5 ] OrderBy [ Name ASC ] Output [ Name ]",
+            "concert_singer | #1 = Scan Table [ singer ] Output [ Name ] ; #2 = Join [ #1 , #7 ] Predicate [ #1.Name = #7.Name ] Output [ #1.Name ]",
+            "world_1 | #1 = Scan Table [ city ] Output [ Name ]",
+        ];
+        let modes = [
+            "lex",
+            "parse_without_guards",
+            "parse_with_guards",
+            "parse_with_guards_and_type_checks",
+        ];
+        for qpl in qpls {
+            for mode in modes {
+                for uri in ["/validate", "/analyze"] {
+                    let body = json!({ "qpl": qpl, "mode": mode }).to_string();
+                    let (status, body) = send(state(), "POST", uri, &body).await;
+                    assert_eq!(status, StatusCode::OK, "{uri} {mode} {qpl}");
+                    // Only the guarded modes check line references and database ids.
+                    if mode.starts_with("parse_with_guards") {
+                        assert_eq!(body["tag"], "invalid", "{uri} {mode} {qpl}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_suggest_completes_last_word() {
+        let qpl = "concert_singer | #1 = Scan Table [ sing";
+        let body = json!({ "qpl": qpl }).to_string();
+        let (status, body) = send(state(), "POST", "/suggest", &body).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            json!({
+                "start": qpl.len() - "sing".len(),
+                "suggestions": [
+                    { "kind": "table", "value": "singer" },
+                    { "kind": "table", "value": "singer_in_concert" },
+                ],
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_session_feeds_forks_and_drops_beams() {
+        let words = ["#1", "=", "Scan", "Table", "[", "singer", "]", "Foo"];
+        let mut state = state();
+        set_tokenizer(&mut state, DEFAULT_TOKENIZER, &word_level_tokenizer(&words)).unwrap();
+        let app = app(state);
+        let send = |method: &str, uri: &str, body: Value| {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            let app = app.clone();
+            async move {
+                let response = app.oneshot(request).await.unwrap();
+                let status = response.status();
+                let bytes = body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (
+                    status,
+                    serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+                )
+            }
+        };
+
+        let response = send("POST", "/session", json!({ "db_id": "world_1" })).await;
+        assert_error(response, StatusCode::NOT_FOUND);
+        let (status, body) = send("POST", "/session", json!({ "db_id": "concert_singer" })).await;
+        assert_eq!(status, StatusCode::OK);
+        let session = format!("/session/{}", body["session_id"]);
+        let feed = format!("{session}/feed");
+
+        for token in [0, 1, 2, 3] {
+            let tokens = json!({ "tokens": [{ "beam_id": 0, "token": token }] });
+            let (_, body) = send("POST", &feed, tokens).await;
+            assert_eq!(
+                body,
+                json!([{ "beam_id": 0, "feed_result": { "tag": "partial" } }])
+            );
+        }
+        let (_, body) = send("POST", &format!("{session}/beam/0/fork"), json!(null)).await;
+        assert_eq!(body, json!({ "beam_id": 1 }));
+
+        let tokens = json!({ "tokens": [
+            { "beam_id": 0, "token": 4 },
+            { "beam_id": 1, "token": 7 },
+        ] });
+        let (_, body) = send("POST", &feed, tokens).await;
+        assert_eq!(
+            body,
+            json!([
+                { "beam_id": 0, "feed_result": { "tag": "partial" } },
+                { "beam_id": 1, "feed_result": { "tag": "failure" } },
+            ])
+        );
+        let tokens = json!({ "tokens": [{ "beam_id": 0, "token": 5 }] });
+        let (_, body) = send("POST", &feed, tokens).await;
+        assert_eq!(body[0]["feed_result"]["tag"], "partial");
+
+        let tokens = json!({ "tokens": [
+            { "beam_id": 0, "token": 6 },
+            { "beam_id": 0, "token": 6 },
+        ] });
+        assert_error(
+            send("POST", &feed, tokens).await,
+            StatusCode::UNPROCESSABLE_ENTITY,
+        );
+
+        let (status, _) = send("DELETE", &format!("{session}/beam/1"), json!(null)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let tokens = json!({ "tokens": [{ "beam_id": 1, "token": 6 }] });
+        assert_error(send("POST", &feed, tokens).await, StatusCode::NOT_FOUND);
+
+        let (status, _) = send("DELETE", &session, json!(null)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let tokens = json!({ "tokens": [{ "beam_id": 0, "token": 6 }] });
+        assert_error(send("POST", &feed, tokens).await, StatusCode::NOT_FOUND);
+    }
 }
@@ -1,63 +1,143 @@
 mod aggregate;
 pub(crate) mod api;
+pub(crate) mod error;
 mod except;
 mod filter;
 mod intersect;
 mod join;
+mod lexer;
+pub(crate) mod lines;
+mod predicate;
 mod scan;
 pub(crate) mod shared;
 mod sort;
+pub(crate) mod suggest;
 mod top;
 mod top_sort;
 mod union;
 mod utils;
 
-use self::shared::Stream;
+use self::{
+    error::{expecting, Expected, QplContext, QplParserError},
+    shared::Stream,
+};
 use crate::domain::{Line, Qpl};
 use aggregate::aggregate;
 use except::except;
 use filter::filter;
 use intersect::intersect;
 use join::join;
+use lexer::lexed_qpl;
 use scan::scan;
+use serde::{Deserialize, Serialize};
 use sort::sort;
 use top::top;
 use top_sort::top_sort;
 use union::union;
 use winnow::{
-    combinator::{alt, eof, separated},
-    error::ParserError,
+    combinator::{alt, cut_err, eof, opt, peek},
     Parser,
 };
 
-pub(crate) fn qpl<'i, E: ParserError<Stream<'i>>>(
-    with_type_checking: bool,
+const OPERATORS: [&str; 10] = [
+    "Scan ",
+    "Aggregate ",
+    "Filter ",
+    "Top ",
+    "Sort ",
+    "TopSort ",
+    "Join ",
+    "Intersect ",
+    "Except ",
+    "Union ",
+];
+
+/// How strictly a QPL is checked, following PICARD's modes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// Only checks that the QPL splits into valid lexemes, without building any lines.
+    Lex,
+    /// Checks the grammar, accepting any table or column name.
+    ParseWithoutGuards,
+    /// Also checks names against the schema and the outputs of previous lines.
+    #[default]
+    ParseWithGuards,
+    /// Also checks that predicates compare values of matching types.
+    ParseWithGuardsAndTypeChecks,
+}
+
+/// What the operators check besides the grammar.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Guards {
+    /// Any table or column name is read, and no output tables are inferred.
+    Off,
+    /// Names are looked up in the schema and in the outputs of previous lines.
+    Names,
+    /// Predicates also compare values of matching types.
+    Types,
+}
+
+impl From<Mode> for Guards {
+    fn from(mode: Mode) -> Self {
+        match mode {
+            Mode::Lex | Mode::ParseWithoutGuards => Guards::Off,
+            Mode::ParseWithGuards => Guards::Names,
+            Mode::ParseWithGuardsAndTypeChecks => Guards::Types,
+        }
+    }
+}
+
+pub(crate) fn qpl<'i, E: QplParserError<'i>>(mode: Mode) -> impl Parser<Stream<'i>, Qpl, E> {
+    qpl_with_boundaries(mode, |_| {})
+}
+
+/// Same grammar as `qpl`, calling `on_boundary` after every " ; " that follows a complete line,
+/// so callers can snapshot the state and resume parsing from there later.
+pub(crate) fn qpl_with_boundaries<'i, E: QplParserError<'i>>(
+    mode: Mode,
+    mut on_boundary: impl FnMut(&Stream<'i>),
 ) -> impl Parser<Stream<'i>, Qpl, E> {
     move |input: &mut Stream<'i>| {
-        (separated(1.., qpl_line(with_type_checking), " ; "), eof)
-            .map(|(qpl, _)| qpl)
-            .parse_next(input)
+        if mode == Mode::Lex {
+            lexed_qpl.parse_next(input)?;
+            return Ok(Qpl::default());
+        }
+        let mut qpl = vec![];
+        loop {
+            let line = cut_err(qpl_line(mode)).parse_next(input)?;
+            qpl.push(line);
+            if opt(" ; ").parse_next(input)?.is_none() {
+                break;
+            }
+            on_boundary(input);
+        }
+        expecting(eof, || Expected::EndOfQpl).parse_next(input)?;
+        Ok(Qpl(qpl))
     }
 }
 
-fn qpl_line<'i, E: ParserError<Stream<'i>>>(
-    with_type_checking: bool,
-) -> impl Parser<Stream<'i>, Line, E> {
+fn qpl_line<'i, E: QplParserError<'i>>(mode: Mode) -> impl Parser<Stream<'i>, Line, E> {
     move |input: &mut Stream<'i>| {
+        let guards = Guards::from(mode);
         let current_idx = input.state.state.current_idx + 1;
-        format!("#{} = ", current_idx).as_str().parse_next(input)?;
+        expecting(format!("#{} = ", current_idx).as_str(), || {
+            Expected::LineStart(current_idx)
+        })
+        .parse_next(input)?;
         input.state.state.current_idx += 1;
+        expecting(peek(alt(OPERATORS)), || Expected::Operator).parse_next(input)?;
         let operation = alt((
-            scan(with_type_checking),
-            aggregate,
-            filter(with_type_checking),
-            top,
-            sort,
-            top_sort,
-            join(with_type_checking),
-            intersect(with_type_checking),
-            except(with_type_checking),
-            union,
+            scan(guards).context(QplContext::Operator("Scan")),
+            aggregate(guards).context(QplContext::Operator("Aggregate")),
+            filter(guards).context(QplContext::Operator("Filter")),
+            top(guards).context(QplContext::Operator("Top")),
+            sort(guards).context(QplContext::Operator("Sort")),
+            top_sort(guards).context(QplContext::Operator("TopSort")),
+            join(guards).context(QplContext::Operator("Join")),
+            intersect(guards).context(QplContext::Operator("Intersect")),
+            except(guards).context(QplContext::Operator("Except")),
+            union(guards).context(QplContext::Operator("Union")),
         ))
         .parse_next(input)?;
         input.state.state.seen.insert(current_idx);
@@ -70,15 +150,17 @@ fn qpl_line<'i, E: ParserError<Stream<'i>>>(
 
 #[cfg(test)]
 mod tests {
+    use self::error::QplError;
     use self::shared::get_input;
     use super::*;
-    use crate::domain::Operation;
-    use winnow::{
-        error::{ContextError, ErrMode},
-        stream::StreamIsPartial,
+    use crate::domain::{
+        Agg, ArithmeticOp, Comparable, Comparison, ExceptOperator, Expression, NumericFunction,
+        Operation, OutputColumn, Predicate,
     };
+    use proptest::{collection::vec, option, prelude::*, sample::select};
+    use winnow::{error::ErrMode, stream::StreamIsPartial};
 
-    const POSITIVES: [&str; 8] = [
+    const POSITIVES: [&str; 13] = [
       "#1 = Scan Table [ stadium ] Output [ Stadium_ID , Capacity , Name ] ; #2 = Scan Table [ concert ] Predicate [ Year >= 2014 ] Output [ Stadium_ID , Year ] ; #3 = Aggregate [ #2 ] GroupBy [ Stadium_ID ] Output [ Stadium_ID , countstar AS Count_Star ] ; #4 = Join [ #1 , #3 ] Predicate [ #3.Stadium_ID = #1.Stadium_ID ] Output [ #1.Name , #3.Count_Star , #1.Capacity ] ; #5 = TopSort [ #4 ] Rows [ 1 ] OrderBy [ Count_Star DESC ] Output [ Capacity , Count_Star , Name ]",
       "#1 = Scan Table [ stadium ] Output [ Stadium_ID , Name ] ; #2 = Scan Table [ concert ] Output [ Stadium_ID ] ; #3 = Except [ #1 , #2 ] Predicate [ #2.Stadium_ID IS NULL OR #1.Stadium_ID = #2.Stadium_ID ] Output [ #1.Name ]",
       "#1 = Scan Table [ singer ] Predicate [ Country = 'france' ] Output [ Age , Country ] ; #2 = Aggregate [ #1 ] Output [ AVG(Age) AS Avg_Age , MAX(Age) AS Max_Age , MIN(Age) AS Min_Age ]",
@@ -86,10 +168,15 @@ mod tests {
       "#1 = Scan Table [ stadium ] Distinct [ true ] Output [ Name ] ; #2 = Scan Table [ stadium ] Output [ Stadium_ID , Name ] ; #3 = Scan Table [ concert ] Predicate [ Year = 2014 ] Output [ Stadium_ID , Year ] ; #4 = Join [ #2 , #3 ] Predicate [ #3.Stadium_ID = #2.Stadium_ID ] Distinct [ true ] Output [ #2.Name ] ; #5 = Except [ #1 , #4 ] Predicate [ #1.Name = #4.Name ] Output [ #1.Name ]",
       "#1 = Scan Table [ stadium ] Predicate [ Capacity >= 5000 AND Capacity <= 10000 ] Output [ Location , Capacity , Name ]",
       "#1 = Scan Table [ stadium ] Output [ Stadium_ID , Name ] ; #2 = Scan Table [ concert ] Output [ Stadium_ID ] ; #3 = Join [ #1 , #2 ] Predicate [ #2.Stadium_ID = #1.Stadium_ID ] Output [ #2.Stadium_ID , #1.Name ] ; #4 = Aggregate [ #3 ] GroupBy [ Stadium_ID ] Output [ countstar AS Count_Star , Name ]",
-      "#1 = Scan Table [ stadium ] Output [ Average , Capacity ] ; #2 = Aggregate [ #1 ] GroupBy [ Average ] Output [ Average , MAX(Capacity) AS Max_Capacity ]"
+      "#1 = Scan Table [ stadium ] Output [ Average , Capacity ] ; #2 = Aggregate [ #1 ] GroupBy [ Average ] Output [ Average , MAX(Capacity) AS Max_Capacity ]",
+      "#1 = Scan Table [ singer ] Predicate [ Country IN ( 'France' , 'Spain' ) AND Age BETWEEN 20 AND 30 ] Output [ Name , Age ] ; #2 = Filter [ #1 ] Predicate [ Name NOT IN ( 'Joe' ) ] Output [ Name ]",
+      "#1 = Scan Table [ stadium ] Output [ Stadium_ID , Capacity ] ; #2 = Scan Table [ concert ] Output [ Stadium_ID , Year ] ; #3 = Join [ #1 , #2 ] Predicate [ #2.Stadium_ID = #1.Stadium_ID AND #2.Year BETWEEN 2014 AND 2015 ] Output [ #1.Capacity ]",
+      "#1 = Scan Table [ singer ] Output [ Name , Age ] ; #2 = Scan Table [ singer ] Output [ Name , Age ] ; #3 = Intersect [ #1 , #2 ] Predicate [ #1.Name = #2.Name AND #2.Age NOT IN ( #1.Age ) ] Output [ #1.Name ] ; #4 = Except [ #3 , #1 ] Predicate [ #3.Name IN ( #1.Name ) ] Output [ #3.Name ]",
+      "#1 = Scan Table [ stadium ] Predicate [ Highest - Lowest > 100 ] Output [ Name , Capacity / 2 AS Half ] ; #2 = Filter [ #1 ] Predicate [ Half >= ABS(- 1000) ] Output [ Name , Half ]",
+      "#1 = Scan Table [ stadium ] Output [ Stadium_ID , Capacity ] ; #2 = Scan Table [ concert ] Output [ Stadium_ID ] ; #3 = Join [ #1 , #2 ] Predicate [ #2.Stadium_ID = #1.Stadium_ID AND ( #1.Capacity - 1000 ) * 2 > ROUND(#1.Capacity / 3) ] Output [ #1.Capacity ]"
     ];
 
-    const NEGATIVES: [&str; 14] = [
+    const NEGATIVES: [&str; 18] = [
       "#1 = Scan Table [ stadium ] Output [ Name, Capacity, Stadium_ID ] ; #2 = Scan Table [ concert ] Predicate [ Year >= 2014 ] Output [ Stadium_ID, Year ] ; #3 = Join [ #1, #2 ] Predicate [ #2.Stadium_ID = #1.Stadium_ID ] Output [ #1.Name, #1.Capacity ] ; #4 = Aggregate [ #3 ] GroupBy [ Name ] Output [ Name, countstar AS Count_Star ] ; #5 = TopSort [ #4 ] Rows [ 1 ] OrderBy [ Count_Star DESC ] Output [ Name, Count_Star, Capacity ]",
       "#1 = Scan Table [ stadium ] Output [ Location, Capacity, Name ] ; #2 = Aggregate [ #1 ] GroupBy [ Capacity ] Output [ Capacity, countstar AS Count_Star, Location ] ; #3 = Filter [ #2 ] Predicate [ Count_Star < 10000.0 ] Output [ Location, Count_Star, Name ]",
       "#1 = Scan Table [ concert ] Output [ Concert_Name, Theme ] ; #2 = Scan Table [ singer_in_concert ] Output [ Concert_ID, Singer_ID ] ; #3 = Join [ #1, #2 ] Predicate [ #2.Concert_ID = #1.Concert_ID ] Output [ #1.Concert_Name, #1.Theme ] ; #4 = Aggregate [ #3 ] GroupBy [ Concert_Name ] Output [ Concert_Name, countstar AS Count_Star ]",
@@ -103,6 +190,10 @@ mod tests {
       "#1 = Scan Table [ stadium ] Output [ Location, Name, Stadium_ID ] ; #2 = Scan Table [ concert ] Predicate [ Year = 2014 AND Year = 2015 ] Output [ Stadium_ID, Year ] ; #3 = Join [ #1, #2 ] Predicate [ #2.Stadium_ID = #1.Stadium_ID ] Output [ #2.Name, #1.Location ]",
       "#1 = Scan Table [ stadium ] Output [ Name, Capacity, Stadium_ID ] ; #2 = Scan Table [ concert ] Predicate [ Year > 2013 ] Output [ Stadium_ID, Year ] ; #3 = Join [ #1, #2 ] Predicate [ #2.Stadium_ID = #1.Stadium_ID ] Output [ #1.Name, #1.Capacity ] ; #4 = Aggregate [ #3 ] GroupBy [ Name ] Output [ Name, countstar AS Count_Star ] ; #5 = TopSort [ #4 ] Rows [ 1 ] OrderBy [ Count_Star DESC ] Output [ Name, Count_Star, Capacity ]",
       "#1 = Scan Table [ stadium ] Output [ Capacity, Location, Name ] ; #2 = Aggregate [ #1 ] GroupBy [ Capacity ] Output [ Capacity, countstar AS Count_Star, Location ] ; #3 = Filter [ #2 ] Predicate [ Count_Star < 10000.0 ] Output [ Location, Name, Count_Star, Location, Name, Count_Star, Location, Count_Star, Location, Name, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Location, Count_Star, Count_Star, Location, Count_Star",
+      "#1 = Scan Table [ singer ] Predicate [ Age IN ( 20 , 'x' ) ] Output [ Name ]",
+      "#1 = Scan Table [ singer ] Predicate [ Age BETWEEN 20 AND 'x' ] Output [ Name ]",
+      "#1 = Scan Table [ singer ] Predicate [ Name + 1 > 2 ] Output [ Name ]",
+      "#1 = Scan Table [ stadium ] Output [ Name * 2 AS Double ]",
       "#1 = Scan Table [ singer ] Output [ Age, Song_Name ] ; #2 = Aggregate [ #1 ] GroupBy [ Age ] Output [ Age, AVG(Age) AS Avg_Age ] ; #3 = TopSort [ #2 ] Rows [ 1 ] OrderBy [ Avg_Age DESC ] Output [ Song_Name, Avg_Age, Affect_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_Sort_"
     ];
 
@@ -110,7 +201,9 @@ mod tests {
     fn test_single_line_qpl() {
         let mut input = get_input("#1 = Scan Table [ stadium ] Output [ Location ]");
         let _ = input.complete();
-        let output = qpl::<ContextError>(true).parse_next(&mut input).unwrap();
+        let output = qpl::<QplError>(Mode::ParseWithGuardsAndTypeChecks)
+            .parse_next(&mut input)
+            .unwrap();
         assert_eq!(
             output,
             vec![Line {
@@ -118,9 +211,14 @@ mod tests {
                 operation: Operation::Scan {
                     table: "stadium".to_owned(),
                     predicate: None,
-                    is_distinct: false
+                    is_distinct: false,
+                    outputs: vec![OutputColumn::Column {
+                        name: "Location".to_owned(),
+                        alias: None
+                    }]
                 }
             }]
+            .into()
         )
     }
 
@@ -128,7 +226,9 @@ mod tests {
     fn test_two_lines_qpl() {
         let mut input = get_input("#1 = Scan Table [ singer ] Output [ Age ] ; #2 = Aggregate [ #1 ] GroupBy [ Age ] Output [ countstar AS Count_Star ]");
         let _ = input.complete();
-        let output = qpl::<ContextError>(true).parse_next(&mut input).unwrap();
+        let output = qpl::<QplError>(Mode::ParseWithGuardsAndTypeChecks)
+            .parse_next(&mut input)
+            .unwrap();
         assert_eq!(
             output,
             vec![
@@ -137,17 +237,23 @@ mod tests {
                     operation: Operation::Scan {
                         table: "singer".to_owned(),
                         predicate: None,
-                        is_distinct: false
+                        is_distinct: false,
+                        outputs: vec![OutputColumn::Column {
+                            name: "Age".to_owned(),
+                            alias: None
+                        }]
                     },
                 },
                 Line {
                     idx: 2,
                     operation: Operation::Aggregate {
                         input: 1,
-                        group_by: vec![String::from("Age")]
+                        group_by: vec![String::from("Age")],
+                        outputs: vec![OutputColumn::CountStar]
                     }
                 }
             ]
+            .into()
         )
     }
 
@@ -156,7 +262,7 @@ mod tests {
         for example in POSITIVES {
             let mut input = get_input(example);
             let _ = input.complete();
-            let result = qpl::<ContextError>(true).parse_next(&mut input);
+            let result = qpl::<QplError>(Mode::ParseWithGuardsAndTypeChecks).parse_next(&mut input);
             assert!(result.is_ok())
         }
     }
@@ -166,15 +272,454 @@ mod tests {
         for example in NEGATIVES {
             let mut input = get_input(example);
             let _ = input.complete();
-            let result = qpl::<ContextError>(true).parse_next(&mut input);
+            let result = qpl::<QplError>(Mode::ParseWithGuardsAndTypeChecks).parse_next(&mut input);
             assert!(result.is_err());
         }
     }
 
+    fn parse_complete(source: &str, mode: Mode) -> Qpl {
+        let mut input = get_input(source);
+        let _ = input.complete();
+        qpl::<QplError>(mode).parse_next(&mut input).unwrap()
+    }
+
+    #[test]
+    fn test_positives_print_as_written() {
+        for mode in [
+            Mode::ParseWithoutGuards,
+            Mode::ParseWithGuards,
+            Mode::ParseWithGuardsAndTypeChecks,
+        ] {
+            for example in POSITIVES {
+                let parsed = parse_complete(example, mode);
+                assert_eq!(parsed.to_string(), example);
+                assert_eq!(parse_complete(&parsed.to_string(), mode), parsed);
+            }
+        }
+    }
+
+    #[test]
+    fn test_print_is_canonical() {
+        let parsed = parse_complete(
+            "#1 = Scan Table [ singer ] Predicate [ Age  >  30.0 ] Output [ Name , Age ] ; #2 = Aggregate [ #1 ] Output [ MAX(DISTINCT Age) AS Max_Dist_age ]",
+            Mode::ParseWithGuards,
+        );
+        assert_eq!(
+            parsed.to_string(),
+            "#1 = Scan Table [ singer ] Predicate [ Age > 30 ] Output [ Name , Age ] ; #2 = Aggregate [ #1 ] Output [ MAX(DISTINCT Age) AS Max_Dist_age ]"
+        );
+    }
+
+    #[test]
+    fn test_print_keeps_needed_parentheses() {
+        let parsed = parse_complete(
+            "#1 = Scan Table [ singer ] Predicate [ ( ( Age > 30 ) ) AND ( Country = 'France' OR Country = 'Spain' ) OR ( Age < 20 AND Name = 'Joe' ) ] Output [ Name ]",
+            Mode::ParseWithGuards,
+        );
+        assert_eq!(
+            parsed.to_string(),
+            "#1 = Scan Table [ singer ] Predicate [ Age > 30 AND ( Country = 'France' OR Country = 'Spain' ) OR Age < 20 AND Name = 'Joe' ] Output [ Name ]"
+        );
+    }
+
+    #[test]
+    fn test_names_starting_like_keywords_or_literals() {
+        let mut schema = crate::schemas::concert_singer();
+        schema.column_names[9] = "Info".to_owned();
+        schema.column_names[10] = "Notes".to_owned();
+        let qpl = "#1 = Scan Table [ singer ] Predicate [ Name IS Notes OR Name = Info ] Output [ Info , Notes ]";
+        for mode in [Mode::ParseWithoutGuards, Mode::ParseWithGuards] {
+            let parsed = crate::parse(qpl, &schema, mode).unwrap();
+            let [Line {
+                operation: Operation::Scan { predicate, .. },
+                ..
+            }] = &parsed.qpl[..]
+            else {
+                panic!("{qpl} is not a single scan");
+            };
+            assert_eq!(
+                predicate,
+                &Some(Predicate::Or {
+                    lhs: Predicate::Single {
+                        comparison: Comparison::Is(
+                            Comparable::Column("Name".to_owned()),
+                            Comparable::Column("Notes".to_owned())
+                        )
+                    }
+                    .into(),
+                    rhs: Predicate::Single {
+                        comparison: Comparison::Equal(
+                            Comparable::Column("Name".to_owned()),
+                            Comparable::Column("Info".to_owned())
+                        )
+                    }
+                    .into(),
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_names_starting_like_float_literals_print_bare() {
+        let mut schema = crate::schemas::concert_singer();
+        schema.column_names[9] = "Info".to_owned();
+        schema.column_names[10] = "Nancy".to_owned();
+        schema.column_names[11] = "Infant".to_owned();
+        let qpl = "#1 = Scan Table [ singer ] Predicate [ Name = \"Info\" OR Nancy = Infant ] Output [ \"Nancy\" , Infant ]";
+        for mode in [Mode::ParseWithoutGuards, Mode::ParseWithGuards] {
+            let parsed = crate::parse(qpl, &schema, mode).unwrap();
+            let printed = parsed.qpl.to_string();
+            assert_eq!(
+                printed,
+                "#1 = Scan Table [ singer ] Predicate [ Name = Info OR Nancy = Infant ] Output [ Nancy , Infant ]"
+            );
+            assert_eq!(
+                crate::parse(&printed, &schema, mode).unwrap().qpl,
+                parsed.qpl
+            );
+        }
+    }
+
+    #[test]
+    fn test_reserved_names_read_bare() {
+        let mut schema = crate::schemas::concert_singer();
+        schema.column_names[9] = "Order".to_owned();
+        let qpl = "#1 = Scan Table [ singer ] Predicate [ Order > 1 ] Output [ Order ] ; #2 = Sort [ #1 ] OrderBy [ Order ASC ] Output [ Order ]";
+        for mode in [Mode::ParseWithoutGuards, Mode::ParseWithGuards] {
+            let parsed = crate::parse(qpl, &schema, mode).unwrap();
+            assert_eq!(
+                parsed.qpl.to_string(),
+                "#1 = Scan Table [ singer ] Predicate [ \"Order\" > 1 ] Output [ \"Order\" ] ; #2 = Sort [ #1 ] OrderBy [ \"Order\" ASC ] Output [ \"Order\" ]"
+            );
+        }
+    }
+
+    /// Bare names, including ones starting like a literal or an operator as `Info` and
+    /// `Notes`, along with names only written quoted.
+    fn identifier() -> BoxedStrategy<String> {
+        prop_oneof![
+            "[A-Z][a-z0-9_]{0,8}",
+            select(vec![
+                "Info",
+                "Nano",
+                "Notes",
+                "Likes",
+                "Isle",
+                "Inside",
+                "Betweenness"
+            ])
+            .prop_map(str::to_owned),
+            "[A-Z][a-z]{0,4}[ \"-][a-z ]{0,4}",
+            select(vec!["Order", "Null", "Desc", "2nd"]).prop_map(str::to_owned),
+        ]
+        .boxed()
+    }
+
+    /// Booleans are left out: they print as `0` and `1`, which read back as numbers.
+    fn comparable() -> impl Strategy<Value = Comparable> {
+        prop_oneof![
+            (-1e6..1e6f64).prop_map(Comparable::Number),
+            (0..1000u32).prop_map(|n| Comparable::Number(n.into())),
+            "[a-zA-Z0-9 %]{0,10}".prop_map(Comparable::Str),
+            Just(Comparable::Null),
+            identifier().prop_map(Comparable::Column),
+            (1..10usize, identifier()).prop_map(|(idx, c)| Comparable::IndexedColumn(idx, c)),
+            expression().prop_map(Comparable::from),
+        ]
+    }
+
+    fn expression() -> impl Strategy<Value = Expression> {
+        let operand = prop_oneof![
+            (-1e6..1e6f64).prop_map(Comparable::Number),
+            identifier().prop_map(Comparable::Column),
+            (1..10usize, identifier()).prop_map(|(idx, c)| Comparable::IndexedColumn(idx, c)),
+        ];
+        let ops = vec![
+            ArithmeticOp::Add,
+            ArithmeticOp::Subtract,
+            ArithmeticOp::Multiply,
+            ArithmeticOp::Divide,
+        ];
+        let function = (select(NumericFunction::values().to_vec()), operand.clone())
+            .prop_map(|(function, argument)| Expression::Function(function, argument));
+        function.prop_recursive(3, 8, 2, move |inner| {
+            let operand = prop_oneof![operand.clone(), inner.prop_map(Comparable::from)];
+            prop_oneof![
+                operand.clone().prop_map(Expression::Negate),
+                (select(ops.clone()), operand.clone(), operand.clone())
+                    .prop_map(|(op, lhs, rhs)| Expression::Binary(op, lhs, rhs)),
+                (select(NumericFunction::values().to_vec()), operand)
+                    .prop_map(|(function, argument)| Expression::Function(function, argument)),
+            ]
+        })
+    }
+
+    fn comparison() -> impl Strategy<Value = Comparison> {
+        let ops = vec![
+            "=", "<>", ">", ">=", "<", "<=", "IS", "IS NOT", "LIKE", "NOT LIKE",
+        ];
+        let binary = (select(ops), comparable(), comparable())
+            .prop_map(|(op, lhs, rhs)| Comparison::from_string(op, lhs, rhs).unwrap());
+        prop_oneof![
+            4 => binary,
+            1 => (comparable(), vec(comparable(), 1..4)).prop_map(|(lhs, values)| Comparison::In(lhs, values)),
+            1 => (comparable(), vec(comparable(), 1..4)).prop_map(|(lhs, values)| Comparison::NotIn(lhs, values)),
+            1 => (comparable(), comparable(), comparable())
+                .prop_map(|(lhs, low, high)| Comparison::Between(lhs, low, high)),
+        ]
+    }
+
+    fn predicate() -> impl Strategy<Value = Predicate> {
+        let single = comparison().prop_map(|comparison| Predicate::Single { comparison });
+        single.prop_recursive(3, 8, 2, |inner| {
+            (any::<bool>(), inner.clone(), inner).prop_map(|(is_and, lhs, rhs)| {
+                let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
+                if is_and {
+                    Predicate::And { lhs, rhs }
+                } else {
+                    Predicate::Or { lhs, rhs }
+                }
+            })
+        })
+    }
+
+    fn column() -> impl Strategy<Value = OutputColumn> {
+        identifier().prop_map(|name| OutputColumn::Column { name, alias: None })
+    }
+
+    fn indexed() -> impl Strategy<Value = OutputColumn> {
+        (1..10usize, identifier()).prop_map(|(input, name)| OutputColumn::Indexed { input, name })
+    }
+
+    fn one_or(
+        outputs: impl Strategy<Value = Vec<OutputColumn>>,
+    ) -> impl Strategy<Value = Vec<OutputColumn>> {
+        prop_oneof![Just(vec![OutputColumn::One]), outputs]
+    }
+
+    fn order_by() -> impl Strategy<Value = Vec<String>> {
+        vec(
+            (identifier(), select(vec!["ASC", "DESC"])).prop_map(|(by, dir)| format!("{by} {dir}")),
+            1..3,
+        )
+    }
+
+    fn operation() -> impl Strategy<Value = Operation> {
+        let inputs = vec(1..10usize, 2);
+        let scan_output = prop_oneof![
+            (identifier(), option::of(identifier()))
+                .prop_map(|(name, alias)| OutputColumn::Column { name, alias }),
+            (expression(), identifier())
+                .prop_map(|(expression, alias)| OutputColumn::Expression { expression, alias }),
+        ];
+        let aggregate_output = prop_oneof![
+            Just(OutputColumn::CountStar),
+            (
+                select(Agg::values()),
+                any::<bool>(),
+                identifier(),
+                identifier()
+            )
+                .prop_map(|(agg, is_distinct, column, alias)| {
+                    OutputColumn::Aggregate {
+                        agg,
+                        is_distinct,
+                        column,
+                        alias,
+                    }
+                }),
+            column(),
+        ];
+        prop_oneof![
+            (
+                identifier(),
+                option::of(predicate()),
+                any::<bool>(),
+                one_or(vec(scan_output, 1..4))
+            )
+                .prop_map(|(table, predicate, is_distinct, outputs)| Operation::Scan {
+                    table,
+                    predicate,
+                    is_distinct,
+                    outputs
+                }),
+            (
+                1..10usize,
+                vec(identifier(), 0..3),
+                vec(aggregate_output, 1..4)
+            )
+                .prop_map(|(input, group_by, outputs)| Operation::Aggregate {
+                    input,
+                    group_by,
+                    outputs
+                }),
+            (
+                1..10usize,
+                option::of(predicate()),
+                any::<bool>(),
+                one_or(vec(column(), 1..4))
+            )
+                .prop_map(|(input, predicate, is_distinct, outputs)| {
+                    Operation::Filter {
+                        input,
+                        predicate,
+                        is_distinct,
+                        outputs,
+                    }
+                }),
+            (1..10usize, 0..1000usize, vec(column(), 1..4)).prop_map(|(input, rows, outputs)| {
+                Operation::Top {
+                    input,
+                    rows,
+                    outputs,
+                }
+            }),
+            (1..10usize, order_by(), any::<bool>(), vec(column(), 1..4)).prop_map(
+                |(input, order_by, is_distinct, outputs)| Operation::Sort {
+                    input,
+                    order_by,
+                    is_distinct,
+                    outputs
+                }
+            ),
+            (
+                1..10usize,
+                0..1000usize,
+                order_by(),
+                any::<bool>(),
+                vec(column(), 1..4)
+            )
+                .prop_map(|(input, rows, order_by, with_ties, outputs)| {
+                    Operation::TopSort {
+                        input,
+                        rows,
+                        order_by,
+                        with_ties,
+                        outputs,
+                    }
+                }),
+            (
+                inputs.clone(),
+                option::of(predicate()),
+                any::<bool>(),
+                one_or(vec(indexed(), 1..4))
+            )
+                .prop_map(|(inputs, predicate, is_distinct, outputs)| {
+                    Operation::Join {
+                        inputs,
+                        predicate,
+                        is_distinct,
+                        outputs,
+                    }
+                }),
+            (
+                inputs.clone(),
+                option::of(predicate()),
+                any::<bool>(),
+                one_or(vec(indexed(), 1..4))
+            )
+                .prop_map(|(inputs, predicate, is_distinct, outputs)| {
+                    Operation::Intersect {
+                        inputs,
+                        predicate,
+                        is_distinct,
+                        outputs,
+                    }
+                }),
+            (
+                inputs.clone(),
+                prop_oneof![
+                    predicate().prop_map(ExceptOperator::Predicate),
+                    (1..10usize, identifier())
+                        .prop_map(|(idx, column)| ExceptOperator::ExceptColum(idx, column)),
+                ],
+                any::<bool>(),
+                one_or(vec(indexed(), 1..4))
+            )
+                .prop_map(|(inputs, operator, is_distinct, outputs)| {
+                    Operation::Except {
+                        inputs,
+                        operator,
+                        is_distinct,
+                        outputs,
+                    }
+                }),
+            (inputs, vec(indexed(), 1..4))
+                .prop_map(|(inputs, outputs)| Operation::Union { inputs, outputs }),
+        ]
+    }
+
+    fn arbitrary_qpl() -> impl Strategy<Value = Qpl> {
+        vec(operation(), 1..5).prop_map(|operations| {
+            operations
+                .into_iter()
+                .zip(1..)
+                .map(|(operation, idx)| Line { idx, operation })
+                .collect::<Vec<_>>()
+                .into()
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn test_parse_of_print_round_trips(qpl in arbitrary_qpl()) {
+            let printed = qpl.to_string();
+            prop_assert_eq!(parse_complete(&printed, Mode::ParseWithoutGuards), qpl);
+        }
+    }
+
     #[test]
     fn test_partial_qpl() {
         let mut input = get_input("#1 = Scan Table [ stadium ] Output [ Name, Capacity, Stadium_ID ] ; #2 = Scan Table [ concert ] Predicate [ Year >= 2014 ] Output [ Stadium_ID, Year ] ; #3 = Join [ #1, #2 ] Predicate [ #2.Stadium_ID = #1.Stadium_ID ] Output [ #1.Name, #1.Capacity ] ; #4 = Aggregate [ #3 ] GroupBy [ Name ] Output [ Name, countstar AS Count_Star ] ; #5 = TopSort [ #4 ] Rows [ 1 ] OrderBy [ Count_Star ");
-        let result = qpl::<ContextError>(true).parse_next(&mut input);
+        let result = qpl::<QplError>(Mode::ParseWithGuardsAndTypeChecks).parse_next(&mut input);
+        assert!(matches!(result, Err(ErrMode::Incomplete(_))));
+    }
+
+    #[test]
+    fn test_unguarded_qpl_ignores_schema() {
+        let mut input = get_input("#1 = Scan Table [ foo ] Predicate [ bar = 'x' ] Output [ bar , baz AS b ] ; #2 = Aggregate [ #1 ] GroupBy [ baz ] Output [ baz , MAX(bar) AS Max_Bar ] ; #3 = Join [ #1 , #2 ] Predicate [ #1.baz = #2.baz ] Output [ #1.bar , #2.Max_Bar ]");
+        let _ = input.complete();
+        let output = qpl::<QplError>(Mode::ParseWithoutGuards)
+            .parse_next(&mut input)
+            .unwrap();
+        assert_eq!(output.len(), 3);
+        assert!(matches!(&output[0].operation, Operation::Scan { table, .. } if table == "foo"));
+    }
+
+    #[test]
+    fn test_unguarded_qpl_checks_structure() {
+        let mut input = get_input(
+            "#1 = Scan Table [ foo ] Output [ bar ] ; #3 = Top [ #1 ] Rows [ 1 ] Output [ bar ]",
+        );
+        let _ = input.complete();
+        let error = qpl::<QplError>(Mode::ParseWithoutGuards)
+            .parse_next(&mut input)
+            .unwrap_err();
+        assert_eq!(error.into_inner().unwrap().line(), Some(2));
+    }
+
+    #[test]
+    fn test_unguarded_qpl_groups_predicates() {
+        let mut input = get_input(
+            "#1 = Scan Table [ foo ] Output [ bar ] ; #2 = Filter [ #1 ] Predicate [ bar = 1 AND ( bar = 2 OR bar = 3 ) ] Output [ bar ]",
+        );
+        let _ = input.complete();
+        let output = qpl::<QplError>(Mode::ParseWithoutGuards)
+            .parse_next(&mut input)
+            .unwrap();
+        assert!(matches!(
+            &output[1].operation,
+            Operation::Filter {
+                predicate: Some(Predicate::And { rhs, .. }),
+                ..
+            } if matches!(**rhs, Predicate::Or { .. })
+        ));
+    }
+
+    #[test]
+    fn test_unguarded_qpl_partial() {
+        let mut input = get_input("#1 = Scan Table [ fo");
+        let result = qpl::<QplError>(Mode::ParseWithoutGuards).parse_next(&mut input);
         assert!(matches!(result, Err(ErrMode::Incomplete(_))));
     }
 }
@@ -1,50 +1,65 @@
 use super::{
-    shared::{column_in_index, column_name, get_output, input_ids, ColumnParserType, Stream},
+    error::{expecting, Expected, QplParserError},
+    shared::{
+        column_in_index, column_name, get_output, guarded, identifier, input_ids, ColumnParserType,
+        Stream,
+    },
     utils::{has_duplicates, starts_with_agg},
+    Guards,
 };
-use crate::domain::{Agg, Operation, Table};
+use crate::domain::{is_bare_identifier, Agg, Operation, OutputColumn, Table};
 use std::collections::{HashMap, HashSet};
 use winnow::{
     ascii::{multispace0, Caseless},
-    combinator::{alt, empty, fail, opt, separated},
-    error::ParserError,
+    combinator::{alt, cut_err, empty, fail, opt, separated},
     PResult, Parser,
 };
 
-pub(crate) fn aggregate<'i, E: ParserError<Stream<'i>>>(
-    input: &mut Stream<'i>,
-) -> PResult<Operation, E> {
-    "Aggregate ".parse_next(input)?;
-    let inputs = input_ids.parse_next(input)?;
-    if inputs.len() != 1 {
-        return fail.parse_next(input);
-    }
-    let input_idx = inputs[0];
-    let gbs = opt(group_by(input_idx)).parse_next(input)?;
-    "Output [ ".parse_next(input)?;
-    let outs = outputs(input_idx).parse_next(input)?;
-    let idx_to_table = &input.state.state.idx_to_table;
-    if !validate_output(input_idx, &outs, idx_to_table) {
-        return fail.parse_next(input);
+pub(crate) fn aggregate<'i, E: QplParserError<'i>>(
+    guards: Guards,
+) -> impl Parser<Stream<'i>, Operation, E> {
+    move |input: &mut Stream<'i>| {
+        "Aggregate ".parse_next(input)?;
+        let inputs = input_ids(1, guards).parse_next(input)?;
+        let input_idx = inputs[0];
+        let gbs = opt(group_by(input_idx, guards)).parse_next(input)?;
+        "Output [ ".parse_next(input)?;
+        let outputs = outputs(input_idx, guards).parse_next(input)?;
+        if guards != Guards::Off {
+            let outs = outputs
+                .iter()
+                .map(|out| out.name().to_owned())
+                .collect::<Vec<_>>();
+            let idx_to_table = &input.state.state.idx_to_table;
+            if !validate_output(input_idx, &outs, idx_to_table) {
+                return expecting(fail, || Expected::Outputs).parse_next(input);
+            }
+            let output_table = get_output(inputs, outs).parse_next(input)?;
+            let state = &mut input.state.state;
+            state.idx_to_table.insert(state.current_idx, output_table);
+        }
+        " ]".parse_next(input)?;
+        Ok(Operation::Aggregate {
+            input: input_idx,
+            group_by: gbs.unwrap_or(vec![]),
+            outputs,
+        })
     }
-    let output_table = get_output(inputs, outs).parse_next(input)?;
-    let state = &mut input.state.state;
-    state.idx_to_table.insert(state.current_idx, output_table);
-    " ]".parse_next(input)?;
-    Ok(Operation::Aggregate {
-        input: input_idx,
-        group_by: gbs.unwrap_or(vec![]),
-    })
 }
 
-fn group_by<'i, E: ParserError<Stream<'i>>>(
+fn group_by<'i, E: QplParserError<'i>>(
     input_idx: usize,
+    guards: Guards,
 ) -> impl Parser<Stream<'i>, Vec<String>, E> {
     move |input: &mut Stream<'i>| {
         "GroupBy [ ".parse_next(input)?;
         let columns = separated(
             1..,
-            column_in_index(input_idx, ColumnParserType::Named),
+            cut_err(guarded(
+                guards,
+                column_in_index(input_idx, ColumnParserType::Named),
+                identifier,
+            )),
             (multispace0, ", "),
         )
         .parse_next(input)?;
@@ -53,44 +68,79 @@ fn group_by<'i, E: ParserError<Stream<'i>>>(
     }
 }
 
-fn outputs<'i, E: ParserError<Stream<'i>>>(
+fn outputs<'i, E: QplParserError<'i>>(
     input_idx: usize,
-) -> impl Parser<Stream<'i>, Vec<String>, E> {
+    guards: Guards,
+) -> impl Parser<Stream<'i>, Vec<OutputColumn>, E> {
     move |input: &mut Stream<'i>| {
         separated(
             1..,
-            alt((
-                "countstar AS Count_Star".map(|s: &'i str| s.to_owned()),
-                aliased_aggregate(input_idx),
-                column_name,
-            )),
+            cut_err(alt((
+                "countstar AS Count_Star".value(OutputColumn::CountStar),
+                aliased_aggregate(input_idx, guards),
+                guarded(guards, column_name, identifier)
+                    .map(|name| OutputColumn::Column { name, alias: None }),
+            ))),
             (multispace0, ", "),
         )
         .parse_next(input)
     }
 }
 
-fn aliased_aggregate<'i, E: ParserError<Stream<'i>>>(
+fn aliased_aggregate<'i, E: QplParserError<'i>>(
     input_idx: usize,
-) -> impl Parser<Stream<'i>, String, E> {
-    move |input: &mut Stream<'i>| {
+    guards: Guards,
+) -> impl Parser<Stream<'i>, OutputColumn, E> {
+    let parser = move |input: &mut Stream<'i>| -> PResult<OutputColumn, E> {
         let aggregate = agg.parse_next(input)?;
         "(".parse_next(input)?;
         let is_distinct = alt(("DISTINCT ".value(true), empty.value(false))).parse_next(input)?;
-        let column = column_in_index(input_idx, ColumnParserType::Named).parse_next(input)?;
+        let column = guarded(
+            guards,
+            column_in_index(input_idx, ColumnParserType::Named),
+            identifier,
+        )
+        .parse_next(input)?;
         ") AS ".parse_next(input)?;
+        // Without guards the alias is free, as it is not derived from a looked up column.
+        if guards == Guards::Off {
+            let alias = identifier.parse_next(input)?;
+            return Ok(OutputColumn::Aggregate {
+                agg: aggregate,
+                is_distinct,
+                column,
+                alias,
+            });
+        }
+        // The alias is quoted when the column is, the prefix only ever makes it bare.
+        let quoted = !is_bare_identifier(&format!("{aggregate}_{column}"));
+        if quoted {
+            "\"".parse_next(input)?;
+        }
         let prefix = format!("{}_", aggregate).as_str().parse_next(input)?;
         let dist = if is_distinct {
             "Dist_".parse_next(input)
         } else {
             empty.value("").parse_next(input)
         }?;
-        let alias = Caseless(column.as_str()).parse_next(input)?;
-        Ok(format!("{}{}{}", prefix, dist, alias))
-    }
+        let alias = if quoted {
+            let alias = Caseless(column.replace('"', "\"\"").as_str()).parse_next(input)?;
+            "\"".parse_next(input)?;
+            alias.replace("\"\"", "\"")
+        } else {
+            Caseless(column.as_str()).parse_next(input)?.to_owned()
+        };
+        Ok(OutputColumn::Aggregate {
+            agg: aggregate,
+            is_distinct,
+            column,
+            alias: format!("{}{}{}", prefix, dist, alias),
+        })
+    };
+    expecting(parser, move || Expected::Aggregate(input_idx))
 }
 
-fn agg<'i, E: ParserError<Stream<'i>>>(input: &mut Stream<'i>) -> PResult<Agg, E> {
+fn agg<'i, E: QplParserError<'i>>(input: &mut Stream<'i>) -> PResult<Agg, E> {
     for agg in Agg::values() {
         if let Some(a) = opt(agg.to_string().to_uppercase().value(agg)).parse_next(input)? {
             return Ok(a);
@@ -108,7 +158,10 @@ fn validate_output(
         .iter()
         .filter(|out| !starts_with_agg(out))
         .collect::<Vec<_>>();
-    let prev_columns = idx_to_table[&input_idx]
+    let Some(prev_table) = idx_to_table.get(&input_idx) else {
+        return false;
+    };
+    let prev_columns = prev_table
         .columns()
         .iter()
         .map(|c| c.name())
@@ -124,8 +177,9 @@ fn validate_output(
 mod tests {
     use super::*;
     use crate::domain::{Column, ColumnType, QplState};
+    use crate::parser::error::QplError;
     use crate::parser::shared::get_input;
-    use winnow::{error::ContextError, stream::StreamIsPartial};
+    use winnow::stream::StreamIsPartial;
 
     #[test]
     fn test_aggregate_count_star() {
@@ -142,12 +196,15 @@ mod tests {
             )]),
         };
         let _ = input.complete();
-        let output = aggregate::<ContextError>.parse_next(&mut input).unwrap();
+        let output = aggregate::<QplError>(Guards::Names)
+            .parse_next(&mut input)
+            .unwrap();
         assert_eq!(
             output,
             Operation::Aggregate {
                 input: 1,
-                group_by: vec![]
+                group_by: vec![],
+                outputs: vec![OutputColumn::CountStar]
             }
         )
     }
@@ -172,12 +229,15 @@ mod tests {
             )]),
         };
         let _ = input.complete();
-        let output = aggregate::<ContextError>.parse_next(&mut input).unwrap();
+        let output = aggregate::<QplError>(Guards::Names)
+            .parse_next(&mut input)
+            .unwrap();
         assert_eq!(
             output,
             Operation::Aggregate {
                 input: 1,
-                group_by: vec!["Theme".to_owned()]
+                group_by: vec!["Theme".to_owned()],
+                outputs: vec![OutputColumn::CountStar]
             }
         )
     }
@@ -201,12 +261,20 @@ mod tests {
             )]),
         };
         let _ = input.complete();
-        let output = aggregate::<ContextError>.parse_next(&mut input).unwrap();
+        let output = aggregate::<QplError>(Guards::Names)
+            .parse_next(&mut input)
+            .unwrap();
         assert_eq!(
             output,
             Operation::Aggregate {
                 input: 1,
-                group_by: vec![]
+                group_by: vec![],
+                outputs: vec![OutputColumn::Aggregate {
+                    agg: Agg::Max,
+                    is_distinct: false,
+                    column: "Age".to_owned(),
+                    alias: "Max_Age".to_owned()
+                }]
             }
         )
     }
@@ -231,12 +299,20 @@ mod tests {
             )]),
         };
         let _ = input.complete();
-        let output = aggregate::<ContextError>.parse_next(&mut input).unwrap();
+        let output = aggregate::<QplError>(Guards::Names)
+            .parse_next(&mut input)
+            .unwrap();
         assert_eq!(
             output,
             Operation::Aggregate {
                 input: 1,
-                group_by: vec![]
+                group_by: vec![],
+                outputs: vec![OutputColumn::Aggregate {
+                    agg: Agg::Count,
+                    is_distinct: true,
+                    column: "Age".to_owned(),
+                    alias: "Count_Dist_Age".to_owned()
+                }]
             }
         )
     }
@@ -260,6 +336,8 @@ mod tests {
             )]),
         };
         let _ = input.complete();
-        assert!(aggregate::<ContextError>.parse_next(&mut input).is_err());
+        assert!(aggregate::<QplError>(Guards::Names)
+            .parse_next(&mut input)
+            .is_err());
     }
 }
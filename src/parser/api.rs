@@ -1,29 +1,206 @@
-use super::{qpl, shared::Stream};
-use crate::domain::{Qpl, SqlSchema};
-use std::collections::HashMap;
+//! Parsing entry points, including the public API which keeps winnow out of its signatures.
+
+use super::{
+    error::{expecting, Expected, ParseError, QplError, QplParserError},
+    qpl, qpl_with_boundaries,
+    shared::word,
+    shared::Stream,
+    Mode,
+};
+use crate::domain::{Qpl, QplEnvironment, QplState, SqlSchema, Table};
+use std::collections::{BTreeMap, HashMap};
 use winnow::{
     ascii::{multispace0, Caseless},
     combinator::{alt, fail, opt, repeat},
-    error::ParserError,
-    PResult, Parser,
+    error::ErrMode,
+    stream::{Stream as _, StreamIsPartial},
+    PResult, Parser, Partial,
 };
 
-pub(crate) fn prefixed_qpl<'i, 'j, E: ParserError<Stream<'i>>>(
+/// A QPL along with the output table inferred for each of its lines.
+#[derive(Debug)]
+pub struct ParsedQpl {
+    pub qpl: Qpl,
+    /// Output tables by line number. Only the modes with guards infer them.
+    pub outputs: BTreeMap<usize, Table>,
+}
+
+/// How far a possibly unfinished QPL got, see [`parse_partial`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PartialStatus {
+    /// The QPL is complete and valid.
+    Complete,
+    /// The QPL is a valid prefix that needs more text.
+    Incomplete,
+    /// No text appended to the QPL can make it valid.
+    Invalid,
+}
+
+/// Parser state right after a line boundary of a partially decoded QPL, from which parsing
+/// a longer text with the same beginning resumes.
+#[derive(Clone, Debug)]
+pub struct PartialParse {
+    pub(crate) consumed: String,
+    pub(crate) env: QplEnvironment,
+}
+
+/// Parses the lines of a QPL against `schema`.
+pub fn parse(source: &str, schema: &SqlSchema, mode: Mode) -> Result<ParsedQpl, ParseError> {
+    let env = QplEnvironment {
+        state: QplState::default(),
+        schema: Some(schema.clone()),
+    };
+    parse_complete(source, env, qpl(mode))
+}
+
+/// Parses a QPL starting with the `<db_id> |` prefix that picks its schema among `schemas`.
+pub fn parse_prefixed(
+    source: &str,
+    schemas: &HashMap<String, SqlSchema>,
+    mode: Mode,
+) -> Result<ParsedQpl, ParseError> {
+    let env = QplEnvironment {
+        state: QplState::default(),
+        schema: None,
+    };
+    parse_complete(source, env, prefixed_qpl(schemas, mode))
+}
+
+/// Checks whether a prefixed QPL, possibly cut short, can still be completed into a valid one.
+///
+/// Parsing skips the part of `source` already covered by `resume_from` and also returns the
+/// last line boundary reached beyond it, to resume from when `source` is extended. Unless
+/// `is_complete`, running out of text is not an error.
+pub fn parse_partial(
+    source: &str,
+    schemas: &HashMap<String, SqlSchema>,
+    mode: Mode,
+    is_complete: bool,
+    resume_from: Option<&PartialParse>,
+) -> (PartialStatus, Option<PartialParse>) {
+    let (result, partial_parse) =
+        resumable_prefixed_qpl::<()>(schemas, mode, source, is_complete, resume_from);
+    let status = match result {
+        Ok(_) => PartialStatus::Complete,
+        Err(ErrMode::Incomplete(_)) => PartialStatus::Incomplete,
+        Err(_) => PartialStatus::Invalid,
+    };
+    (status, partial_parse)
+}
+
+fn parse_complete<'i>(
+    source: &'i str,
+    env: QplEnvironment,
+    mut parser: impl Parser<Stream<'i>, Qpl, QplError>,
+) -> Result<ParsedQpl, ParseError> {
+    let mut input = Stream {
+        input: Partial::new(source),
+        state: env,
+    };
+    let _ = input.complete();
+    match parser.parse_next(&mut input) {
+        Ok(qpl) => Ok(ParsedQpl {
+            qpl,
+            outputs: input.state.state.idx_to_table.into_iter().collect(),
+        }),
+        Err(e) => Err(match e.into_inner() {
+            Some(error) => ParseError::new(source, &error),
+            None => ParseError::incomplete(source),
+        }),
+    }
+}
+
+pub(crate) fn prefixed_qpl<'i, 'j, E: QplParserError<'i>>(
     schemas: &'j HashMap<String, SqlSchema>,
-    with_type_checking: bool,
+    mode: Mode,
 ) -> impl Parser<Stream<'i>, Qpl, E> + 'j {
     move |input: &mut Stream<'i>| {
+        qpl_prefix(schemas, mode).parse_next(input)?;
+        qpl(mode).parse_next(input)
+    }
+}
+
+/// Parses `source` like `prefixed_qpl`, skipping the part already covered by `resume_from`
+/// when `source` extends it. The lines skipped that way are not parsed again, so after a
+/// resume the returned `Qpl` only holds the lines that follow the resume point.
+///
+/// Also returns the last line boundary reached beyond `resume_from`, if any.
+pub(crate) fn resumable_prefixed_qpl<'i, E: QplParserError<'i>>(
+    schemas: &HashMap<String, SqlSchema>,
+    mode: Mode,
+    source: &'i str,
+    is_complete: bool,
+    resume_from: Option<&PartialParse>,
+) -> (PResult<Qpl, E>, Option<PartialParse>) {
+    let resume_from = resume_from.filter(|p| source.starts_with(&p.consumed));
+    let (offset, env) = match resume_from {
+        Some(p) => (p.consumed.len(), p.env.clone()),
+        None => (
+            0,
+            QplEnvironment {
+                state: QplState::default(),
+                schema: None,
+            },
+        ),
+    };
+    let mut input = Stream {
+        input: Partial::new(&source[offset..]),
+        state: env,
+    };
+    if is_complete {
+        let _ = input.complete();
+    }
+
+    let mut boundary = None;
+    if resume_from.is_none() {
+        if let Err(e) = qpl_prefix(schemas, mode).parse_next(&mut input) {
+            return (Err(e), None);
+        }
+        boundary = Some((input.eof_offset(), input.state.clone()));
+    }
+    let result = qpl_with_boundaries(mode, |input: &Stream<'i>| {
+        boundary = Some((input.eof_offset(), input.state.clone()));
+    })
+    .parse_next(&mut input);
+
+    let partial_parse = boundary.map(|(eof_offset, env)| PartialParse {
+        consumed: source[..source.len() - eof_offset].to_owned(),
+        env,
+    });
+    (result, partial_parse)
+}
+
+pub(crate) fn qpl_prefix<'i, 'j, E: QplParserError<'i>>(
+    schemas: &'j HashMap<String, SqlSchema>,
+    mode: Mode,
+) -> impl Parser<Stream<'i>, (), E> + 'j {
+    move |input: &mut Stream<'i>| {
+        // Lexing covers the prefix along with the lines.
+        if mode == Mode::Lex {
+            return Ok(());
+        }
         multispace0.parse_next(input)?;
-        repeat(0.., special_token).parse_next(input)?;
+        let () = repeat(0.., special_token).parse_next(input)?;
         multispace0.parse_next(input)?;
-        let schema = schema(schemas).parse_next(input)?;
-        input.state.schema = Some(schema.clone());
-        (multispace0, "|", multispace0).parse_next(input)?;
-        qpl(with_type_checking).parse_next(input)
+        let schema = if mode == Mode::ParseWithoutGuards {
+            let db_id = expecting(word, || Expected::DbId).parse_next(input)?;
+            schemas.get(&db_id).cloned()
+        } else {
+            let schema = expecting(schema(schemas), || Expected::DbId).parse_next(input)?;
+            Some(schema.clone())
+        };
+        input.state.schema = schema;
+        (
+            multispace0,
+            expecting("|", || Expected::Literal("|")),
+            multispace0,
+        )
+            .void()
+            .parse_next(input)
     }
 }
 
-fn schema<'i, 'j, E: ParserError<Stream<'i>>>(
+fn schema<'i, 'j, E: QplParserError<'i>>(
     schemas: &'j HashMap<String, SqlSchema>,
 ) -> impl Parser<Stream<'i>, &'j SqlSchema, E> + 'j {
     move |input: &mut Stream<'i>| {
@@ -39,6 +216,120 @@ fn schema<'i, 'j, E: ParserError<Stream<'i>>>(
     }
 }
 
-fn special_token<'i, E: ParserError<Stream<'i>>>(input: &mut Stream<'i>) -> PResult<(), E> {
+fn special_token<'i, E: QplParserError<'i>>(input: &mut Stream<'i>) -> PResult<(), E> {
     ("<", alt(("pad", "s", "/s")), ">").void().parse_next(input)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schemas::concert_singer;
+    use winnow::error::ErrMode;
+
+    const QPL: &str = "concert_singer | #1 = Scan Table [ singer ] Output [ Singer_ID , Age ] ; #2 = Aggregate [ #1 ] GroupBy [ Singer_ID ] Output [ Singer_ID , MAX(Age) AS Max_Age ]";
+
+    fn schemas() -> HashMap<String, SqlSchema> {
+        let schema = concert_singer();
+        HashMap::from([(schema.db_id.clone(), schema)])
+    }
+
+    #[test]
+    fn test_parse_returns_ast_and_outputs() {
+        let source = QPL.split_once(" | ").unwrap().1;
+        let parsed = parse(source, &concert_singer(), Mode::ParseWithGuards).unwrap();
+        assert_eq!(parsed.qpl.len(), 2);
+        assert_eq!(
+            parsed.outputs.keys().copied().collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(parsed.outputs[&2].columns()[1].name(), "Max_Age");
+
+        let error = parse(QPL, &concert_singer(), Mode::ParseWithGuards).unwrap_err();
+        assert_eq!(error.offset(), 0);
+    }
+
+    #[test]
+    fn test_parse_partial_reports_status() {
+        let schemas = schemas();
+        let source = &QPL[..QPL.find("#2 = Agg").unwrap() + 4];
+        let mode = Mode::ParseWithGuards;
+        let (status, partial_parse) = parse_partial(source, &schemas, mode, false, None);
+        assert_eq!(status, PartialStatus::Incomplete);
+        let (status, _) = parse_partial(QPL, &schemas, mode, true, partial_parse.as_ref());
+        assert_eq!(status, PartialStatus::Complete);
+        let (status, _) = parse_partial(source, &schemas, mode, true, None);
+        assert_eq!(status, PartialStatus::Invalid);
+    }
+
+    #[test]
+    fn test_resumable_returns_last_line_boundary() {
+        let schemas = schemas();
+        let source = &QPL[..QPL.find("#2 = Agg").unwrap() + 4];
+        let (result, partial_parse) = resumable_prefixed_qpl::<()>(
+            &schemas,
+            Mode::ParseWithGuardsAndTypeChecks,
+            source,
+            false,
+            None,
+        );
+        assert!(matches!(result, Err(ErrMode::Incomplete(_))));
+        let partial_parse = partial_parse.unwrap();
+        assert!(partial_parse.consumed.ends_with(" ; "));
+        assert_eq!(partial_parse.env.state.current_idx, 1);
+        assert!(partial_parse.env.state.seen.contains(&1));
+    }
+
+    #[test]
+    fn test_resumable_resumes_from_line_boundary() {
+        let schemas = schemas();
+        let source = &QPL[..QPL.find("#2 = Agg").unwrap() + 4];
+        let (_, partial_parse) = resumable_prefixed_qpl::<()>(
+            &schemas,
+            Mode::ParseWithGuardsAndTypeChecks,
+            source,
+            false,
+            None,
+        );
+        let (result, next) = resumable_prefixed_qpl::<()>(
+            &schemas,
+            Mode::ParseWithGuardsAndTypeChecks,
+            QPL,
+            true,
+            partial_parse.as_ref(),
+        );
+        assert_eq!(result.unwrap().len(), 1);
+        assert!(next.is_none());
+
+        let invalid = QPL.replace("MAX(Age) AS Max_Age", "MAX(Age) AS Foo");
+        let (result, _) = resumable_prefixed_qpl::<()>(
+            &schemas,
+            Mode::ParseWithGuardsAndTypeChecks,
+            &invalid,
+            true,
+            partial_parse.as_ref(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_resumable_ignores_snapshot_of_other_prefix() {
+        let schemas = schemas();
+        let source = &QPL[..QPL.find("#2 = Agg").unwrap() + 4];
+        let (_, partial_parse) = resumable_prefixed_qpl::<()>(
+            &schemas,
+            Mode::ParseWithGuardsAndTypeChecks,
+            source,
+            false,
+            None,
+        );
+        let other = QPL.replace("Singer_ID , Age", "Age , Singer_ID");
+        let (result, _) = resumable_prefixed_qpl::<()>(
+            &schemas,
+            Mode::ParseWithGuardsAndTypeChecks,
+            &other,
+            true,
+            partial_parse.as_ref(),
+        );
+        assert_eq!(result.unwrap().len(), 2);
+    }
+}
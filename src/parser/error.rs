@@ -0,0 +1,342 @@
+use super::shared::Stream;
+use crate::domain::ColumnType;
+use std::fmt;
+use winnow::{
+    error::{AddContext, ErrMode, ErrorKind, ParserError},
+    stream::{Offset, Stream as _},
+    PResult, Parser,
+};
+
+/// Error bound shared by every QPL parser.
+///
+/// Lets `()` be used on the hot decoding path while `QplError` collects
+/// diagnostics for validation.
+pub(crate) trait QplParserError<'i>:
+    ParserError<Stream<'i>> + AddContext<Stream<'i>, QplContext>
+{
+}
+
+impl<'i, E> QplParserError<'i> for E where
+    E: ParserError<Stream<'i>> + AddContext<Stream<'i>, QplContext>
+{
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum QplContext {
+    Operator(&'static str),
+    Expected(Expected),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Expected {
+    Literal(&'static str),
+    DbId,
+    LineStart(usize),
+    Operator,
+    InputIds,
+    InputCount(usize),
+    TableName,
+    ColumnOfTable(String),
+    ColumnOfLine(usize),
+    ColumnOfLines(Vec<usize>),
+    ComparisonOp,
+    Value,
+    TypedValue(ColumnType),
+    OrderBy(usize),
+    Aggregate(usize),
+    Outputs,
+    EndOfQpl,
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Literal(s) => write!(f, "\"{}\"", s),
+            Expected::DbId => write!(f, "registered database id"),
+            Expected::LineStart(idx) => write!(f, "\"#{} = \"", idx),
+            Expected::Operator => write!(f, "operator"),
+            Expected::InputIds => write!(f, "ids of previous lines"),
+            Expected::InputCount(1) => write!(f, "a single input id"),
+            Expected::InputCount(n) => write!(f, "{} input ids", n),
+            Expected::TableName => write!(f, "table name"),
+            Expected::ColumnOfTable(table) => write!(f, "column of table `{}`", table),
+            Expected::ColumnOfLine(idx) => write!(f, "column of #{}", idx),
+            Expected::ColumnOfLines(idxs) => {
+                let idxs = idxs
+                    .iter()
+                    .map(|idx| format!("#{}", idx))
+                    .collect::<Vec<_>>()
+                    .join(" or ");
+                write!(f, "column of {}", idxs)
+            }
+            Expected::ComparisonOp => write!(f, "comparison operator"),
+            Expected::Value => write!(f, "literal or column"),
+            Expected::TypedValue(typ) => write!(f, "type {:?} literal", typ),
+            Expected::OrderBy(idx) => write!(f, "column of #{} with ASC or DESC", idx),
+            Expected::Aggregate(idx) => write!(f, "aggregate over a column of #{}", idx),
+            Expected::Outputs => write!(f, "distinct output columns of the inputs"),
+            Expected::EndOfQpl => write!(f, "\" ; \" or end of QPL"),
+        }
+    }
+}
+
+/// Diagnostic error keeping the failure that got the farthest into the input.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct QplError {
+    eof_offset: usize,
+    line: Option<usize>,
+    operator: Option<&'static str>,
+    expected: Vec<Expected>,
+}
+
+impl QplError {
+    pub(crate) fn offset(&self, source: &str) -> usize {
+        source.len().saturating_sub(self.eof_offset)
+    }
+
+    pub(crate) fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    pub(crate) fn operator(&self) -> Option<&'static str> {
+        self.operator
+    }
+
+    pub(crate) fn expected(&self) -> &[Expected] {
+        &self.expected
+    }
+}
+
+impl fmt::Display for QplError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(line) = self.line {
+            write!(f, "line #{}: ", line)?;
+        }
+        if let Some(operator) = self.operator {
+            write!(f, "in {}: ", operator)?;
+        }
+        if self.expected.is_empty() {
+            write!(f, "invalid QPL")
+        } else {
+            let expected = self
+                .expected
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            write!(f, "expected {}", expected)
+        }
+    }
+}
+
+/// Why a QPL was rejected and where.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    reason: String,
+    offset: usize,
+    line: Option<usize>,
+    operator: Option<String>,
+    expected: Vec<String>,
+}
+
+impl ParseError {
+    pub(crate) fn new(source: &str, error: &QplError) -> Self {
+        Self {
+            reason: error.to_string(),
+            offset: error.offset(source),
+            line: error.line(),
+            operator: error.operator().map(|op| op.to_owned()),
+            expected: error.expected().iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    pub(crate) fn incomplete(source: &str) -> Self {
+        Self {
+            reason: "Incomplete QPL".to_owned(),
+            offset: source.len(),
+            line: None,
+            operator: None,
+            expected: vec![],
+        }
+    }
+
+    /// Byte offset in the source where parsing failed.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Number of the line that failed, if the failure is within a line.
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+
+    /// Operator of the failing line, if parsing got as far as the operator.
+    pub fn operator(&self) -> Option<&str> {
+        self.operator.as_deref()
+    }
+
+    /// What would have been accepted at the failing offset.
+    pub fn expected(&self) -> &[String] {
+        &self.expected
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl<'i> ParserError<Stream<'i>> for QplError {
+    fn from_error_kind(input: &Stream<'i>, _kind: ErrorKind) -> Self {
+        let current_idx = input.state.state.current_idx;
+        Self {
+            eof_offset: input.eof_offset(),
+            line: (current_idx > 0).then_some(current_idx),
+            operator: None,
+            expected: vec![],
+        }
+    }
+
+    fn append(
+        self,
+        _input: &Stream<'i>,
+        _token_start: &<Stream<'i> as winnow::stream::Stream>::Checkpoint,
+        _kind: ErrorKind,
+    ) -> Self {
+        self
+    }
+
+    fn or(mut self, other: Self) -> Self {
+        match self.eof_offset.cmp(&other.eof_offset) {
+            std::cmp::Ordering::Less => self,
+            std::cmp::Ordering::Greater => other,
+            std::cmp::Ordering::Equal => {
+                if self.operator != other.operator {
+                    self.operator = None;
+                }
+                for expected in other.expected {
+                    if !self.expected.contains(&expected) {
+                        self.expected.push(expected);
+                    }
+                }
+                self
+            }
+        }
+    }
+}
+
+impl<'i> AddContext<Stream<'i>, QplContext> for QplError {
+    fn add_context(
+        mut self,
+        input: &Stream<'i>,
+        token_start: &<Stream<'i> as winnow::stream::Stream>::Checkpoint,
+        context: QplContext,
+    ) -> Self {
+        match context {
+            QplContext::Operator(operator) => {
+                self.operator.get_or_insert(operator);
+            }
+            QplContext::Expected(expected) if self.expected.is_empty() => {
+                // Report the failure where the expected token starts, not where it broke.
+                self.eof_offset = input.eof_offset() + input.offset_from(token_start);
+                if let Expected::LineStart(idx) = expected {
+                    self.line = Some(idx);
+                }
+                self.expected.push(expected);
+            }
+            QplContext::Expected(_) => {}
+        }
+        self
+    }
+}
+
+/// Tags failures of `parser` with what was expected, building the
+/// description only when the parser actually fails.
+pub(crate) fn expecting<'i, O, E: QplParserError<'i>>(
+    mut parser: impl Parser<Stream<'i>, O, E>,
+    expected: impl Fn() -> Expected,
+) -> impl Parser<Stream<'i>, O, E> {
+    move |input: &mut Stream<'i>| -> PResult<O, E> {
+        let start = input.checkpoint();
+        parser.parse_next(input).map_err(|e: ErrMode<E>| {
+            e.map(|e| e.add_context(input, &start, QplContext::Expected(expected())))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{qpl, shared::get_input, Mode};
+    use winnow::stream::StreamIsPartial;
+
+    fn parse_error(source: &str) -> QplError {
+        let mut input = get_input(source);
+        let _ = input.complete();
+        qpl::<QplError>(Mode::ParseWithGuardsAndTypeChecks)
+            .parse_next(&mut input)
+            .unwrap_err()
+            .into_inner()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_error_reports_unknown_column_of_table() {
+        let source = "#1 = Scan Table [ concert ] Output [ Stadium_ID , Location ]";
+        let error = parse_error(source);
+        assert_eq!(error.line(), Some(1));
+        assert_eq!(error.operator(), Some("Scan"));
+        assert_eq!(
+            error.expected(),
+            [Expected::ColumnOfTable("concert".to_owned())]
+        );
+        assert_eq!(error.offset(source), source.find("Location").unwrap());
+    }
+
+    #[test]
+    fn test_error_reports_type_mismatch() {
+        let source = "#1 = Scan Table [ concert ] Predicate [ Year >= '2014' ] Output [ Year ]";
+        let error = parse_error(source);
+        assert_eq!(error.line(), Some(1));
+        assert_eq!(error.operator(), Some("Scan"));
+        assert_eq!(error.expected(), [Expected::TypedValue(ColumnType::Number)]);
+        assert_eq!(error.offset(source), source.find("'2014'").unwrap());
+        assert_eq!(
+            error.to_string(),
+            "line #1: in Scan: expected type Number literal"
+        );
+    }
+
+    #[test]
+    fn test_error_reports_failing_line() {
+        let source = "#1 = Scan Table [ singer ] Output [ Age ] ; #2 = Aggregate [ #1 ] GroupBy [ Name ] Output [ countstar AS Count_Star ]";
+        let error = parse_error(source);
+        assert_eq!(error.line(), Some(2));
+        assert_eq!(error.operator(), Some("Aggregate"));
+        assert_eq!(error.expected(), [Expected::ColumnOfLine(1)]);
+        assert_eq!(error.offset(source), source.find("Name").unwrap());
+    }
+
+    #[test]
+    fn test_error_reports_unknown_operator() {
+        let source = "#1 = Scan Table [ singer ] Output [ Age ] ; #2 = Foo [ #1 ]";
+        let error = parse_error(source);
+        assert_eq!(error.line(), Some(2));
+        assert_eq!(error.operator(), None);
+        assert_eq!(error.expected(), [Expected::Operator]);
+        assert_eq!(error.offset(source), source.find("Foo").unwrap());
+    }
+
+    #[test]
+    fn test_error_reports_wrong_line_number() {
+        let source =
+            "#1 = Scan Table [ singer ] Output [ Age ] ; #3 = Top [ #1 ] Rows [ 1 ] Output [ Age ]";
+        let error = parse_error(source);
+        assert_eq!(error.line(), Some(2));
+        assert_eq!(error.expected(), [Expected::LineStart(2)]);
+        assert_eq!(error.offset(source), source.find("#3").unwrap());
+    }
+}
@@ -1,144 +1,66 @@
 use super::{
+    error::{expecting, Expected, QplParserError},
+    predicate::{predicate, predicate_wrapper, InputColumns},
     shared::{
-        boolean, get_table_from_indexed_outputs, indexed_column, input_ids, null, number,
-        predicate_wrapper, spaced_comparison_op, string, Stream,
+        get_table_from_indexed_outputs, indexed_output_columns, input_column, input_ids, Stream,
     },
     utils::has_duplicates,
+    Guards,
 };
-use crate::domain::{ColumnType, Comparable, Comparison, Operation, Predicate, Table};
+use crate::domain::{Operation, Table};
 use std::collections::{HashMap, HashSet};
 use winnow::{
     ascii::multispace0,
-    combinator::{alt, empty, fail, opt, separated},
-    error::ParserError,
+    combinator::{alt, cut_err, empty, fail, opt, separated},
     Parser,
 };
 
-pub(crate) fn intersect<'i, E: ParserError<Stream<'i>>>(
-    with_type_checking: bool,
+pub(crate) fn intersect<'i, E: QplParserError<'i>>(
+    guards: Guards,
 ) -> impl Parser<Stream<'i>, Operation, E> {
     move |input: &mut Stream<'i>| {
         "Intersect ".parse_next(input)?;
-        let inputs = input_ids.parse_next(input)?;
-        if inputs.len() != 2 {
-            return fail.parse_next(input);
-        }
-        let predicate =
-            opt(predicate_wrapper(predicate(with_type_checking, &inputs))).parse_next(input)?;
+        let inputs = input_ids(2, guards).parse_next(input)?;
+        let predicate = opt(predicate_wrapper(predicate(
+            InputColumns {
+                inputs: &inputs,
+                keyed_equality: false,
+            },
+            guards,
+        )))
+        .parse_next(input)?;
         let is_distinct =
             alt(("Distinct [ true ] ".value(true), empty.value(false))).parse_next(input)?;
         "Output [ ".parse_next(input)?;
         let outs_with_index = alt((
             "1 AS One".map(|x: &'i str| vec![(usize::MAX, x.to_owned())]),
-            separated(1.., indexed_column(&inputs), (multispace0, ", ")),
+            separated(
+                1..,
+                cut_err(input_column(&inputs, guards)),
+                (multispace0, ", "),
+            ),
         ))
         .parse_next(input)?;
-        let idx_to_table = &input.state.state.idx_to_table;
-        if !validate_output(&inputs, &outs_with_index, idx_to_table) {
-            return fail.parse_next(input);
+        let outputs = indexed_output_columns(&outs_with_index);
+        if guards != Guards::Off {
+            let idx_to_table = &input.state.state.idx_to_table;
+            if !validate_output(&inputs, &outs_with_index, idx_to_table) {
+                return expecting(fail, || Expected::Outputs).parse_next(input);
+            }
+            let output_table = get_table_from_indexed_outputs(outs_with_index).parse_next(input)?;
+            let state = &mut input.state.state;
+            state.idx_to_table.insert(state.current_idx, output_table);
         }
-        let output_table = get_table_from_indexed_outputs(outs_with_index).parse_next(input)?;
-        let state = &mut input.state.state;
-        state.idx_to_table.insert(state.current_idx, output_table);
         " ]".parse_next(input)?;
         Ok(Operation::Intersect {
             inputs,
             predicate,
             is_distinct,
+            outputs,
         })
     }
 }
 
-fn predicate<'i, 'j, E: ParserError<Stream<'i>>>(
-    with_type_checking: bool,
-    input_idxs: &'j [usize],
-) -> impl Parser<Stream<'i>, Predicate, E> + 'j {
-    move |input: &mut Stream<'i>| {
-        comparison(with_type_checking, input_idxs)
-            .map(|c| Predicate::Single { comparison: c })
-            .parse_next(input)
-    }
-}
-
-fn comparison<'i, 'j, E: ParserError<Stream<'i>>>(
-    with_type_checking: bool,
-    input_idxs: &'j [usize],
-) -> impl Parser<Stream<'i>, Comparison, E> + 'j {
-    move |input: &mut Stream<'i>| {
-        let (idx, column) = indexed_column(input_idxs).parse_next(input)?;
-        let op = spaced_comparison_op.parse_next(input)?;
-        if with_type_checking {
-            let idx_to_table = &input.state.state.idx_to_table;
-            let typ = idx_to_table[&idx].columns().iter().find_map(|c| {
-                if c.name() == column {
-                    Some(c.typ().clone())
-                } else {
-                    None
-                }
-            });
-            if typ.is_none() {
-                return fail.parse_next(input);
-            }
-            let typ = typ.unwrap();
-            let rhs = type_comparable(input_idxs, &typ).parse_next(input)?;
-            Ok(Comparison::from_string(
-                &op,
-                Comparable::Column(column),
-                rhs,
-            ))
-        } else {
-            let rhs = comparable(input_idxs).parse_next(input)?;
-            Ok(Comparison::from_string(
-                &op,
-                Comparable::Column(column),
-                rhs,
-            ))
-        }
-    }
-}
-
-fn comparable<'i, 'j, E: ParserError<Stream<'i>>>(
-    input_idxs: &'j [usize],
-) -> impl Parser<Stream<'i>, Comparable, E> + 'j {
-    move |input: &mut Stream<'i>| {
-        alt((
-            number,
-            boolean,
-            string,
-            null,
-            indexed_column(input_idxs).map(|(_, column)| Comparable::Column(column)),
-        ))
-        .parse_next(input)
-    }
-}
-
-fn type_comparable<'i, 'j, E: ParserError<Stream<'i>>>(
-    input_idxs: &'j [usize],
-    lhs_type: &'j ColumnType,
-) -> impl Parser<Stream<'i>, Comparable, E> + 'j {
-    move |input: &mut Stream<'i>| column_in_index_of_type(lhs_type, input_idxs).parse_next(input)
-}
-
-fn column_in_index_of_type<'i, 'j, E: ParserError<Stream<'i>>>(
-    typ: &'j ColumnType,
-    input_idxs: &'j [usize],
-) -> impl Parser<Stream<'i>, Comparable, E> + 'j {
-    move |input: &mut Stream<'i>| {
-        let (idx, column) = indexed_column(input_idxs).parse_next(input)?;
-        let table = &input.state.state.idx_to_table[&idx];
-        let is_column_in_table_of_type = table
-            .columns()
-            .iter()
-            .any(|c| c.name() == column && c.typ() == typ);
-
-        if is_column_in_table_of_type {
-            Ok(Comparable::Column(column))
-        } else {
-            fail.parse_next(input)
-        }
-    }
-}
-
 fn validate_output(
     inputs: &[usize],
     outs: &[(usize, String)],
@@ -146,7 +68,7 @@ fn validate_output(
 ) -> bool {
     let prev_columns = inputs
         .iter()
-        .map(|i| &idx_to_table[i])
+        .filter_map(|i| idx_to_table.get(i))
         .flat_map(|t| t.columns())
         .map(|c| c.name())
         .collect::<HashSet<_>>();
@@ -0,0 +1,61 @@
+//! Lexical check of a QPL: the text only has to split into QPL lexemes.
+
+use super::{
+    error::{expecting, Expected, QplParserError},
+    shared::{number, quoted_identifier, string, Stream},
+};
+use winnow::{
+    ascii::multispace0,
+    combinator::{alt, eof, preceded, repeat},
+    token::take_while,
+    PResult, Parser,
+};
+
+pub(crate) fn lexed_qpl<'i, E: QplParserError<'i>>(input: &mut Stream<'i>) -> PResult<(), E> {
+    let () = repeat(0.., preceded(multispace0, lexeme)).parse_next(input)?;
+    multispace0.parse_next(input)?;
+    expecting(eof, || Expected::EndOfQpl).parse_next(input)?;
+    Ok(())
+}
+
+fn lexeme<'i, E: QplParserError<'i>>(input: &mut Stream<'i>) -> PResult<(), E> {
+    alt((
+        ("<", alt(("pad", "s", "/s")), ">").void(),
+        number.void(),
+        string.void(),
+        quoted_identifier.void(),
+        take_while(1.., |c: char| c.is_alphanumeric() || c == '_').void(),
+        alt(("<>", "<=", ">=", "<", ">", "=")).void(),
+        alt(("#", ".", ",", ";", "|", "[", "]", "(", ")")).void(),
+        alt(("+", "-", "*", "/")).void(),
+    ))
+    .parse_next(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{error::QplError, shared::get_input};
+    use winnow::{error::ErrMode, stream::StreamIsPartial};
+
+    #[test]
+    fn test_lexed_qpl_accepts_unknown_names() {
+        let mut input = get_input("<pad> foo | #1 = Scan Table [ bar ] Predicate [ baz >= 2.5 AND qux = 'a b' ] Output [ #1.baz , \"Home \"\"Town\"\"\" ]");
+        let _ = input.complete();
+        assert!(lexed_qpl::<QplError>.parse_next(&mut input).is_ok());
+    }
+
+    #[test]
+    fn test_lexed_qpl_rejects_unknown_characters() {
+        let mut input = get_input("#1 = Scan Table [ bar ] Output [ ! ]");
+        let _ = input.complete();
+        assert!(lexed_qpl::<QplError>.parse_next(&mut input).is_err());
+    }
+
+    #[test]
+    fn test_lexed_qpl_partial_string() {
+        let mut input = get_input("#1 = Scan Table [ bar ] Predicate [ baz = 'a");
+        let result = lexed_qpl::<QplError>.parse_next(&mut input);
+        assert!(matches!(result, Err(ErrMode::Incomplete(_))));
+    }
+}
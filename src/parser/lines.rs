@@ -0,0 +1,166 @@
+//! Line-at-a-time parsing for interactive use, along with completions of the line being typed.
+
+use super::{
+    error::ParseError,
+    qpl_line,
+    shared::Stream,
+    suggest::{line_suggestions, Suggestion},
+    Mode,
+};
+use crate::domain::{Line, QplEnvironment, QplState, SqlSchema, Table};
+use winnow::{combinator::eof, stream::StreamIsPartial, Parser, Partial};
+
+/// Parses a QPL one line at a time, keeping the state between lines so that later lines can
+/// refer to the outputs of earlier ones.
+#[derive(Clone, Debug)]
+pub struct LineParser {
+    env: QplEnvironment,
+    mode: Mode,
+}
+
+impl LineParser {
+    pub fn new(schema: &SqlSchema, mode: Mode) -> Self {
+        Self {
+            env: QplEnvironment {
+                state: QplState::default(),
+                schema: Some(schema.clone()),
+            },
+            mode,
+        }
+    }
+
+    /// Number the next line starts with, as in `#<n> = `.
+    pub fn next_idx(&self) -> usize {
+        self.env.state.current_idx + 1
+    }
+
+    /// Parses `line`, which has to be exactly the next line. A rejected line leaves the
+    /// state as it was.
+    pub fn push(&mut self, line: &str) -> Result<Line, ParseError> {
+        let mut input = Stream {
+            input: Partial::new(line),
+            state: self.env.clone(),
+        };
+        let _ = input.complete();
+        match (qpl_line(self.mode), eof).parse_next(&mut input) {
+            Ok((line, _)) => {
+                self.env = input.state;
+                Ok(line)
+            }
+            Err(e) => Err(match e.into_inner() {
+                Some(error) => ParseError::new(line, &error),
+                None => ParseError::incomplete(line),
+            }),
+        }
+    }
+
+    /// Output table inferred for line `idx`. Only the modes with guards infer them.
+    pub fn output(&self, idx: usize) -> Option<&Table> {
+        self.env.state.idx_to_table.get(&idx)
+    }
+
+    /// Completions of the last word of `line`, the next line being typed, along with the
+    /// byte offset where that word starts.
+    ///
+    /// Completions are the texts of the [`Suggestion`]s for the line.
+    pub fn completions(&self, line: &str) -> (usize, Vec<String>) {
+        let (start, suggestions) = line_suggestions(&self.env, self.mode, line);
+        (
+            start,
+            suggestions.iter().filter_map(Suggestion::text).collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schemas::concert_singer;
+
+    fn parser() -> LineParser {
+        LineParser::new(&concert_singer(), Mode::ParseWithGuards)
+    }
+
+    #[test]
+    fn test_push_keeps_state_between_lines() {
+        let mut parser = parser();
+        assert_eq!(parser.next_idx(), 1);
+        let line = parser
+            .push("#1 = Scan Table [ singer ] Output [ Singer_ID , Age ]")
+            .unwrap();
+        assert_eq!(line.idx, 1);
+        assert_eq!(parser.output(1).unwrap().columns().len(), 2);
+
+        let error = parser
+            .push("#2 = Aggregate [ #1 ] Output [ MAX(Name) AS Max_Name ]")
+            .unwrap_err();
+        assert_eq!(error.line(), Some(2));
+        assert_eq!(parser.next_idx(), 2);
+
+        parser
+            .push("#2 = Aggregate [ #1 ] GroupBy [ Singer_ID ] Output [ Singer_ID , MAX(Age) AS Max_Age ]")
+            .unwrap();
+        assert_eq!(parser.output(2).unwrap().columns()[1].name(), "Max_Age");
+        assert_eq!(parser.next_idx(), 3);
+    }
+
+    #[test]
+    fn test_push_rejects_trailing_text() {
+        let mut parser = parser();
+        assert!(parser
+            .push("#1 = Scan Table [ singer ] Output [ Age ] ; ")
+            .is_err());
+        assert_eq!(parser.next_idx(), 1);
+    }
+
+    #[test]
+    fn test_completions_follow_grammar_and_guards() {
+        let mut parser = parser();
+        assert_eq!(parser.completions("#1 = Sc"), (5, vec!["Scan".to_owned()]));
+        assert_eq!(
+            parser.completions("#1 = Scan Table [ st").1,
+            vec!["stadium"]
+        );
+        assert_eq!(
+            parser.completions("#1 = Scan Table [ singer ] Output [ ").1,
+            vec![
+                "(",
+                "-",
+                "Age",
+                "Country",
+                "Is_male",
+                "Name",
+                "Singer_ID",
+                "Song_Name",
+                "Song_release_year"
+            ]
+        );
+        assert_eq!(
+            parser.completions("#1 = Scan Table [ singer ] O").1,
+            vec!["Output"]
+        );
+        assert_eq!(
+            parser
+                .completions("#1 = Scan Table [ singer ] Predicate [ A")
+                .1,
+            vec!["Age"]
+        );
+        assert!(parser
+            .completions("#1 = Scan Table [ singer ] Predicate [ Lo")
+            .1
+            .is_empty());
+
+        parser
+            .push("#1 = Scan Table [ singer ] Output [ Singer_ID , Age ]")
+            .unwrap();
+        assert_eq!(parser.completions("#2 = Aggregate [ ").1, vec!["#1"]);
+        assert_eq!(
+            parser.completions("#2 = Aggregate [ #1 ] Output [ MA").1,
+            vec!["MAX(Age) AS Max_Age", "MAX(Singer_ID) AS Max_Singer_ID"]
+        );
+        assert_eq!(
+            parser.completions("#2 = Aggregate [ #1 ] Output [ c").1,
+            vec!["countstar AS Count_Star"]
+        );
+    }
+}
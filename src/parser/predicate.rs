@@ -0,0 +1,760 @@
+//! Predicate grammar shared by every operator with a `Predicate [ ... ]`. The operators only
+//! differ in how a column is referred to, which a `ColumnResolver` decides.
+
+use super::{
+    error::{expecting, Expected, QplParserError},
+    shared::{
+        aliased_column, any_indexed_column, boolean, column_key, column_name, column_type, guarded,
+        identifier, null, number, schema_of, string, word_boundary, Stream,
+    },
+    Guards,
+};
+use crate::domain::*;
+use winnow::{
+    ascii::{dec_uint, multispace0, Caseless},
+    combinator::{alt, cut_err, delimited, fail, opt, separated, terminated},
+    error::ErrMode,
+    stream::Stream as _,
+    PResult, Parser,
+};
+
+/// How the columns of a predicate are written and what they stand for.
+pub(crate) trait ColumnResolver: Copy {
+    /// Reads a column, failing without saying what was expected.
+    fn column<'i, E: QplParserError<'i>>(&self, input: &mut Stream<'i>) -> PResult<Comparable, E>;
+
+    /// The column `column` refers to, with its type and keys.
+    fn describe(&self, input: &Stream<'_>, column: &Comparable) -> Option<Column>;
+
+    /// What is expected where a column is missing.
+    fn expected(&self) -> Expected;
+
+    /// Whether `=` between two columns has to follow a key, as in joins.
+    fn keyed_equality(&self) -> bool {
+        false
+    }
+
+    /// Whether a literal can be compared on the left too, as in `1 = Age`.
+    fn literal_lhs(&self) -> bool {
+        false
+    }
+}
+
+/// Any name, as in `Capacity` or `#2.Name`, without looking it up, for QPL without guards.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct AnyColumns;
+
+impl ColumnResolver for AnyColumns {
+    fn column<'i, E: QplParserError<'i>>(&self, input: &mut Stream<'i>) -> PResult<Comparable, E> {
+        alt((
+            any_indexed_column.map(|(idx, column)| Comparable::IndexedColumn(idx, column)),
+            identifier.map(Comparable::Column),
+        ))
+        .parse_next(input)
+    }
+
+    fn describe(&self, _input: &Stream<'_>, _column: &Comparable) -> Option<Column> {
+        None
+    }
+
+    fn expected(&self) -> Expected {
+        Expected::Value
+    }
+
+    fn literal_lhs(&self) -> bool {
+        true
+    }
+}
+
+/// Columns of a table of the schema, as in `Capacity`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TableColumns<'t>(pub(crate) &'t str);
+
+impl ColumnResolver for TableColumns<'_> {
+    fn column<'i, E: QplParserError<'i>>(&self, input: &mut Stream<'i>) -> PResult<Comparable, E> {
+        let column = column_name.parse_next(input)?;
+        if column_type(schema_of(input)?, self.0, &column).is_none() {
+            return fail.parse_next(input);
+        }
+        Ok(Comparable::Column(column))
+    }
+
+    fn describe(&self, input: &Stream<'_>, column: &Comparable) -> Option<Column> {
+        let Comparable::Column(name) = column else {
+            return None;
+        };
+        let schema = input.state.schema.as_ref()?;
+        Some(Column::Plain {
+            name: name.to_owned(),
+            typ: column_type(schema, self.0, name)?,
+            keys: column_key(schema, self.0, name),
+        })
+    }
+
+    fn expected(&self) -> Expected {
+        Expected::ColumnOfTable(self.0.to_owned())
+    }
+}
+
+/// Columns of the single input line, as in `Max_Age`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct LineColumns(pub(crate) usize);
+
+impl ColumnResolver for LineColumns {
+    fn column<'i, E: QplParserError<'i>>(&self, input: &mut Stream<'i>) -> PResult<Comparable, E> {
+        alt((
+            column_of_line(self.0, column_name),
+            column_of_line(self.0, aliased_column),
+        ))
+        .map(Comparable::Column)
+        .parse_next(input)
+    }
+
+    fn describe(&self, input: &Stream<'_>, column: &Comparable) -> Option<Column> {
+        let Comparable::Column(name) = column else {
+            return None;
+        };
+        line_column(input, self.0, name)
+    }
+
+    fn expected(&self) -> Expected {
+        Expected::ColumnOfLine(self.0)
+    }
+}
+
+/// Columns of either input line, as in `#2.Name`.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct InputColumns<'j> {
+    pub(crate) inputs: &'j [usize],
+    pub(crate) keyed_equality: bool,
+}
+
+impl ColumnResolver for InputColumns<'_> {
+    fn column<'i, E: QplParserError<'i>>(&self, input: &mut Stream<'i>) -> PResult<Comparable, E> {
+        "#".parse_next(input)?;
+        let idx = dec_uint.parse_next(input)?;
+        if !self.inputs.contains(&idx) {
+            return fail.parse_next(input);
+        }
+        ".".parse_next(input)?;
+        let column = alt((
+            column_of_line(idx, column_name),
+            column_of_line(idx, aliased_column),
+        ))
+        .parse_next(input)?;
+        Ok(Comparable::IndexedColumn(idx, column))
+    }
+
+    fn describe(&self, input: &Stream<'_>, column: &Comparable) -> Option<Column> {
+        let Comparable::IndexedColumn(idx, name) = column else {
+            return None;
+        };
+        line_column(input, *idx, name)
+    }
+
+    fn expected(&self) -> Expected {
+        Expected::ColumnOfLines(self.inputs.to_vec())
+    }
+
+    fn keyed_equality(&self) -> bool {
+        self.keyed_equality
+    }
+}
+
+/// Reads a column with `name`, as long as line `idx` outputs it.
+fn column_of_line<'i, E: QplParserError<'i>>(
+    idx: usize,
+    mut name: impl Parser<Stream<'i>, String, E>,
+) -> impl Parser<Stream<'i>, String, E> {
+    move |input: &mut Stream<'i>| {
+        let column = name.parse_next(input)?;
+        if line_column(input, idx, &column).is_none() {
+            return fail.parse_next(input);
+        }
+        Ok(column)
+    }
+}
+
+fn line_column(input: &Stream<'_>, idx: usize, name: &str) -> Option<Column> {
+    let table = input.state.state.idx_to_table.get(&idx)?;
+    table.columns().iter().find(|c| c.name() == name).cloned()
+}
+
+/// Predicate whose columns `resolver` reads, with values of the type of the column they are
+/// compared to under type guards. Without guards, its columns are any names.
+pub(crate) fn predicate<'i, E: QplParserError<'i>>(
+    resolver: impl ColumnResolver,
+    guards: Guards,
+) -> impl Parser<Stream<'i>, Predicate, E> {
+    guarded(
+        guards,
+        predicate_of(comparison(resolver, guards == Guards::Types)),
+        predicate_of(comparison(AnyColumns, false)),
+    )
+}
+
+fn comparison<'i, E: QplParserError<'i>>(
+    resolver: impl ColumnResolver,
+    with_type_checking: bool,
+) -> impl Parser<Stream<'i>, Comparison, E> {
+    move |input: &mut Stream<'i>| {
+        let expression =
+            opt(arithmetic(operand_of(resolver, with_type_checking))).parse_next(input)?;
+        let (lhs, column) = match expression {
+            Some(expression) => (Comparable::from(expression), None),
+            None => {
+                let lhs = expecting(
+                    |input: &mut Stream<'i>| {
+                        if resolver.literal_lhs() {
+                            if let Some(literal) = opt(literal).parse_next(input)? {
+                                return Ok(literal);
+                            }
+                        }
+                        resolver.column(input)
+                    },
+                    || resolver.expected(),
+                )
+                .parse_next(input)?;
+                let column = resolver.describe(input, &lhs);
+                if with_type_checking && column.is_none() {
+                    return fail.parse_next(input);
+                }
+                (lhs, column)
+            }
+        };
+        let op = spaced_comparison_op.parse_next(input)?;
+        if !with_type_checking {
+            let value = expecting(comparable(resolver), || Expected::Value);
+            return comparison_of(&op, lhs, value).parse_next(input);
+        }
+        // Expressions are always numbers, and keyless.
+        let typ = column
+            .as_ref()
+            .map_or(ColumnType::Number, |column| column.typ().clone());
+        match column {
+            Some(column @ Column::Plain { .. }) if op == "=" && resolver.keyed_equality() => {
+                let value = expecting(key_comparable(resolver, column), || {
+                    Expected::TypedValue(typ.clone())
+                });
+                let comparison = comparison_of(&op, lhs, value).parse_next(input)?;
+                Ok(comparison)
+            }
+            _ => {
+                let value = expecting(type_comparable(resolver, typ.clone()), || {
+                    Expected::TypedValue(typ.clone())
+                });
+                let comparison = comparison_of(&op, lhs, value).parse_next(input)?;
+                Ok(comparison)
+            }
+        }
+    }
+}
+
+fn comparable<'i, E: QplParserError<'i>>(
+    resolver: impl ColumnResolver,
+) -> impl Parser<Stream<'i>, Comparable, E> {
+    move |input: &mut Stream<'i>| {
+        alt((
+            arithmetic(operand_of(resolver, false)).map(Comparable::from),
+            literal,
+            |input: &mut Stream<'i>| resolver.column(input),
+        ))
+        .parse_next(input)
+    }
+}
+
+fn literal<'i, E: QplParserError<'i>>(input: &mut Stream<'i>) -> PResult<Comparable, E> {
+    alt((number, boolean, string, null)).parse_next(input)
+}
+
+fn type_comparable<'i, E: QplParserError<'i>>(
+    resolver: impl ColumnResolver,
+    lhs_type: ColumnType,
+) -> impl Parser<Stream<'i>, Comparable, E> {
+    use ColumnType::*;
+    move |input: &mut Stream<'i>| match lhs_type {
+        Number => alt((
+            arithmetic(operand_of(resolver, true)).map(Comparable::from),
+            number,
+            null,
+            column_of_type(resolver, Number),
+        ))
+        .parse_next(input),
+        Boolean => alt((boolean, null, column_of_type(resolver, Boolean))).parse_next(input),
+        Text => alt((string, null, column_of_type(resolver, Text))).parse_next(input),
+        Time => alt((string, null, column_of_type(resolver, Time))).parse_next(input),
+        Others => alt((
+            number,
+            boolean,
+            string,
+            null,
+            column_of_type(resolver, Others),
+        ))
+        .parse_next(input),
+    }
+}
+
+fn column_of_type<'i, E: QplParserError<'i>>(
+    resolver: impl ColumnResolver,
+    typ: ColumnType,
+) -> impl Parser<Stream<'i>, Comparable, E> {
+    move |input: &mut Stream<'i>| {
+        let column = resolver.column(input)?;
+        match resolver.describe(input, &column) {
+            Some(c) if *c.typ() == typ => Ok(column),
+            _ => fail.parse_next(input),
+        }
+    }
+}
+
+/// A column `lhs` can equal: an aliased column of the same type, or one on the other end of
+/// a key of `lhs`.
+fn key_comparable<'i, E: QplParserError<'i>>(
+    resolver: impl ColumnResolver,
+    lhs: Column,
+) -> impl Parser<Stream<'i>, Comparable, E> {
+    move |input: &mut Stream<'i>| {
+        let column = resolver.column(input)?;
+        match resolver.describe(input, &column) {
+            Some(rhs)
+                if rhs.typ() == lhs.typ() && (is_aliased(&rhs) || is_key_pair(&lhs, &rhs)) =>
+            {
+                Ok(column)
+            }
+            _ => fail.parse_next(input),
+        }
+    }
+}
+
+fn is_aliased(column: &Column) -> bool {
+    matches!(column, Column::Aliased { .. })
+}
+
+/// Whether `rhs` is a foreign key to the primary key `lhs`, or shares a referenced table with
+/// the foreign key `lhs`.
+fn is_key_pair(lhs: &Column, rhs: &Column) -> bool {
+    lhs.keys().iter().any(|key| match key {
+        KeyType::PrimaryKey { table } => rhs
+            .keys()
+            .iter()
+            .any(|k| matches!(k, KeyType::ForeignKey { table: t } if t == table)),
+        KeyType::ForeignKey { table } => rhs.keys().iter().any(|k| match k {
+            KeyType::PrimaryKey { table: t } | KeyType::ForeignKey { table: t } => t == table,
+        }),
+    })
+}
+
+/// A number or a column `resolver` reads, as an operand of arithmetic. Under type guards,
+/// only `Number` columns are operands, and without guards any name is.
+pub(crate) fn operand<'i, E: QplParserError<'i>>(
+    resolver: impl ColumnResolver,
+    guards: Guards,
+) -> impl Parser<Stream<'i>, Comparable, E> {
+    guarded(
+        guards,
+        operand_of(resolver, guards == Guards::Types),
+        operand_of(AnyColumns, false),
+    )
+}
+
+fn operand_of<'i, E: QplParserError<'i>>(
+    resolver: impl ColumnResolver,
+    with_type_checking: bool,
+) -> impl Parser<Stream<'i>, Comparable, E> {
+    move |input: &mut Stream<'i>| {
+        if with_type_checking {
+            alt((number, column_of_type(resolver, ColumnType::Number))).parse_next(input)
+        } else {
+            alt((number, |input: &mut Stream<'i>| resolver.column(input))).parse_next(input)
+        }
+    }
+}
+
+pub(crate) fn predicate_wrapper<'i, E: QplParserError<'i>>(
+    mut inner: impl Parser<Stream<'i>, Predicate, E>,
+) -> impl Parser<Stream<'i>, Predicate, E> {
+    move |input: &mut Stream<'i>| {
+        "Predicate [ ".parse_next(input)?;
+        let p = cut_err(inner.by_ref()).parse_next(input)?;
+        " ] ".parse_next(input)?;
+        Ok(p)
+    }
+}
+
+/// Predicate over the comparisons `comparison` parses, where `AND` binds tighter than `OR`,
+/// both fold left, and `( ... )` groups, as in `( A OR B ) AND C`.
+fn predicate_of<'i, E: QplParserError<'i>>(
+    mut comparison: impl Parser<Stream<'i>, Comparison, E>,
+) -> impl Parser<Stream<'i>, Predicate, E> {
+    move |input: &mut Stream<'i>| disjunction(&mut comparison, input)
+}
+
+fn disjunction<'i, E: QplParserError<'i>>(
+    comparison: &mut dyn Parser<Stream<'i>, Comparison, E>,
+    input: &mut Stream<'i>,
+) -> PResult<Predicate, E> {
+    let mut lhs = conjunction(comparison, input)?;
+    while opt(" OR ").parse_next(input)?.is_some() {
+        let rhs = conjunction(comparison, input)?;
+        lhs = Predicate::Or {
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        };
+    }
+    Ok(lhs)
+}
+
+fn conjunction<'i, E: QplParserError<'i>>(
+    comparison: &mut dyn Parser<Stream<'i>, Comparison, E>,
+    input: &mut Stream<'i>,
+) -> PResult<Predicate, E> {
+    let mut lhs = grouped_or_single(comparison, input)?;
+    while opt(" AND ").parse_next(input)?.is_some() {
+        let rhs = grouped_or_single(comparison, input)?;
+        lhs = Predicate::And {
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        };
+    }
+    Ok(lhs)
+}
+
+fn grouped_or_single<'i, E: QplParserError<'i>>(
+    comparison: &mut dyn Parser<Stream<'i>, Comparison, E>,
+    input: &mut Stream<'i>,
+) -> PResult<Predicate, E> {
+    let start = input.checkpoint();
+    if opt("( ").parse_next(input)?.is_some() {
+        let grouped = disjunction(comparison, input).and_then(|predicate| {
+            cut_err(expecting(" )", || Expected::Literal(" )"))).parse_next(input)?;
+            Ok(predicate)
+        });
+        match grouped {
+            Err(ErrMode::Backtrack(e) | ErrMode::Cut(e)) => {
+                // The parentheses may group arithmetic instead, as in `( A - B ) > 1`.
+                let end = input.checkpoint();
+                input.reset(&start);
+                return match comparison.parse_next(input) {
+                    Ok(comparison) => Ok(Predicate::Single { comparison }),
+                    Err(ErrMode::Incomplete(needed)) => Err(ErrMode::Incomplete(needed)),
+                    Err(ErrMode::Backtrack(_) | ErrMode::Cut(_)) => {
+                        input.reset(&end);
+                        Err(ErrMode::Cut(e))
+                    }
+                };
+            }
+            grouped => return grouped,
+        }
+    }
+    let comparison =
+        cut_err(|input: &mut Stream<'i>| comparison.parse_next(input)).parse_next(input)?;
+    Ok(Predicate::Single { comparison })
+}
+
+/// Arithmetic over the operands `operand` parses, where `*` and `/` bind tighter than `+` and
+/// `-`, all fold left, `- x` negates, `ABS(x)` and `ROUND(x)` apply a function and `( ... )`
+/// groups. A bare operand is no arithmetic, so it is left to the alternatives, and so are the
+/// errors: arithmetic that does not parse fails where it started, expecting nothing.
+pub(crate) fn arithmetic<'i, E: QplParserError<'i>>(
+    mut operand: impl Parser<Stream<'i>, Comparable, E>,
+) -> impl Parser<Stream<'i>, Expression, E> {
+    move |input: &mut Stream<'i>| {
+        let start = input.checkpoint();
+        match sum(&mut operand, input) {
+            Ok(Comparable::Expression(expression)) => Ok(*expression),
+            Ok(_) | Err(ErrMode::Backtrack(_)) => {
+                input.reset(&start);
+                fail.parse_next(input)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+fn sum<'i, E: QplParserError<'i>>(
+    operand: &mut dyn Parser<Stream<'i>, Comparable, E>,
+    input: &mut Stream<'i>,
+) -> PResult<Comparable, E> {
+    let mut lhs = product(operand, input)?;
+    let op = alt((
+        " + ".value(ArithmeticOp::Add),
+        " - ".value(ArithmeticOp::Subtract),
+    ));
+    let mut op = opt(op);
+    while let Some(op) = op.parse_next(input)? {
+        let rhs = product(operand, input)?;
+        lhs = Expression::Binary(op, lhs, rhs).into();
+    }
+    Ok(lhs)
+}
+
+fn product<'i, E: QplParserError<'i>>(
+    operand: &mut dyn Parser<Stream<'i>, Comparable, E>,
+    input: &mut Stream<'i>,
+) -> PResult<Comparable, E> {
+    let mut lhs = factor(operand, input)?;
+    let op = alt((
+        " * ".value(ArithmeticOp::Multiply),
+        " / ".value(ArithmeticOp::Divide),
+    ));
+    let mut op = opt(op);
+    while let Some(op) = op.parse_next(input)? {
+        let rhs = factor(operand, input)?;
+        lhs = Expression::Binary(op, lhs, rhs).into();
+    }
+    Ok(lhs)
+}
+
+fn factor<'i, E: QplParserError<'i>>(
+    operand: &mut dyn Parser<Stream<'i>, Comparable, E>,
+    input: &mut Stream<'i>,
+) -> PResult<Comparable, E> {
+    if opt("- ").parse_next(input)?.is_some() {
+        let operand = factor(operand, input)?;
+        return Ok(Expression::Negate(operand).into());
+    }
+    for function in NumericFunction::values() {
+        if opt((function.to_string().as_str(), "("))
+            .parse_next(input)?
+            .is_some()
+        {
+            let argument = sum(operand, input)?;
+            ")".parse_next(input)?;
+            return Ok(Expression::Function(function, argument).into());
+        }
+    }
+    if opt("( ").parse_next(input)?.is_some() {
+        let grouped = sum(operand, input)?;
+        " )".parse_next(input)?;
+        return Ok(grouped);
+    }
+    operand.parse_next(input)
+}
+
+/// Caseless `word`, which must end where a name would, as the `IS` of `Name IS Notes`.
+fn keyword<'i, E: QplParserError<'i>>(word: &'static str) -> impl Parser<Stream<'i>, (), E> {
+    terminated(Caseless(word), word_boundary).void()
+}
+
+pub(crate) fn comparison_op<'i, E: QplParserError<'i>>(
+    input: &mut Stream<'i>,
+) -> PResult<String, E> {
+    let op = expecting(
+        alt((
+            "<>",
+            "<=",
+            ">=",
+            keyword("is not").value("IS NOT"),
+            keyword("is").value("IS"),
+            keyword("in").value("IN"),
+            keyword("like").value("LIKE"),
+            keyword("not like").value("NOT LIKE"),
+            keyword("not in").value("NOT IN"),
+            keyword("between").value("BETWEEN"),
+            "<",
+            ">",
+            "=",
+        )),
+        || Expected::ComparisonOp,
+    )
+    .parse_next(input)?;
+    Ok(op.to_owned())
+}
+
+/// Parses the operands following `op` with `value` and builds the comparison: one value for
+/// most operators, `( v1 , v2 )` for `IN` and `NOT IN`, and `low AND high` for `BETWEEN`.
+fn comparison_of<'i, E: QplParserError<'i>>(
+    op: &str,
+    lhs: Comparable,
+    mut value: impl Parser<Stream<'i>, Comparable, E>,
+) -> impl Parser<Stream<'i>, Comparison, E> {
+    let op = op.to_owned();
+    move |input: &mut Stream<'i>| {
+        let lhs = lhs.clone();
+        match op.as_str() {
+            "IN" | "NOT IN" => {
+                expecting("( ", || Expected::Literal("( ")).parse_next(input)?;
+                let values =
+                    separated(1.., value.by_ref(), (multispace0, ", ")).parse_next(input)?;
+                expecting(" )", || Expected::Literal(" )")).parse_next(input)?;
+                if op == "IN" {
+                    Ok(Comparison::In(lhs, values))
+                } else {
+                    Ok(Comparison::NotIn(lhs, values))
+                }
+            }
+            "BETWEEN" => {
+                let low = value.parse_next(input)?;
+                expecting(" AND ", || Expected::Literal(" AND ")).parse_next(input)?;
+                let high = value.parse_next(input)?;
+                Ok(Comparison::Between(lhs, low, high))
+            }
+            op => {
+                let rhs = value.parse_next(input)?;
+                match Comparison::from_string(op, lhs, rhs) {
+                    Some(comparison) => Ok(comparison),
+                    None => expecting(fail, || Expected::ComparisonOp).parse_next(input),
+                }
+            }
+        }
+    }
+}
+
+fn spaced_comparison_op<'i, E: QplParserError<'i>>(input: &mut Stream<'i>) -> PResult<String, E> {
+    delimited(multispace0, comparison_op, multispace0).parse_next(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{error::QplError, shared::get_input};
+    use crate::{parse_partial, schemas::concert_singer, Mode, PartialStatus};
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_arithmetic_binds_products_tighter() {
+        let mut input = get_input("- Age + ABS(Age - 1) * 2 / ( Age + 3 ) ]");
+        let output = arithmetic::<QplError>(alt((number, column_name.map(Comparable::Column))))
+            .parse_next(&mut input)
+            .unwrap();
+        let age = || Comparable::Column("Age".to_owned());
+        assert_eq!(
+            output,
+            Expression::Binary(
+                ArithmeticOp::Add,
+                Expression::Negate(age()).into(),
+                Expression::Binary(
+                    ArithmeticOp::Divide,
+                    Expression::Binary(
+                        ArithmeticOp::Multiply,
+                        Expression::Function(
+                            NumericFunction::Abs,
+                            Expression::Binary(
+                                ArithmeticOp::Subtract,
+                                age(),
+                                Comparable::Number(1.0)
+                            )
+                            .into()
+                        )
+                        .into(),
+                        Comparable::Number(2.0)
+                    )
+                    .into(),
+                    Expression::Binary(ArithmeticOp::Add, age(), Comparable::Number(3.0)).into()
+                )
+                .into()
+            )
+        );
+        assert_eq!(*input.input, " ]");
+
+        // A bare operand is left to the alternatives.
+        let mut input = get_input("( Age ) ]");
+        let result =
+            arithmetic::<QplError>(column_name.map(Comparable::Column)).parse_next(&mut input);
+        assert!(result.is_err());
+        assert_eq!(*input.input, "( Age ) ]");
+    }
+
+    #[test]
+    fn test_partial_grouped_arithmetic_is_incomplete() {
+        let schemas = HashMap::from([("concert_singer".to_owned(), concert_singer())]);
+        let scan = "concert_singer | #1 = Scan Table [ stadium ] Predicate [ ";
+        for mode in [
+            Mode::ParseWithoutGuards,
+            Mode::ParseWithGuards,
+            Mode::ParseWithGuardsAndTypeChecks,
+        ] {
+            for prefix in [
+                "( Highest - Lo",
+                "( Highest - Lowest )",
+                "( Highest - Lowest ) ",
+                "( Highest - Lowest ) * 2 ",
+                "( Highest - Lowest ) >",
+            ] {
+                let source = format!("{scan}{prefix}");
+                let (status, _) = parse_partial(&source, &schemas, mode, false, None);
+                assert_eq!(status, PartialStatus::Incomplete, "{source}");
+            }
+            let source = format!("{scan}( Highest - Lowest ) * 2 > 100 ] Output [ Name ]");
+            let (status, _) = parse_partial(&source, &schemas, mode, true, None);
+            assert_eq!(status, PartialStatus::Complete);
+        }
+    }
+
+    #[test]
+    fn test_any_columns_are_not_looked_up() {
+        let mut input = get_input("1 = #2.Foo AND Bar IN ( 'x' , Baz ) ]");
+        let output = predicate::<QplError>(AnyColumns, Guards::Off)
+            .parse_next(&mut input)
+            .unwrap();
+        let column = |name: &str| Comparable::Column(name.to_owned());
+        assert_eq!(
+            output,
+            Predicate::And {
+                lhs: Predicate::Single {
+                    comparison: Comparison::Equal(
+                        Comparable::Number(1.0),
+                        Comparable::IndexedColumn(2, "Foo".to_owned())
+                    )
+                }
+                .into(),
+                rhs: Predicate::Single {
+                    comparison: Comparison::In(
+                        column("Bar"),
+                        vec![Comparable::Str("x".to_owned()), column("Baz")]
+                    )
+                }
+                .into(),
+            }
+        );
+        assert_eq!(*input.input, " ]");
+    }
+
+    #[test]
+    fn test_keyed_equality_follows_keys() {
+        let singer_id = |key| Column::Plain {
+            name: "Singer_ID".to_owned(),
+            typ: ColumnType::Number,
+            keys: vec![key],
+        };
+        let age = Column::Plain {
+            name: "Age".to_owned(),
+            typ: ColumnType::Number,
+            keys: vec![],
+        };
+        let tables = [
+            Table::Indexed {
+                idx: 1,
+                columns: vec![
+                    singer_id(KeyType::PrimaryKey {
+                        table: "singer".to_owned(),
+                    }),
+                    age,
+                ],
+            },
+            Table::Indexed {
+                idx: 2,
+                columns: vec![singer_id(KeyType::ForeignKey {
+                    table: "singer".to_owned(),
+                })],
+            },
+        ];
+        let parse = |text, keyed_equality| {
+            let mut input = get_input(text);
+            input.state.state.idx_to_table = (1..).zip(tables.clone()).collect();
+            let resolver = InputColumns {
+                inputs: &[1, 2],
+                keyed_equality,
+            };
+            predicate::<QplError>(resolver, Guards::Types)
+                .parse_next(&mut input)
+                .is_ok()
+        };
+
+        assert!(parse("#1.Singer_ID = #2.Singer_ID ]", true));
+        assert!(!parse("#1.Age = #2.Singer_ID ]", true));
+        assert!(parse("#1.Age = #2.Singer_ID ]", false));
+        // Only equality follows keys.
+        assert!(parse("#1.Age > #2.Singer_ID ]", true));
+    }
+}
@@ -1,180 +1,94 @@
 use super::{
+    error::{expecting, Expected, QplParserError},
+    predicate::{arithmetic, operand, predicate, predicate_wrapper, TableColumns},
     shared::{
-        boolean, column_in_table, column_key, column_name, column_type, null, number,
-        predicate_wrapper, spaced_comparison_op, string, table_name, Stream,
+        column_in_table, column_key, column_type, guarded, identifier, schema_of, table_name,
+        Stream,
     },
     utils::has_duplicates,
+    Guards,
 };
-use crate::domain::{
-    Column, ColumnType, Comparable, Comparison, Operation, Predicate, SqlSchema, Table,
-};
+use crate::domain::{Column, ColumnType, Operation, OutputColumn, SqlSchema, Table};
 use winnow::{
     ascii::multispace0,
-    combinator::{alt, empty, fail, opt, separated, separated_foldl1},
-    error::ParserError,
+    combinator::{alt, cut_err, empty, fail, opt, separated},
     Parser,
 };
 
-pub(crate) fn scan<'i, E: ParserError<Stream<'i>>>(
-    with_type_checking: bool,
+pub(crate) fn scan<'i, E: QplParserError<'i>>(
+    guards: Guards,
 ) -> impl Parser<Stream<'i>, Operation, E> {
     move |input: &mut Stream<'i>| {
         "Scan Table [ ".parse_next(input)?;
-        let table = table_name.parse_next(input)?;
+        let table = guarded(
+            guards,
+            table_name,
+            expecting(identifier, || Expected::TableName),
+        )
+        .parse_next(input)?;
         " ] ".parse_next(input)?;
         let predicate =
-            opt(predicate_wrapper(predicate(with_type_checking, &table))).parse_next(input)?;
+            opt(predicate_wrapper(predicate(TableColumns(&table), guards))).parse_next(input)?;
         let is_distinct =
             alt(("Distinct [ true ] ".value(true), empty.value(false))).parse_next(input)?;
         "Output [ ".parse_next(input)?;
-        let outs_with_aliases = alt((
-            "1 AS One".map(|x: &str| vec![(x.to_owned(), None)]),
-            separated(1.., column_in_table(&table), (multispace0, ", ")),
+        let any_column = (identifier, opt((" AS ", identifier)))
+            .map(|(name, alias)| (name, alias.map(|(_, alias)| alias)));
+        let output = alt((
+            (
+                arithmetic(operand(TableColumns(&table), guards)),
+                " AS ",
+                identifier,
+            )
+                .map(|(expression, _, alias)| OutputColumn::Expression { expression, alias }),
+            guarded(guards, column_in_table(&table), any_column)
+                .map(|(name, alias)| OutputColumn::Column { name, alias }),
+        ));
+        let outputs: Vec<OutputColumn> = alt((
+            "1 AS One".map(|_| vec![OutputColumn::One]),
+            separated(1.., cut_err(output), (multispace0, ", ")),
         ))
         .parse_next(input)?;
-        if has_duplicates(&outs_with_aliases) {
-            return fail.parse_next(input);
+        if guards != Guards::Off {
+            let names = outputs
+                .iter()
+                .map(|out| out.to_string())
+                .collect::<Vec<_>>();
+            if has_duplicates(&names) {
+                return expecting(fail, || Expected::Outputs).parse_next(input);
+            }
+            let schema = schema_of(input)?;
+            let output_table = get_output_table(schema, &table, &outputs);
+            let state = &mut input.state.state;
+            state.idx_to_table.insert(state.current_idx, output_table);
         }
-        let schema = &input.state.schema.as_ref().unwrap();
-        let output_table = get_output_table(schema, &table, &outs_with_aliases);
-        let state = &mut input.state.state;
-        state.idx_to_table.insert(state.current_idx, output_table);
         " ]".parse_next(input)?;
         Ok(Operation::Scan {
             table,
             predicate,
             is_distinct,
+            outputs,
         })
     }
 }
 
-fn predicate<'i, 't, E: ParserError<Stream<'i>>>(
-    with_type_checking: bool,
-    table: &'t str,
-) -> impl Parser<Stream<'i>, Predicate, E> + 't {
-    move |input: &mut Stream<'i>| {
-        separated_foldl1(
-            comparison(with_type_checking, table).map(|c| Predicate::Single { comparison: c }),
-            alt((" AND ", " OR ")),
-            |lhs, op, rhs| match op {
-                " AND " => Predicate::And {
-                    lhs: Box::new(lhs),
-                    rhs: Box::new(rhs),
-                },
-                " OR " => Predicate::Or {
-                    lhs: Box::new(lhs),
-                    rhs: Box::new(rhs),
-                },
-                _ => panic!("Invalid operation on predicates: {}", op),
-            },
-        )
-        .parse_next(input)
-    }
-}
-
-fn comparison<'i, 't, E: ParserError<Stream<'i>>>(
-    with_type_checking: bool,
-    table: &'t str,
-) -> impl Parser<Stream<'i>, Comparison, E> + 't {
-    move |input: &mut Stream<'i>| {
-        let (column, _) = column_in_table(table).parse_next(input)?;
-        let op = spaced_comparison_op.parse_next(input)?;
-        if with_type_checking {
-            let schema = &input.state.schema.as_ref().unwrap();
-            let typ = column_type(schema, table, &column);
-            if typ.is_none() {
-                return fail.parse_next(input);
-            }
-            let rhs = type_comparable(typ.unwrap(), table).parse_next(input)?;
-            Ok(Comparison::from_string(
-                &op,
-                Comparable::Column(column),
-                rhs,
-            ))
-        } else {
-            let rhs = comparable(table).parse_next(input)?;
-            Ok(Comparison::from_string(
-                &op,
-                Comparable::Column(column),
-                rhs,
-            ))
-        }
-    }
-}
-
-fn comparable<'i, 't, E: ParserError<Stream<'i>>>(
-    table: &'t str,
-) -> impl Parser<Stream<'i>, Comparable, E> + 't {
-    move |input: &mut Stream<'i>| {
-        alt((
-            number,
-            boolean,
-            string,
-            null,
-            column_in_table(table).map(|(column, _)| Comparable::Column(column)),
-        ))
-        .parse_next(input)
-    }
-}
-
-fn type_comparable<'i, 't, E: ParserError<Stream<'i>>>(
-    lhs_type: ColumnType,
-    table: &'t str,
-) -> impl Parser<Stream<'i>, Comparable, E> + 't {
-    use ColumnType::*;
-    move |input: &mut Stream<'i>| match lhs_type {
-        Number => alt((number, null, column_in_table_of_type(Number, table))).parse_next(input),
-        Boolean => alt((boolean, null, column_in_table_of_type(Boolean, table))).parse_next(input),
-        Text => alt((string, null, column_in_table_of_type(Text, table))).parse_next(input),
-        Time => alt((string, null, column_in_table_of_type(Time, table))).parse_next(input),
-        Others => alt((
-            number,
-            boolean,
-            string,
-            null,
-            column_in_table_of_type(Others, table),
-        ))
-        .parse_next(input),
-    }
-}
-
-fn column_in_table_of_type<'i, 't, E: ParserError<Stream<'i>>>(
-    typ: ColumnType,
-    table: &'t str,
-) -> impl Parser<Stream<'i>, Comparable, E> + 't {
-    move |input: &mut Stream<'i>| {
-        let column = column_name.parse_next(input)?;
-        let schema = &input.state.schema.as_ref().unwrap();
-        let ti = schema.table_names.iter().position(|t| t == table).unwrap();
-        let is_column_in_table_of_type = schema.column_names.iter().enumerate().any(|(i, cn)| {
-            *cn == column && schema.column_to_table[i] == ti && schema.column_types[i] == typ
-        });
-
-        if is_column_in_table_of_type {
-            Ok(Comparable::Column(column))
-        } else {
-            fail.parse_next(input)
-        }
-    }
-}
-
-fn get_output_table(schema: &SqlSchema, table: &str, outs: &[(String, Option<String>)]) -> Table {
+fn get_output_table(schema: &SqlSchema, table: &str, outs: &[OutputColumn]) -> Table {
     Table::Named {
         name: table.to_owned(),
         columns: outs
             .iter()
             .map(|out| match out {
-                (out, _) if out == "1 AS One" => Column::Dummy,
-                (out, Some(alias)) => Column::Plain {
-                    name: alias.to_owned(),
-                    typ: column_type(schema, table, out).unwrap().clone(),
-                    keys: column_key(schema, table, out),
+                OutputColumn::Column { name, alias } => Column::Plain {
+                    name: alias.as_ref().unwrap_or(name).to_owned(),
+                    typ: column_type(schema, table, name).unwrap().clone(),
+                    keys: column_key(schema, table, name),
                 },
-                (out, None) => Column::Plain {
-                    name: out.to_owned(),
-                    typ: column_type(schema, table, out).unwrap().clone(),
-                    keys: column_key(schema, table, out),
+                OutputColumn::Expression { alias, .. } => Column::Aliased {
+                    name: alias.to_owned(),
+                    typ: ColumnType::Number,
+                    keys: vec![],
                 },
+                _ => Column::Dummy,
             })
             .collect(),
     }
@@ -183,21 +97,28 @@ fn get_output_table(schema: &SqlSchema, table: &str, outs: &[(String, Option<Str
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::domain::Operation;
+    use crate::domain::{Comparable, Comparison, Operation, Predicate};
+    use crate::parser::error::QplError;
     use crate::parser::shared::get_input;
-    use winnow::{error::ContextError, stream::StreamIsPartial};
+    use winnow::stream::StreamIsPartial;
 
     #[test]
     fn test_scan_toy_example() {
         let mut input = get_input("Scan Table [ stadium ] Output [ Location ]");
         let _ = input.complete();
-        let output = scan::<ContextError>(true).parse_next(&mut input).unwrap();
+        let output = scan::<QplError>(Guards::Types)
+            .parse_next(&mut input)
+            .unwrap();
         assert_eq!(
             output,
             Operation::Scan {
                 table: "stadium".to_owned(),
                 predicate: None,
-                is_distinct: false
+                is_distinct: false,
+                outputs: vec![OutputColumn::Column {
+                    name: "Location".to_owned(),
+                    alias: None
+                }]
             }
         )
     }
@@ -208,7 +129,9 @@ mod tests {
             "Scan Table [ concert ] Predicate [ Year >= 2014 AND Year <= 2024 ] Distinct [ true ] Output [ Stadium_ID , Year ]",
         );
         let _ = input.complete();
-        let output = scan::<ContextError>(true).parse_next(&mut input).unwrap();
+        let output = scan::<QplError>(Guards::Types)
+            .parse_next(&mut input)
+            .unwrap();
         assert_eq!(
             output,
             Operation::Scan {
@@ -227,9 +150,69 @@ mod tests {
                         )
                     })
                 }),
-                is_distinct: true
+                is_distinct: true,
+                outputs: vec![
+                    OutputColumn::Column {
+                        name: "Stadium_ID".to_owned(),
+                        alias: None
+                    },
+                    OutputColumn::Column {
+                        name: "Year".to_owned(),
+                        alias: None
+                    }
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn test_scan_and_binds_tighter_than_or() {
+        let year = |op: fn(Comparable, Comparable) -> Comparison, year: f64| {
+            Box::new(Predicate::Single {
+                comparison: op(
+                    Comparable::Column("Year".to_owned()),
+                    Comparable::Number(year),
+                ),
+            })
+        };
+        let predicate = |source: &str| {
+            let source = format!("Scan Table [ concert ] Predicate [ {source} ] Output [ Year ]");
+            let mut input = get_input(&source);
+            let _ = input.complete();
+            let output = scan::<QplError>(Guards::Types).parse_next(&mut input);
+            match output {
+                Ok(Operation::Scan { predicate, .. }) => predicate,
+                result => panic!("{source} parsed as {result:?}"),
             }
+        };
+        assert_eq!(
+            predicate("Year = 2014 OR Year >= 2020 AND Year <= 2024"),
+            Some(Predicate::Or {
+                lhs: year(Comparison::Equal, 2014.0),
+                rhs: Box::new(Predicate::And {
+                    lhs: year(Comparison::GreaterThanOrEqual, 2020.0),
+                    rhs: year(Comparison::LessThanOrEqual, 2024.0),
+                }),
+            })
         );
+        assert_eq!(
+            predicate("( Year = 2014 OR Year >= 2020 ) AND Year <= 2024"),
+            Some(Predicate::And {
+                lhs: Box::new(Predicate::Or {
+                    lhs: year(Comparison::Equal, 2014.0),
+                    rhs: year(Comparison::GreaterThanOrEqual, 2020.0),
+                }),
+                rhs: year(Comparison::LessThanOrEqual, 2024.0),
+            })
+        );
+
+        let mut input = get_input(
+            "Scan Table [ concert ] Predicate [ ( Year = 2014 OR Year = 2015 ] Output [ Year ]",
+        );
+        let _ = input.complete();
+        assert!(scan::<QplError>(Guards::Types)
+            .parse_next(&mut input)
+            .is_err());
     }
 
     #[test]
@@ -238,13 +221,51 @@ mod tests {
             "Scan Table [ concert ] Predicate [ Year >= '2014' ] Output [ Stadium_ID , Year ]",
         );
         let _ = input.complete();
-        assert!(scan::<ContextError>(true).parse_next(&mut input).is_err());
+        assert!(scan::<QplError>(Guards::Types)
+            .parse_next(&mut input)
+            .is_err());
+    }
+
+    #[test]
+    fn test_scan_outputs_number_expressions() {
+        let source = "Scan Table [ stadium ] Predicate [ Highest - Lowest > 100 ] Output [ Name , Capacity / 2 AS Half ]";
+        let mut input = get_input(source);
+        let _ = input.complete();
+        let output = scan::<QplError>(Guards::Types)
+            .parse_next(&mut input)
+            .unwrap();
+        let Operation::Scan { outputs, .. } = output else {
+            panic!("expected a scan");
+        };
+        assert_eq!(outputs[1].to_string(), "Capacity / 2 AS Half");
+        let table = &input.state.state.idx_to_table[&0];
+        assert_eq!(table.columns()[1].name(), "Half");
+        assert_eq!(*table.columns()[1].typ(), ColumnType::Number);
+
+        // Only number columns take part in arithmetic, unless types are left unchecked.
+        for source in [
+            "Scan Table [ stadium ] Output [ Name * 2 AS Double ]",
+            "Scan Table [ stadium ] Predicate [ Capacity > Name - 1 ] Output [ Name ]",
+        ] {
+            let mut input = get_input(source);
+            let _ = input.complete();
+            assert!(scan::<QplError>(Guards::Types)
+                .parse_next(&mut input)
+                .is_err());
+            let mut input = get_input(source);
+            let _ = input.complete();
+            assert!(scan::<QplError>(Guards::Names)
+                .parse_next(&mut input)
+                .is_ok());
+        }
     }
 
     #[test]
     fn test_scan_fails_on_duplicate_outputs() {
         let mut input = get_input("Scan Table [ concert ] Output [ Stadium_ID , Stadium_ID ]");
         let _ = input.complete();
-        assert!(scan::<ContextError>(true).parse_next(&mut input).is_err());
+        assert!(scan::<QplError>(Guards::Types)
+            .parse_next(&mut input)
+            .is_err());
     }
 }
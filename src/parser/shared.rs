@@ -1,10 +1,14 @@
-use super::utils::*;
+use super::{
+    error::{expecting, Expected, QplParserError},
+    utils::*,
+    Guards,
+};
 use crate::domain::*;
 use winnow::{
-    ascii::{alphanumeric1, dec_uint, float, multispace0, Caseless},
-    combinator::{alt, delimited, fail, opt, separated},
-    error::ParserError,
-    token::take_while,
+    ascii::{dec_uint, digit0, digit1, multispace0, Caseless},
+    combinator::{alt, fail, not, opt, separated, terminated},
+    error::{ErrMode, ErrorKind, ParserError},
+    token::{one_of, take_while},
     PResult, Parser, Partial, Stateful,
 };
 
@@ -13,7 +17,19 @@ use crate::schemas::concert_singer;
 
 pub(crate) type Stream<'i> = Stateful<Partial<&'i str>, QplEnvironment>;
 
-pub(crate) fn choice<'i, E: ParserError<Stream<'i>>>(
+/// `parser` where names are looked up, otherwise `unguarded`, which reads any name.
+pub(crate) fn guarded<'i, O, E: QplParserError<'i>>(
+    guards: Guards,
+    mut parser: impl Parser<Stream<'i>, O, E>,
+    mut unguarded: impl Parser<Stream<'i>, O, E>,
+) -> impl Parser<Stream<'i>, O, E> {
+    move |input: &mut Stream<'i>| match guards {
+        Guards::Off => unguarded.parse_next(input),
+        Guards::Names | Guards::Types => parser.parse_next(input),
+    }
+}
+
+pub(crate) fn choice<'i, E: QplParserError<'i>>(
     choices: Vec<String>,
 ) -> impl Parser<Stream<'i>, String, E> {
     move |input: &mut Stream<'i>| {
@@ -26,29 +42,101 @@ pub(crate) fn choice<'i, E: ParserError<Stream<'i>>>(
     }
 }
 
-pub(crate) fn table_name<'i, E: ParserError<Stream<'i>>>(
+/// Name between double quotes, with its double quotes doubled, as in `"Home ""Town"""`.
+pub(crate) fn quoted_identifier<'i, E: QplParserError<'i>>(
     input: &mut Stream<'i>,
 ) -> PResult<String, E> {
-    let schema = &input.state.schema.as_ref().unwrap();
+    "\"".parse_next(input)?;
+    let mut name = String::new();
+    loop {
+        name.push_str(take_while(0.., |c| c != '"').parse_next(input)?);
+        "\"".parse_next(input)?;
+        if opt("\"").parse_next(input)?.is_none() {
+            return Ok(name);
+        }
+        name.push('"');
+    }
+}
+
+/// A new name, as in aliases: bare when it is a word, reserved or not, or quoted.
+pub(crate) fn identifier<'i, E: QplParserError<'i>>(input: &mut Stream<'i>) -> PResult<String, E> {
+    alt((
+        quoted_identifier,
+        take_while(1.., |c: char| c.is_alphanumeric() || c == '_')
+            .verify(|name: &str| is_word(name))
+            .map(ToOwned::to_owned),
+    ))
+    .parse_next(input)
+}
+
+/// A word of letters, digits and underscores, as in database ids.
+pub(crate) fn word<'i, E: QplParserError<'i>>(input: &mut Stream<'i>) -> PResult<String, E> {
+    take_while(1.., |c: char| c.is_alphanumeric() || c == '_')
+        .map(|s: &str| s.to_owned())
+        .parse_next(input)
+}
+
+/// One of `names`, bare or quoted and in any case, returned as `names` spells it. Names that
+/// are not words are only read quoted.
+pub(crate) fn name_of<'i, E: QplParserError<'i>>(
+    names: Vec<String>,
+) -> impl Parser<Stream<'i>, String, E> {
+    let bare = names.iter().filter(|name| is_word(name)).cloned().collect();
+    let mut bare = choice(bare);
+    move |input: &mut Stream<'i>| {
+        let Some(quoted) = opt(quoted_identifier).parse_next(input)? else {
+            return bare.parse_next(input);
+        };
+        match names.iter().find(|name| name.eq_ignore_ascii_case(&quoted)) {
+            Some(name) => Ok(name.to_owned()),
+            None => fail.parse_next(input),
+        }
+    }
+}
+
+/// Schema the QPL refers to, failing when no schema has been selected.
+pub(crate) fn schema_of<'a, 'i, E: QplParserError<'i>>(
+    input: &'a Stream<'i>,
+) -> PResult<&'a SqlSchema, E> {
+    input
+        .state
+        .schema
+        .as_ref()
+        .ok_or_else(|| ErrMode::from_error_kind(input, ErrorKind::Fail))
+}
+
+/// Output table of line `idx`, failing when that line has not been parsed.
+pub(crate) fn table_of<'a, 'i, E: QplParserError<'i>>(
+    input: &'a Stream<'i>,
+    idx: usize,
+) -> PResult<&'a Table, E> {
+    input
+        .state
+        .state
+        .idx_to_table
+        .get(&idx)
+        .ok_or_else(|| ErrMode::from_error_kind(input, ErrorKind::Fail))
+}
+
+pub(crate) fn table_name<'i, E: QplParserError<'i>>(input: &mut Stream<'i>) -> PResult<String, E> {
+    let schema = schema_of(input)?;
     let mut table_names = schema.table_names.clone();
 
     table_names.sort_unstable_by(|a, b| cmp_length_desc(a, b));
 
-    choice(table_names).parse_next(input)
+    expecting(name_of(table_names), || Expected::TableName).parse_next(input)
 }
 
-pub(crate) fn column_name<'i, E: ParserError<Stream<'i>>>(
-    input: &mut Stream<'i>,
-) -> PResult<String, E> {
-    let schema = &input.state.schema.as_ref().unwrap();
+pub(crate) fn column_name<'i, E: QplParserError<'i>>(input: &mut Stream<'i>) -> PResult<String, E> {
+    let schema = schema_of(input)?;
     let mut column_names = schema.column_names.clone();
 
     column_names.sort_unstable_by(|a, b| cmp_length_desc(a, b));
 
-    choice(column_names).parse_next(input)
+    name_of(column_names).parse_next(input)
 }
 
-pub(crate) fn aliased_column<'i, E: ParserError<Stream<'i>>>(
+pub(crate) fn aliased_column<'i, E: QplParserError<'i>>(
     input: &mut Stream<'i>,
 ) -> PResult<String, E> {
     let state = &input.state.state;
@@ -59,34 +147,37 @@ pub(crate) fn aliased_column<'i, E: ParserError<Stream<'i>>>(
             table
                 .columns()
                 .iter()
+                .filter(|c| matches!(c, Column::Aliased { .. }) || starts_with_agg(c.name()))
                 .map(|c| c.name().to_owned())
                 .collect::<Vec<_>>()
         })
-        .filter(|x| starts_with_agg(x))
         .collect::<Vec<_>>();
 
-    choice(previous_aliases).parse_next(input)
+    name_of(previous_aliases).parse_next(input)
 }
 
-pub(crate) fn column_in_table<'i, 't, E: ParserError<Stream<'i>>>(
+pub(crate) fn column_in_table<'i, 't, E: QplParserError<'i>>(
     table: &'t str,
 ) -> impl Parser<Stream<'i>, (String, Option<String>), E> + 't {
     move |input: &mut Stream<'i>| {
-        let column = column_name.parse_next(input)?;
-        let alias = opt((" AS ", alphanumeric1))
-            .map(|alias_opt| alias_opt.map(|(_, alias)| alias))
-            .parse_next(input)?;
-        let schema = &input.state.schema.as_ref().unwrap();
-        let t = schema.table_names.iter().position(|t| t == table).unwrap();
-        let is_column_in_table = schema.column_names.iter().enumerate().any(|(i, cn)| {
-            cn.to_lowercase() == column.to_lowercase() && schema.column_to_table[i] == t
-        });
-
-        if is_column_in_table {
-            Ok((column.to_owned(), alias.map(|s| s.to_owned())))
-        } else {
-            fail.parse_next(input)
-        }
+        let parser = |input: &mut Stream<'i>| -> PResult<(String, Option<String>), E> {
+            let column = column_name.parse_next(input)?;
+            let alias = opt((" AS ", identifier))
+                .map(|alias_opt| alias_opt.map(|(_, alias)| alias))
+                .parse_next(input)?;
+            let schema = schema_of(input)?;
+            let t = schema.table_names.iter().position(|t| t == table);
+            let is_column_in_table = schema.column_names.iter().enumerate().any(|(i, cn)| {
+                cn.to_lowercase() == column.to_lowercase() && Some(schema.column_to_table[i]) == t
+            });
+
+            if is_column_in_table {
+                Ok((column, alias))
+            } else {
+                fail.parse_next(input)
+            }
+        };
+        expecting(parser, || Expected::ColumnOfTable(table.to_owned())).parse_next(input)
     }
 }
 
@@ -95,11 +186,11 @@ pub(crate) enum ColumnParserType {
     Aliased,
 }
 
-pub(crate) fn column_in_index<'i, E: ParserError<Stream<'i>>>(
+pub(crate) fn column_in_index<'i, E: QplParserError<'i>>(
     idx: usize,
     column_parser_type: ColumnParserType,
 ) -> impl Parser<Stream<'i>, String, E> {
-    move |input: &mut Stream<'i>| {
+    let parser = move |input: &mut Stream<'i>| -> PResult<String, E> {
         let column = match column_parser_type {
             ColumnParserType::Named => column_name.parse_next(input)?,
             ColumnParserType::Aliased => aliased_column.parse_next(input)?,
@@ -120,98 +211,121 @@ pub(crate) fn column_in_index<'i, E: ParserError<Stream<'i>>>(
         } else {
             fail.parse_next(input)
         }
-    }
+    };
+    expecting(parser, move || Expected::ColumnOfLine(idx))
 }
 
-pub(crate) fn indexed_column<'i, 'j, E: ParserError<Stream<'i>>>(
+pub(crate) fn indexed_column<'i, 'j, E: QplParserError<'i>>(
     inputs: &'j [usize],
 ) -> impl Parser<Stream<'i>, (usize, String), E> + 'j {
     move |input: &mut Stream<'i>| {
-        "#".parse_next(input)?;
-        let idx = dec_uint.parse_next(input)?;
-        if !inputs.contains(&idx) {
-            return fail.parse_next(input);
-        }
-        ".".parse_next(input)?;
-        let column = alt((
-            column_in_index(idx, ColumnParserType::Named),
-            column_in_index(idx, ColumnParserType::Aliased),
-        ))
-        .parse_next(input)?;
-        Ok((idx, column))
+        let parser = |input: &mut Stream<'i>| -> PResult<(usize, String), E> {
+            "#".parse_next(input)?;
+            let idx = dec_uint.parse_next(input)?;
+            if !inputs.contains(&idx) {
+                return fail.parse_next(input);
+            }
+            ".".parse_next(input)?;
+            let column = alt((
+                column_in_index(idx, ColumnParserType::Named),
+                column_in_index(idx, ColumnParserType::Aliased),
+            ))
+            .parse_next(input)?;
+            Ok((idx, column))
+        };
+        expecting(parser, || Expected::ColumnOfLines(inputs.to_vec())).parse_next(input)
     }
 }
 
-pub(crate) fn comparison_op<'i, E: ParserError<Stream<'i>>>(
+/// Column of an input line, as in `#2.Name`, without looking either up.
+pub(crate) fn any_indexed_column<'i, E: QplParserError<'i>>(
     input: &mut Stream<'i>,
-) -> PResult<String, E> {
-    let op = alt((
-        "<>",
-        "<=",
-        ">=",
-        Caseless("is not").value("IS NOT"),
-        Caseless("is").value("IS"),
-        Caseless("like").value("LIKE"),
-        Caseless("not like").value("NOT LIKE"),
-        "<",
-        ">",
-        "=",
-    ))
-    .parse_next(input)?;
-    Ok(op.to_owned())
+) -> PResult<(usize, String), E> {
+    ("#", dec_uint, ".", identifier)
+        .map(|(_, idx, _, column)| (idx, column))
+        .parse_next(input)
 }
 
-pub(crate) fn number<'i, E: ParserError<Stream<'i>>>(
-    input: &mut Stream<'i>,
-) -> PResult<Comparable, E> {
-    float.parse_next(input).map(Comparable::Number)
+/// Column of one of `inputs`, as in `#2.Name`, or of any line without guards.
+pub(crate) fn input_column<'i, 'j, E: QplParserError<'i>>(
+    inputs: &'j [usize],
+    guards: Guards,
+) -> impl Parser<Stream<'i>, (usize, String), E> + 'j {
+    move |input: &mut Stream<'i>| match guards {
+        Guards::Off => any_indexed_column.parse_next(input),
+        Guards::Names | Guards::Types => indexed_column(inputs).parse_next(input),
+    }
 }
 
-pub(crate) fn string<'i, E: ParserError<Stream<'i>>>(
-    input: &mut Stream<'i>,
-) -> PResult<Comparable, E> {
+/// Column of a previous line, by its name or alias, or any name without guards.
+pub(crate) fn line_column_name<'i, E: QplParserError<'i>>(
+    guards: Guards,
+) -> impl Parser<Stream<'i>, String, E> {
+    guarded(guards, alt((column_name, aliased_column)), identifier)
+}
+
+/// Decimal literal, as in `-1.5e3`. Unlike `float`, `inf` and `nan` are not numbers, and
+/// neither is a literal too large to be finite, since SQL has neither.
+pub(crate) fn number<'i, E: QplParserError<'i>>(input: &mut Stream<'i>) -> PResult<Comparable, E> {
+    let sign = opt(one_of(['+', '-']));
+    let mantissa = alt(((digit1, opt((".", digit0))).void(), (".", digit1).void()));
+    let exponent = opt((one_of(['e', 'E']), opt(one_of(['+', '-'])), digit1));
+    let number = (sign, mantissa, exponent)
+        .recognize()
+        .verify_map(|literal: &str| literal.parse::<f64>().ok().filter(|n| n.is_finite()))
+        .map(Comparable::Number)
+        .parse_next(input)?;
+    word_boundary.parse_next(input)?;
+    Ok(number)
+}
+
+/// Fails when a name goes on, so that literals and keywords are not read off the start of a
+/// longer name, as `Inf` off `Info`.
+pub(crate) fn word_boundary<'i, E: QplParserError<'i>>(input: &mut Stream<'i>) -> PResult<(), E> {
+    not(one_of(|c: char| c.is_alphanumeric() || c == '_')).parse_next(input)
+}
+
+pub(crate) fn string<'i, E: QplParserError<'i>>(input: &mut Stream<'i>) -> PResult<Comparable, E> {
     "'".parse_next(input)?;
     let string = take_while(0.., |c| c != '\'').parse_next(input)?;
     "'".parse_next(input)?;
     Ok(Comparable::Str(string.to_owned()))
 }
 
-pub(crate) fn boolean<'i, E: ParserError<Stream<'i>>>(
-    input: &mut Stream<'i>,
-) -> PResult<Comparable, E> {
+pub(crate) fn boolean<'i, E: QplParserError<'i>>(input: &mut Stream<'i>) -> PResult<Comparable, E> {
     alt(("0".value(false), "1".value(true)))
         .map(Comparable::Boolean)
         .parse_next(input)
 }
 
-pub(crate) fn null<'i, E: ParserError<Stream<'i>>>(
-    input: &mut Stream<'i>,
-) -> PResult<Comparable, E> {
-    "NULL".value(Comparable::Null).parse_next(input)
-}
-
-pub(crate) fn input_ids<'i, E: ParserError<Stream<'i>>>(
-    input: &mut Stream<'i>,
-) -> PResult<Vec<usize>, E> {
-    "[ ".parse_next(input)?;
-    let single = ("#", dec_uint).map(|(_, id): (&str, usize)| id);
-    let ids: Vec<usize> = separated(1..=2, single, (multispace0, ", ")).parse_next(input)?;
-    let state = &input.state.state;
-    if !ids.iter().all(|id| state.seen.contains(id)) {
-        return fail.parse_next(input);
-    }
-    " ] ".parse_next(input)?;
-    Ok(ids)
+pub(crate) fn null<'i, E: QplParserError<'i>>(input: &mut Stream<'i>) -> PResult<Comparable, E> {
+    terminated("NULL", word_boundary)
+        .value(Comparable::Null)
+        .parse_next(input)
 }
 
-pub(crate) fn predicate_wrapper<'i, E: ParserError<Stream<'i>>>(
-    mut inner: impl Parser<Stream<'i>, Predicate, E>,
-) -> impl Parser<Stream<'i>, Predicate, E> {
-    move |input: &mut Stream<'i>| {
-        "Predicate [ ".parse_next(input)?;
-        let p = inner.parse_next(input)?;
+/// Ids of `count` input lines, which have to be previous lines under guards.
+pub(crate) fn input_ids<'i, E: QplParserError<'i>>(
+    count: usize,
+    guards: Guards,
+) -> impl Parser<Stream<'i>, Vec<usize>, E> {
+    let parser = move |input: &mut Stream<'i>| -> PResult<Vec<usize>, E> {
+        "[ ".parse_next(input)?;
+        let single = ("#", dec_uint).map(|(_, id): (&str, usize)| id);
+        let ids: Vec<usize> = separated(1..=2, single, (multispace0, ", ")).parse_next(input)?;
+        let state = &input.state.state;
+        if guards != Guards::Off && !ids.iter().all(|id| state.seen.contains(id)) {
+            return fail.parse_next(input);
+        }
         " ] ".parse_next(input)?;
-        Ok(p)
+        Ok(ids)
+    };
+    move |input: &mut Stream<'i>| {
+        let ids = expecting(parser, || Expected::InputIds).parse_next(input)?;
+        if ids.len() != count {
+            return expecting(fail, || Expected::InputCount(count)).parse_next(input);
+        }
+        Ok(ids)
     }
 }
 
@@ -229,7 +343,9 @@ pub(crate) fn column_type(schema: &SqlSchema, table: &str, column: &str) -> Opti
 }
 
 pub(crate) fn column_key(schema: &SqlSchema, table: &str, column: &str) -> Vec<KeyType> {
-    let t = schema.table_names.iter().position(|t| t == table).unwrap();
+    let Some(t) = schema.table_names.iter().position(|t| t == table) else {
+        return vec![];
+    };
     let c = schema
         .column_names
         .iter()
@@ -273,7 +389,7 @@ pub(crate) fn column_key(schema: &SqlSchema, table: &str, column: &str) -> Vec<K
     }
 }
 
-pub(crate) fn get_table_from_indexed_outputs<'i, E: ParserError<Stream<'i>>>(
+pub(crate) fn get_table_from_indexed_outputs<'i, E: QplParserError<'i>>(
     outs: Vec<(usize, String)>,
 ) -> impl Parser<Stream<'i>, Table, E> {
     move |input: &mut Stream<'i>| {
@@ -292,7 +408,9 @@ pub(crate) fn get_table_from_indexed_outputs<'i, E: ParserError<Stream<'i>>>(
                     typ: ColumnType::Number,
                     keys: vec![],
                 }),
-                (idx, out) => state.idx_to_table[&idx]
+                (idx, out) => state
+                    .idx_to_table
+                    .get(idx)?
                     .columns()
                     .iter()
                     .find(|c| c.name() == out)
@@ -305,12 +423,12 @@ pub(crate) fn get_table_from_indexed_outputs<'i, E: ParserError<Stream<'i>>>(
                 idx: state.current_idx,
                 columns: cs,
             }),
-            None => fail.parse_next(input),
+            None => expecting(fail, || Expected::Outputs).parse_next(input),
         }
     }
 }
 
-pub(crate) fn get_output<'i, E: ParserError<Stream<'i>>>(
+pub(crate) fn get_output<'i, E: QplParserError<'i>>(
     inputs: Vec<usize>,
     outs: Vec<String>,
 ) -> impl Parser<Stream<'i>, Table, E> {
@@ -318,7 +436,7 @@ pub(crate) fn get_output<'i, E: ParserError<Stream<'i>>>(
         let current_idx = input.state.state.current_idx;
         let prev = inputs
             .iter()
-            .map(|i| &input.state.state.idx_to_table[i])
+            .filter_map(|i| input.state.state.idx_to_table.get(i))
             .collect::<Vec<_>>();
         let columns = outs
             .iter()
@@ -348,34 +466,57 @@ pub(crate) fn get_output<'i, E: ParserError<Stream<'i>>>(
                 idx: current_idx,
                 columns: cs,
             }),
-            None => fail.parse_next(input),
+            None => expecting(fail, || Expected::Outputs).parse_next(input),
         }
     }
 }
 
-pub(crate) fn order_by<'i, E: ParserError<Stream<'i>>>(
+/// Output list of a line with a single input, from the names of its columns.
+pub(crate) fn output_columns(outs: &[String]) -> Vec<OutputColumn> {
+    outs.iter()
+        .map(|out| match out {
+            out if out == "1 AS One" => OutputColumn::One,
+            out => OutputColumn::Column {
+                name: out.to_owned(),
+                alias: None,
+            },
+        })
+        .collect()
+}
+
+/// Output list of a line with two inputs, from the inputs and names of its columns.
+pub(crate) fn indexed_output_columns(outs: &[(usize, String)]) -> Vec<OutputColumn> {
+    outs.iter()
+        .map(|out| match out {
+            (_, out) if out == "1 AS One" => OutputColumn::One,
+            (input, out) => OutputColumn::Indexed {
+                input: *input,
+                name: out.to_owned(),
+            },
+        })
+        .collect()
+}
+
+pub(crate) fn order_by<'i, E: QplParserError<'i>>(
     input_idx: usize,
+    guards: Guards,
 ) -> impl Parser<Stream<'i>, String, E> {
-    move |input: &mut Stream<'i>| {
-        let by = alt((aliased_column, column_name)).parse_next(input)?;
-        let state = &input.state.state;
-        let is_valid_column = state.idx_to_table[&input_idx]
-            .columns()
-            .iter()
-            .any(|c| c.name() == by);
+    let parser = move |input: &mut Stream<'i>| -> PResult<String, E> {
+        let by =
+            guarded(guards, alt((aliased_column, column_name)), identifier).parse_next(input)?;
+        let is_valid_column = guards == Guards::Off
+            || table_of(input, input_idx)?
+                .columns()
+                .iter()
+                .any(|c| c.name() == by);
         if !is_valid_column {
             return fail.parse_next(input);
         }
         multispace0.parse_next(input)?;
         let dir = alt(("ASC", "DESC")).parse_next(input)?;
         Ok(format!("{by} {dir}"))
-    }
-}
-
-pub(crate) fn spaced_comparison_op<'i, E: ParserError<Stream<'i>>>(
-    input: &mut Stream<'i>,
-) -> PResult<String, E> {
-    delimited(multispace0, comparison_op, multispace0).parse_next(input)
+    };
+    expecting(parser, move || Expected::OrderBy(input_idx))
 }
 
 #[cfg(test)]
@@ -392,23 +533,21 @@ pub(crate) fn get_input(input: &str) -> Stream<'_> {
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
-    use winnow::{
-        error::{ContextError, ErrMode},
-        stream::StreamIsPartial,
-    };
+    use crate::parser::error::QplError;
+    use winnow::{error::ErrMode, stream::StreamIsPartial};
 
     #[test]
     fn test_table_name_complete_table_exists() {
         let mut input = get_input("singer");
         let _ = input.complete();
-        let result = table_name::<ContextError>.parse_next(&mut input).unwrap();
+        let result = table_name::<QplError>.parse_next(&mut input).unwrap();
         assert_eq!(result, "singer")
     }
 
     #[test]
     fn test_table_name_partial_parse() {
         let mut input = get_input("sing");
-        match table_name::<ContextError>.parse_next(&mut input) {
+        match table_name::<QplError>.parse_next(&mut input) {
             Err(ErrMode::Incomplete(_)) => {}
             _ => panic!("\"sing\" should be a partial parse of the table \"singer\""),
         }
@@ -417,14 +556,29 @@ pub(crate) mod tests {
     #[test]
     fn test_table_name_table_does_not_exist() {
         let mut input = get_input("foobar");
-        assert!(table_name::<ContextError>.parse_next(&mut input).is_err());
+        assert!(table_name::<QplError>.parse_next(&mut input).is_err());
+    }
+
+    #[test]
+    fn test_number_is_a_finite_decimal_literal() {
+        for (literal, n) in [("-1.5e3 ", -1500.0), ("2. ", 2.0), (".5 ", 0.5)] {
+            let mut input = get_input(literal);
+            let output = number::<QplError>.parse_next(&mut input).unwrap();
+            assert_eq!(output, Comparable::Number(n));
+        }
+        for literal in ["inf ", "NaN ", "1e999 "] {
+            let mut input = get_input(literal);
+            assert!(number::<QplError>.parse_next(&mut input).is_err());
+        }
     }
 
     #[test]
     fn test_input_ids_one_id() {
         let mut input = get_input("[ #1 ] ");
         input.state.state.seen.insert(1);
-        let output = input_ids::<ContextError>.parse_next(&mut input).unwrap();
+        let output = input_ids::<QplError>(1, Guards::Names)
+            .parse_next(&mut input)
+            .unwrap();
         assert_eq!(output, vec![1]);
     }
 
@@ -433,20 +587,37 @@ pub(crate) mod tests {
         let mut input = get_input("[ #1, #2 ] ");
         input.state.state.seen.insert(1);
         input.state.state.seen.insert(2);
-        let output = input_ids::<ContextError>.parse_next(&mut input).unwrap();
+        let output = input_ids::<QplError>(2, Guards::Names)
+            .parse_next(&mut input)
+            .unwrap();
         assert_eq!(output, vec![1, 2]);
     }
 
     #[test]
     fn test_input_ids_fails_if_ids_not_seen() {
         let mut input = get_input("[ #1, #2 ] ");
-        assert!(input_ids::<ContextError>.parse_next(&mut input).is_err());
+        assert!(input_ids::<QplError>(2, Guards::Names)
+            .parse_next(&mut input)
+            .is_err());
+        let mut input = get_input("[ #1, #2 ] ");
+        let output = input_ids::<QplError>(2, Guards::Off)
+            .parse_next(&mut input)
+            .unwrap();
+        assert_eq!(output, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_input_ids_fails_on_wrong_count() {
+        let mut input = get_input("[ #1, #2 ] ");
+        assert!(input_ids::<QplError>(1, Guards::Off)
+            .parse_next(&mut input)
+            .is_err());
     }
 
     #[test]
     fn test_column_name_returns_original_column_name() {
         let mut input = get_input("stadium_id");
-        let output = column_name::<ContextError>.parse_next(&mut input).unwrap();
+        let output = column_name::<QplError>.parse_next(&mut input).unwrap();
         assert_eq!(output, "Stadium_ID");
     }
 
@@ -454,7 +625,7 @@ pub(crate) mod tests {
     fn test_column_in_table_returns_existing_column_without_alias() {
         let mut input = get_input("Stadium_ID");
         let _ = input.complete();
-        let (column, alias) = column_in_table::<ContextError>("stadium")
+        let (column, alias) = column_in_table::<QplError>("stadium")
             .parse_next(&mut input)
             .unwrap();
         assert_eq!(column, "Stadium_ID");
@@ -465,10 +636,37 @@ pub(crate) mod tests {
     fn test_column_in_table_returns_existing_column_with_alias() {
         let mut input = get_input("Stadium_ID AS sid");
         let _ = input.complete();
-        let (column, alias) = column_in_table::<ContextError>("stadium")
+        let (column, alias) = column_in_table::<QplError>("stadium")
             .parse_next(&mut input)
             .unwrap();
         assert_eq!(column, "Stadium_ID");
         assert_eq!(alias, Some("sid".to_owned()));
     }
+
+    #[test]
+    fn test_names_that_are_not_identifiers_are_quoted() {
+        let mut input = get_input("\"home town\" AS \"Birth \"\"Place\"\"\" ]");
+        let schema = input.state.schema.as_mut().unwrap();
+        schema.column_names[9] = "Home Town".to_owned();
+        let (column, alias) = column_in_table::<QplError>("singer")
+            .parse_next(&mut input)
+            .unwrap();
+        assert_eq!(column, "Home Town");
+        assert_eq!(alias, Some("Birth \"Place\"".to_owned()));
+
+        let mut input = get_input("\"Stadium_ID\" ");
+        let output = column_name::<QplError>.parse_next(&mut input).unwrap();
+        assert_eq!(output, "Stadium_ID");
+
+        // Reserved words are printed quoted, but read bare where a name is expected.
+        let mut input = get_input("Order ");
+        input.state.schema.as_mut().unwrap().column_names[12] = "Order".to_owned();
+        let output = column_name::<QplError>.parse_next(&mut input).unwrap();
+        assert_eq!(output, "Order");
+        let mut input = get_input("Age AS Desc ");
+        let (_, alias) = column_in_table::<QplError>("singer")
+            .parse_next(&mut input)
+            .unwrap();
+        assert_eq!(alias, Some("Desc".to_owned()));
+    }
 }
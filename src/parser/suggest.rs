@@ -0,0 +1,450 @@
+//! Valid continuations of a partial QPL, the string-level counterpart of token masking.
+
+use super::{
+    api::{qpl_prefix, resumable_prefixed_qpl},
+    qpl_line,
+    shared::Stream,
+    Mode, OPERATORS,
+};
+use crate::domain::{spelling, Agg, Column, QplEnvironment, SqlSchema};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use winnow::{combinator::eof, error::ErrMode, stream::StreamIsPartial, PResult, Parser, Partial};
+
+/// Grammar words other than the operators and comparison operators.
+const KEYWORDS: [&str; 20] = [
+    "Table",
+    "Predicate",
+    "Distinct",
+    "Output",
+    "GroupBy",
+    "OrderBy",
+    "Rows",
+    "WithTies",
+    "ExceptColumns",
+    "ASC",
+    "DESC",
+    "AND",
+    "OR",
+    "(",
+    ")",
+    "+",
+    "-",
+    "*",
+    "/",
+    "true",
+];
+
+const COMPARISON_OPS: [&str; 13] = [
+    "=", "<>", "<", ">", "<=", ">=", "IS", "IS NOT", "LIKE", "NOT LIKE", "IN", "NOT IN", "BETWEEN",
+];
+
+/// Tokens that can follow a table, column, aggregate or literal. A separator would not do,
+/// since some operators only check their columns once the list is closed.
+const AFTER_NAME: [&str; 6] = [" ]", " )", " = ", " + ", " AND ", " ASC"];
+
+/// Kind of literal a comparison accepts, following the type of the column it compares.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LiteralKind {
+    Number,
+    Text,
+    /// `0` or `1`, only suggested where numbers are not accepted.
+    Boolean,
+    Null,
+}
+
+impl LiteralKind {
+    const ALL: [LiteralKind; 4] = [Self::Number, Self::Text, Self::Boolean, Self::Null];
+
+    /// Literal of this kind checked in place of the ones it stands for.
+    fn sample(self) -> &'static str {
+        match self {
+            LiteralKind::Number => "2.5",
+            LiteralKind::Text => "'a'",
+            LiteralKind::Boolean => "1",
+            LiteralKind::Null => "NULL",
+        }
+    }
+}
+
+/// A continuation of a partial QPL.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case", tag = "kind", content = "value")]
+pub enum Suggestion {
+    Database(String),
+    Keyword(String),
+    /// Id of a previous line.
+    Reference(usize),
+    Table(String),
+    /// Column, qualified as `#<n>.<column>` in operators with two inputs.
+    Column(String),
+    /// Aggregate along with its alias, as in `MAX(Age) AS Max_Age`.
+    Aggregate(String),
+    ComparisonOp(String),
+    Literal(LiteralKind),
+}
+
+impl Suggestion {
+    /// Text to insert, except for literals standing for any value of their kind.
+    pub fn text(&self) -> Option<String> {
+        match self {
+            Suggestion::Database(text)
+            | Suggestion::Keyword(text)
+            | Suggestion::Table(text)
+            | Suggestion::Column(text)
+            | Suggestion::Aggregate(text)
+            | Suggestion::ComparisonOp(text) => Some(text.clone()),
+            Suggestion::Reference(idx) => Some(format!("#{idx}")),
+            Suggestion::Literal(LiteralKind::Null) => Some("NULL".to_owned()),
+            Suggestion::Literal(_) => None,
+        }
+    }
+}
+
+/// Continuations of a partial QPL at its end, see [`suggest`].
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Suggestions {
+    /// Byte offset of the word the suggestions complete, from which they replace the text.
+    pub start: usize,
+    pub suggestions: Vec<Suggestion>,
+}
+
+/// Suggests what can follow a prefixed QPL cut short at the cursor.
+///
+/// The last word, possibly empty, is completed by suggestions starting with it. Only
+/// continuations the grammar and, depending on `mode`, the guards and type checks accept
+/// are suggested, and none once the QPL cannot be completed anymore.
+pub fn suggest(source: &str, schemas: &HashMap<String, SqlSchema>, mode: Mode) -> Suggestions {
+    let (result, boundary) = resumable_prefixed_qpl::<()>(schemas, mode, source, false, None);
+    if matches!(result, Err(ErrMode::Backtrack(_) | ErrMode::Cut(_))) {
+        return Suggestions {
+            start: source.len(),
+            suggestions: vec![],
+        };
+    }
+    let Some(boundary) = boundary else {
+        return prefix_suggestions(source, schemas, mode);
+    };
+    let offset = boundary.consumed.len();
+    let line = &source[offset..];
+    let (start, mut suggestions) = line_suggestions(&boundary.env, mode, line);
+    let (before, word) = line.split_at(start);
+    if word.is_empty() && is_complete_line(&boundary.env, mode, before.trim_end()) {
+        suggestions.push(Suggestion::Keyword(";".to_owned()));
+    }
+    Suggestions {
+        start: offset + start,
+        suggestions,
+    }
+}
+
+/// Suggestions within the `<db_id> |` prefix.
+fn prefix_suggestions(
+    source: &str,
+    schemas: &HashMap<String, SqlSchema>,
+    mode: Mode,
+) -> Suggestions {
+    let start = word_start(source);
+    let (before, word) = source.split_at(start);
+    let candidates = schemas
+        .keys()
+        .map(|db_id| Suggestion::Database(db_id.clone()))
+        .chain([Suggestion::Keyword("|".to_owned())]);
+    let suggestions = candidates
+        .filter(|suggestion| suggestion.text().is_some_and(|text| text.starts_with(word)))
+        .filter(|suggestion| {
+            let text = format!("{before}{} ", suggestion.text().unwrap_or_default());
+            let mut input = Stream {
+                input: Partial::new(text.as_str()),
+                state: QplEnvironment {
+                    state: Default::default(),
+                    schema: None,
+                },
+            };
+            let result: PResult<(), ()> = qpl_prefix(schemas, mode).parse_next(&mut input);
+            matches!(result, Ok(_) | Err(ErrMode::Incomplete(_)))
+        })
+        .collect::<BTreeSet<_>>();
+    Suggestions {
+        start,
+        suggestions: suggestions.into_iter().collect(),
+    }
+}
+
+/// Suggestions completing the last word of `line`, the line being written after the lines
+/// `env` was left in by, along with the byte offset of that word.
+pub(crate) fn line_suggestions(
+    env: &QplEnvironment,
+    mode: Mode,
+    line: &str,
+) -> (usize, Vec<Suggestion>) {
+    let start = word_start(line);
+    let (before, word) = line.split_at(start);
+    let continues = |text: &str| continues(env, mode, &format!("{before}{text}"));
+    let accepts = |text: &str| {
+        AFTER_NAME
+            .iter()
+            .any(|after| continues(&format!("{text}{after}")))
+    };
+
+    let mut suggestions = BTreeSet::new();
+    let keywords = OPERATORS
+        .iter()
+        .map(|op| op.trim_end())
+        .chain(KEYWORDS)
+        .map(|keyword| Suggestion::Keyword(keyword.to_owned()));
+    let comparison_ops = COMPARISON_OPS
+        .iter()
+        .map(|op| Suggestion::ComparisonOp((*op).to_owned()));
+    // Only lines already parsed are referred to, so the grammar alone decides where
+    // references fit.
+    let references = env.state.seen.iter().map(|idx| Suggestion::Reference(*idx));
+    suggestions.extend(
+        keywords
+            .chain(comparison_ops)
+            .chain(references)
+            .filter(|suggestion| completes(suggestion, word))
+            .filter(|suggestion| continues(&format!("{} ", suggestion.text().unwrap_or_default()))),
+    );
+    suggestions.extend(
+        names(env)
+            .into_iter()
+            .filter(|suggestion| completes(suggestion, word))
+            .filter(|suggestion| accepts(&suggestion.text().unwrap_or_default())),
+    );
+
+    let literals = LiteralKind::ALL
+        .into_iter()
+        .filter(|kind| accepts(kind.sample()))
+        .collect::<Vec<_>>();
+    suggestions.extend(
+        literals
+            .iter()
+            // "1" also reads as a number.
+            .filter(|kind| {
+                **kind != LiteralKind::Boolean || !literals.contains(&LiteralKind::Number)
+            })
+            .map(|kind| Suggestion::Literal(*kind))
+            .filter(|suggestion| completes(suggestion, word)),
+    );
+    (start, suggestions.into_iter().collect())
+}
+
+/// Tables, columns and aggregates that could be mentioned after `env`.
+fn names(env: &QplEnvironment) -> BTreeSet<Suggestion> {
+    let mut names = BTreeSet::from([Suggestion::Aggregate("countstar AS Count_Star".to_owned())]);
+    if let Some(schema) = &env.schema {
+        let spelled = |name: &String| spelling(name).into_owned();
+        names.extend(
+            schema
+                .table_names
+                .iter()
+                .map(spelled)
+                .map(Suggestion::Table),
+        );
+        names.extend(
+            schema
+                .column_names
+                .iter()
+                .map(spelled)
+                .map(Suggestion::Column),
+        );
+    }
+    for (idx, table) in &env.state.idx_to_table {
+        for column in table.columns() {
+            let (Column::Plain { name, .. } | Column::Aliased { name, .. }) = column else {
+                continue;
+            };
+            let spelled = spelling(name);
+            names.insert(Suggestion::Column(spelled.clone().into_owned()));
+            names.insert(Suggestion::Column(format!("#{idx}.{spelled}")));
+            for agg in Agg::values() {
+                let upper = agg.to_string().to_uppercase();
+                let alias = spelling(&format!("{agg}_{name}")).into_owned();
+                names.insert(Suggestion::Aggregate(format!(
+                    "{upper}({spelled}) AS {alias}"
+                )));
+            }
+        }
+    }
+    names
+}
+
+/// Whether `suggestion` completes `word`. Literals without a text complete nothing but the
+/// empty word.
+fn completes(suggestion: &Suggestion, word: &str) -> bool {
+    suggestion
+        .text()
+        .map_or(word.is_empty(), |text| text.starts_with(word))
+}
+
+fn word_start(text: &str) -> usize {
+    text.rfind(char::is_whitespace).map_or(0, |i| {
+        i + text[i..].chars().next().map_or(1, char::len_utf8)
+    })
+}
+
+/// Whether `text` is a prefix of a valid line, or a whole one.
+fn continues(env: &QplEnvironment, mode: Mode, text: &str) -> bool {
+    let mut input = Stream {
+        input: Partial::new(text),
+        state: env.clone(),
+    };
+    match qpl_line::<()>(mode).parse_next(&mut input) {
+        Ok(_) => input.input.is_empty(),
+        Err(e) => e.is_incomplete(),
+    }
+}
+
+fn is_complete_line(env: &QplEnvironment, mode: Mode, text: &str) -> bool {
+    let mut input = Stream {
+        input: Partial::new(text),
+        state: env.clone(),
+    };
+    let _ = input.complete();
+    (qpl_line::<()>(mode), eof).parse_next(&mut input).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schemas::concert_singer;
+
+    const LINE: &str = "concert_singer | #1 = Scan Table [ singer ] Output [ Singer_ID , Age ]";
+
+    fn schemas() -> HashMap<String, SqlSchema> {
+        let schema = concert_singer();
+        HashMap::from([(schema.db_id.clone(), schema)])
+    }
+
+    fn suggestions(source: &str, mode: Mode) -> Vec<Suggestion> {
+        suggest(source, &schemas(), mode).suggestions
+    }
+
+    fn keyword(text: &str) -> Suggestion {
+        Suggestion::Keyword(text.to_owned())
+    }
+
+    #[test]
+    fn test_suggest_prefix_and_line_start() {
+        assert_eq!(
+            suggest("conc", &schemas(), Mode::ParseWithGuards),
+            Suggestions {
+                start: 0,
+                suggestions: vec![Suggestion::Database("concert_singer".to_owned())]
+            }
+        );
+        assert_eq!(
+            suggestions("concert_singer ", Mode::ParseWithGuards),
+            vec![keyword("|")]
+        );
+        assert_eq!(
+            suggestions("concert_singer | #1 = ", Mode::ParseWithGuards),
+            [
+                "Scan",
+                "Aggregate",
+                "Filter",
+                "Top",
+                "Sort",
+                "TopSort",
+                "Join",
+                "Intersect",
+                "Except",
+                "Union"
+            ]
+            .into_iter()
+            .map(keyword)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_suggest_references_and_separator() {
+        let source = format!("{LINE} ");
+        assert_eq!(
+            suggestions(&source, Mode::ParseWithGuards),
+            vec![keyword(";")]
+        );
+
+        let source = format!("{LINE} ; #2 = Aggregate [ ");
+        assert_eq!(
+            suggestions(&source, Mode::ParseWithGuards),
+            vec![Suggestion::Reference(1)]
+        );
+        let source = format!(
+            "{LINE} ; #2 = Aggregate [ #1 ] Output [ countstar AS Count_Star ] ; #3 = Join [ #1 , "
+        );
+        let suggested = suggestions(&source, Mode::ParseWithGuards);
+        assert_eq!(
+            suggested,
+            vec![Suggestion::Reference(1), Suggestion::Reference(2)]
+        );
+    }
+
+    #[test]
+    fn test_suggest_comparisons_and_typed_literals() {
+        let source = "concert_singer | #1 = Scan Table [ singer ] Predicate [ Age ";
+        let suggested = suggestions(source, Mode::ParseWithGuards);
+        assert!(suggested.contains(&Suggestion::ComparisonOp(">=".to_owned())));
+        assert!(suggested.contains(&Suggestion::ComparisonOp("IS NOT".to_owned())));
+        assert!(!suggested.contains(&keyword("Output")));
+
+        let source = "concert_singer | #1 = Scan Table [ singer ] Predicate [ Age = ";
+        let suggested = suggestions(source, Mode::ParseWithGuardsAndTypeChecks);
+        assert!(suggested.contains(&Suggestion::Literal(LiteralKind::Number)));
+        assert!(suggested.contains(&Suggestion::Literal(LiteralKind::Null)));
+        assert!(!suggested.contains(&Suggestion::Literal(LiteralKind::Text)));
+        assert!(suggested.contains(&Suggestion::Column("Singer_ID".to_owned())));
+        assert!(!suggested.contains(&Suggestion::Column("Name".to_owned())));
+
+        let source = "concert_singer | #1 = Scan Table [ singer ] Predicate [ Name = ";
+        let suggested = suggestions(source, Mode::ParseWithGuardsAndTypeChecks);
+        assert!(suggested.contains(&Suggestion::Literal(LiteralKind::Text)));
+        assert!(!suggested.contains(&Suggestion::Literal(LiteralKind::Number)));
+
+        let source = "concert_singer | #1 = Scan Table [ singer ] Predicate [ ";
+        assert!(suggestions(source, Mode::ParseWithGuards).contains(&keyword("(")));
+        let source = "concert_singer | #1 = Scan Table [ singer ] Predicate [ ( Name = 'Joe' ";
+        let suggested = suggestions(source, Mode::ParseWithGuards);
+        assert!(suggested.contains(&keyword(")")));
+        assert!(!suggested.contains(&keyword("Output")));
+        let source = "concert_singer | #1 = Scan Table [ singer ] Predicate [ ( Name = ";
+        let suggested = suggestions(source, Mode::ParseWithGuardsAndTypeChecks);
+        assert!(suggested.contains(&Suggestion::Literal(LiteralKind::Text)));
+
+        let source = "concert_singer | #1 = Scan Table [ singer ] Predicate [ Age IN ( 20 , ";
+        let suggested = suggestions(source, Mode::ParseWithGuardsAndTypeChecks);
+        assert!(suggested.contains(&Suggestion::Literal(LiteralKind::Number)));
+        assert!(!suggested.contains(&Suggestion::Literal(LiteralKind::Text)));
+        let source = "concert_singer | #1 = Scan Table [ singer ] Predicate [ Age BETWEEN ";
+        let suggested = suggestions(source, Mode::ParseWithGuardsAndTypeChecks);
+        assert!(suggested.contains(&Suggestion::Literal(LiteralKind::Number)));
+
+        // Arithmetic only goes on with number columns once types are checked.
+        let source = "concert_singer | #1 = Scan Table [ singer ] Predicate [ Age ";
+        let suggested = suggestions(source, Mode::ParseWithGuardsAndTypeChecks);
+        assert!(suggested.contains(&keyword("*")));
+        let source = "concert_singer | #1 = Scan Table [ singer ] Predicate [ Age - ";
+        let suggested = suggestions(source, Mode::ParseWithGuardsAndTypeChecks);
+        assert!(suggested.contains(&Suggestion::Column("Singer_ID".to_owned())));
+        assert!(!suggested.contains(&Suggestion::Column("Name".to_owned())));
+        let source = "concert_singer | #1 = Scan Table [ singer ] Predicate [ Name ";
+        let suggested = suggestions(source, Mode::ParseWithGuardsAndTypeChecks);
+        assert!(!suggested.contains(&keyword("+")));
+    }
+
+    #[test]
+    fn test_suggest_nothing_after_invalid_text() {
+        let source = "concert_singer | #1 = Scan Table [ singer ] Output [ Foo ] ";
+        assert_eq!(
+            suggest(source, &schemas(), Mode::ParseWithGuards),
+            Suggestions {
+                start: source.len(),
+                suggestions: vec![]
+            }
+        );
+    }
+}
@@ -1,36 +1,45 @@
 use super::{
-    shared::{get_table_from_indexed_outputs, indexed_column, input_ids, Stream},
+    error::{expecting, Expected, QplParserError},
+    shared::{
+        get_table_from_indexed_outputs, indexed_output_columns, input_column, input_ids, Stream,
+    },
     utils::has_duplicates,
+    Guards,
 };
 use crate::domain::{Operation, Table};
 use std::collections::{HashMap, HashSet};
 use winnow::{
     ascii::multispace0,
-    combinator::{fail, separated},
-    error::ParserError,
-    PResult, Parser,
+    combinator::{cut_err, fail, separated},
+    Parser,
 };
 
-pub(crate) fn union<'i, E: ParserError<Stream<'i>>>(
-    input: &mut Stream<'i>,
-) -> PResult<Operation, E> {
-    "Union ".parse_next(input)?;
-    let inputs = input_ids.parse_next(input)?;
-    if inputs.len() != 2 {
-        return fail.parse_next(input);
+pub(crate) fn union<'i, E: QplParserError<'i>>(
+    guards: Guards,
+) -> impl Parser<Stream<'i>, Operation, E> {
+    move |input: &mut Stream<'i>| {
+        "Union ".parse_next(input)?;
+        let inputs = input_ids(2, guards).parse_next(input)?;
+        "Output [ ".parse_next(input)?;
+        let outs_with_index: Vec<(usize, String)> = separated(
+            1..,
+            cut_err(input_column(&inputs, guards)),
+            (multispace0, ", "),
+        )
+        .parse_next(input)?;
+        let outputs = indexed_output_columns(&outs_with_index);
+        if guards != Guards::Off {
+            let idx_to_table = &input.state.state.idx_to_table;
+            if !validate_output(&inputs, &outs_with_index, idx_to_table) {
+                return expecting(fail, || Expected::Outputs).parse_next(input);
+            }
+            let output_table = get_table_from_indexed_outputs(outs_with_index).parse_next(input)?;
+            let state = &mut input.state.state;
+            state.idx_to_table.insert(state.current_idx, output_table);
+        }
+        " ]".parse_next(input)?;
+        Ok(Operation::Union { inputs, outputs })
     }
-    "Output [ ".parse_next(input)?;
-    let outs_with_index: Vec<(usize, String)> =
-        separated(1.., indexed_column(&inputs), (multispace0, ", ")).parse_next(input)?;
-    let idx_to_table = &input.state.state.idx_to_table;
-    if !validate_output(&inputs, &outs_with_index, idx_to_table) {
-        return fail.parse_next(input);
-    }
-    let output_table = get_table_from_indexed_outputs(outs_with_index).parse_next(input)?;
-    let state = &mut input.state.state;
-    state.idx_to_table.insert(state.current_idx, output_table);
-    " ]".parse_next(input)?;
-    Ok(Operation::Union { inputs })
 }
 
 fn validate_output(
@@ -40,7 +49,7 @@ fn validate_output(
 ) -> bool {
     let prev_columns = inputs
         .iter()
-        .map(|i| &idx_to_table[i])
+        .filter_map(|i| idx_to_table.get(i))
         .flat_map(|t| t.columns())
         .map(|c| c.name())
         .collect::<HashSet<_>>();
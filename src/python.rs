@@ -0,0 +1,240 @@
+//! Python extension module, for checking tokens in-process from a `generate` loop.
+
+use crate::{
+    cache::PartialParseCache, decoding, domain::SqlSchema, spider::SpiderSchema, Mode,
+    PartialStatus,
+};
+use pyo3::{exceptions::PyValueError, prelude::*};
+use std::{collections::HashMap, str::FromStr};
+use tokenizers::Tokenizer;
+
+const DEFAULT_TOKENIZER: &str = "default";
+
+/// Registered schemas and tokenizers, along with the parser snapshots of each tokenizer.
+#[pyclass]
+struct Picard {
+    schemas: HashMap<String, SqlSchema>,
+    tokenizers: HashMap<String, (Tokenizer, PartialParseCache)>,
+    mode: Mode,
+}
+
+#[pymethods]
+impl Picard {
+    #[new]
+    #[pyo3(signature = (mode = "parse_with_guards"))]
+    fn new(mode: &str) -> Result<Self, ArgumentError> {
+        Ok(Self {
+            schemas: HashMap::new(),
+            tokenizers: HashMap::new(),
+            mode: parse_mode(mode)?,
+        })
+    }
+
+    /// Registers a schema given as the JSON accepted by the server's `/schema` route.
+    fn register_schema(&mut self, schema_json: &str) -> Result<(), ArgumentError> {
+        let schema: SqlSchema = serde_json::from_str(schema_json).map_err(value_error)?;
+        schema.validate().map_err(ArgumentError)?;
+        self.add_schema(schema);
+        Ok(())
+    }
+
+    /// Registers every database of a Spider-style `tables.json`, returning their ids.
+    fn register_spider_schemas(&mut self, tables_json: &str) -> Result<Vec<String>, ArgumentError> {
+        let tables: Vec<SpiderSchema> = serde_json::from_str(tables_json).map_err(value_error)?;
+        let schemas = tables
+            .into_iter()
+            .map(SqlSchema::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(ArgumentError)?;
+        let db_ids = schemas.iter().map(|s| s.db_id.clone()).collect();
+        for schema in schemas {
+            self.add_schema(schema);
+        }
+        Ok(db_ids)
+    }
+
+    /// Registers a tokenizer given as its `tokenizer.json` contents.
+    #[pyo3(signature = (tokenizer_json, name = DEFAULT_TOKENIZER))]
+    fn register_tokenizer(
+        &mut self,
+        tokenizer_json: &str,
+        name: &str,
+    ) -> Result<(), ArgumentError> {
+        let tokenizer = Tokenizer::from_str(tokenizer_json).map_err(value_error)?;
+        self.tokenizers
+            .insert(name.to_owned(), (tokenizer, PartialParseCache::default()));
+        Ok(())
+    }
+
+    /// Appends each of `top_tokens[i]` to `input_ids[i]`, returning
+    /// `(batch_id, top_token, status)` for each, where status is one of `"complete"`,
+    /// `"partial"` and `"failure"`.
+    ///
+    /// The tokens are checked in parallel with the GIL released.
+    #[pyo3(signature = (input_ids, top_tokens, tokenizer = None, mode = None))]
+    fn batch_feed(
+        &self,
+        py: Python<'_>,
+        input_ids: Vec<Vec<u32>>,
+        top_tokens: Vec<Vec<u32>>,
+        tokenizer: Option<&str>,
+        mode: Option<&str>,
+    ) -> Result<Vec<(u32, u32, &'static str)>, ArgumentError> {
+        py.allow_threads(|| self.feed(&input_ids, &top_tokens, tokenizer, mode))
+    }
+}
+
+impl Picard {
+    /// Checks the tokens of `batch_feed`, without needing the GIL.
+    fn feed(
+        &self,
+        input_ids: &[Vec<u32>],
+        top_tokens: &[Vec<u32>],
+        tokenizer: Option<&str>,
+        mode: Option<&str>,
+    ) -> Result<Vec<(u32, u32, &'static str)>, ArgumentError> {
+        let name = tokenizer.unwrap_or(DEFAULT_TOKENIZER);
+        let (tokenizer, partial_parses) = self
+            .tokenizers
+            .get(name)
+            .ok_or_else(|| ArgumentError(format!("Tokenizer {name} not registered")))?;
+        let mode = mode.map(parse_mode).transpose()?.unwrap_or(self.mode);
+        let fed = decoding::batch_feed(
+            input_ids,
+            top_tokens,
+            tokenizer,
+            partial_parses,
+            &self.schemas,
+            mode,
+        )
+        .map_err(value_error)?;
+        Ok(fed
+            .into_iter()
+            .map(|fed| {
+                let status = match fed.status {
+                    PartialStatus::Complete => "complete",
+                    PartialStatus::Incomplete => "partial",
+                    PartialStatus::Invalid => "failure",
+                };
+                (fed.batch_id, fed.top_token, status)
+            })
+            .collect())
+    }
+
+    fn add_schema(&mut self, schema: SqlSchema) {
+        self.schemas.insert(schema.db_id.clone(), schema);
+        for (_, partial_parses) in self.tokenizers.values() {
+            partial_parses.clear();
+        }
+    }
+}
+
+fn parse_mode(mode: &str) -> Result<Mode, ArgumentError> {
+    serde_json::from_value(serde_json::Value::from(mode)).map_err(value_error)
+}
+
+fn value_error(e: impl ToString) -> ArgumentError {
+    ArgumentError(e.to_string())
+}
+
+/// Invalid argument, raised to Python as a `ValueError`.
+#[derive(Debug)]
+struct ArgumentError(String);
+
+impl From<ArgumentError> for PyErr {
+    fn from(e: ArgumentError) -> Self {
+        PyValueError::new_err(e.0)
+    }
+}
+
+#[pymodule]
+fn rusty_picard(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Picard>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schemas::concert_singer;
+    use serde_json::json;
+
+    const WORDS: [&str; 7] = ["concert_singer", "|", "#1", "=", "Scan", "Table", "["];
+
+    /// A word-level tokenizer without decoder, so decoding joins tokens with spaces.
+    fn word_level_tokenizer(words: &[&str]) -> String {
+        let vocab = words
+            .iter()
+            .zip(0..)
+            .map(|(word, id)| (word.to_string(), json!(id)));
+        json!({
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [],
+            "normalizer": null,
+            "pre_tokenizer": null,
+            "post_processor": null,
+            "decoder": null,
+            "model": {
+                "type": "WordLevel",
+                "vocab": vocab.collect::<serde_json::Map<_, _>>(),
+                "unk_token": words[0]
+            }
+        })
+        .to_string()
+    }
+
+    fn picard() -> Picard {
+        let mut picard = Picard::new("parse_with_guards").unwrap();
+        let schema = serde_json::to_string(&concert_singer()).unwrap();
+        picard.register_schema(&schema).unwrap();
+        picard
+            .register_tokenizer(&word_level_tokenizer(&WORDS), DEFAULT_TOKENIZER)
+            .unwrap();
+        picard
+    }
+
+    #[test]
+    fn test_register_rejects_invalid_input() {
+        let mut picard = picard();
+        assert!(Picard::new("fast").is_err());
+        assert!(picard.register_schema("{}").is_err());
+        assert!(picard.register_spider_schemas("[{}]").is_err());
+        assert!(picard.register_tokenizer("{}", "broken").is_err());
+        assert!(!picard.tokenizers.contains_key("broken"));
+    }
+
+    #[test]
+    fn test_register_spider_schemas_returns_db_ids() {
+        let tables = json!([{
+            "db_id": "pets",
+            "table_names_original": ["pet"],
+            "column_names_original": [[-1, "*"], [0, "Pet_ID"]],
+            "column_types": ["text", "number"],
+            "primary_keys": [1]
+        }]);
+        let mut picard = picard();
+        let db_ids = picard.register_spider_schemas(&tables.to_string()).unwrap();
+        assert_eq!(db_ids, ["pets"]);
+        assert!(picard.schemas.contains_key("pets"));
+        assert!(picard.schemas.contains_key("concert_singer"));
+    }
+
+    #[test]
+    fn test_feed_reports_status_of_each_token() {
+        let picard = picard();
+        let input_ids = vec![vec![0, 1, 2, 3, 4]];
+        let top_tokens = vec![vec![5, 0]];
+        assert_eq!(
+            picard.feed(&input_ids, &top_tokens, None, None).unwrap(),
+            [(0, 5, "partial"), (0, 0, "failure")]
+        );
+        assert!(picard
+            .feed(&input_ids, &top_tokens, Some("codellama"), None)
+            .is_err());
+        assert!(picard
+            .feed(&input_ids, &top_tokens, None, Some("fast"))
+            .is_err());
+        assert!(picard.feed(&input_ids, &[], None, None).is_err());
+    }
+}
@@ -1,7 +1,5 @@
-#[cfg(test)]
 use crate::domain::SqlSchema;
 
-#[cfg(test)]
 pub fn concert_singer() -> SqlSchema {
     use crate::domain::ColumnType::*;
     use std::collections::HashMap;
@@ -53,7 +51,7 @@ pub fn concert_singer() -> SqlSchema {
     let foreign_keys: Vec<(usize, usize)> = vec![(17, 0), (20, 7), (19, 14)];
     let primary_keys: Vec<usize> = vec![0, 7, 14, 19];
 
-    SqlSchema::new(
+    SqlSchema {
         db_id,
         table_names,
         column_names,
@@ -62,5 +60,5 @@ pub fn concert_singer() -> SqlSchema {
         table_to_columns,
         foreign_keys,
         primary_keys,
-    )
+    }
 }
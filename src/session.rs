@@ -0,0 +1,298 @@
+use crate::api::{ApiError, BeamFeedResult, BeamToken, FeedResult};
+use rayon::prelude::*;
+use rusty_picard::{Mode, PartialParse};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+use tokenizers::Tokenizer;
+
+/// How long a session is kept without being used.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// How many sessions can be open at once.
+const MAX_SESSIONS: usize = 1024;
+
+/// Open decoding sessions by id.
+///
+/// Ids are random, so that a client cannot guess the sessions of another. Sessions left idle
+/// for longer than the timeout are closed, and no more are opened past the limit.
+#[derive(Debug)]
+pub(crate) struct Sessions {
+    idle_timeout: Duration,
+    max_sessions: usize,
+    sessions: Mutex<HashMap<u64, OpenSession>>,
+}
+
+#[derive(Debug)]
+struct OpenSession {
+    session: Arc<Mutex<Session>>,
+    last_used: Instant,
+}
+
+impl Default for Sessions {
+    fn default() -> Self {
+        Self::new(IDLE_TIMEOUT, MAX_SESSIONS)
+    }
+}
+
+impl Sessions {
+    pub(crate) fn new(idle_timeout: Duration, max_sessions: usize) -> Self {
+        Self {
+            idle_timeout,
+            max_sessions,
+            sessions: Mutex::default(),
+        }
+    }
+
+    pub(crate) fn open(&self, session: Session) -> Result<u64, ApiError> {
+        let mut sessions = self.sessions();
+        let now = Instant::now();
+        sessions.retain(|_, open| now.duration_since(open.last_used) <= self.idle_timeout);
+        if sessions.len() >= self.max_sessions {
+            return Err(ApiError::TooManySessions(self.max_sessions));
+        }
+        let id = loop {
+            // 53 bits, which JSON clients reading numbers as doubles keep exact.
+            let id = rand::random::<u64>() >> 11;
+            if !sessions.contains_key(&id) {
+                break id;
+            }
+        };
+        let session = Arc::new(Mutex::new(session));
+        sessions.insert(
+            id,
+            OpenSession {
+                session,
+                last_used: now,
+            },
+        );
+        Ok(id)
+    }
+
+    pub(crate) fn get(&self, id: u64) -> Result<Arc<Mutex<Session>>, ApiError> {
+        let mut sessions = self.sessions();
+        let now = Instant::now();
+        match sessions.get_mut(&id) {
+            Some(open) if now.duration_since(open.last_used) <= self.idle_timeout => {
+                open.last_used = now;
+                Ok(open.session.clone())
+            }
+            Some(_) => {
+                sessions.remove(&id);
+                Err(ApiError::UnknownSession(id))
+            }
+            None => Err(ApiError::UnknownSession(id)),
+        }
+    }
+
+    pub(crate) fn close(&self, id: u64) -> Result<(), ApiError> {
+        self.sessions()
+            .remove(&id)
+            .map(|_| ())
+            .ok_or(ApiError::UnknownSession(id))
+    }
+
+    /// Drops the parser snapshots of every beam, which are parsed from scratch on their next
+    /// token.
+    pub(crate) fn clear_partial_parses(&self) {
+        for open in self.sessions().values() {
+            for beam in lock(&open.session).beams.values_mut() {
+                beam.resume_from = None;
+            }
+        }
+    }
+
+    fn sessions(&self) -> MutexGuard<'_, HashMap<u64, OpenSession>> {
+        self.sessions.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Beams are only replaced whole, so a panic while the lock was held leaves them consistent.
+pub(crate) fn lock(session: &Mutex<Session>) -> MutexGuard<'_, Session> {
+    session.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The beams of one beam search, each decoded and parsed incrementally as tokens arrive.
+#[derive(Debug)]
+pub(crate) struct Session {
+    pub(crate) tokenizer: String,
+    pub(crate) mode: Mode,
+    /// Text the generated tokens are parsed after, `<db_id> | ` when the model is not
+    /// expected to generate it.
+    prefix: String,
+    beams: HashMap<u32, Beam>,
+    next_beam_id: u32,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Beam {
+    input_ids: Vec<u32>,
+    decoded: String,
+    /// Tokens from `prefix_offset` are decoded again along with the new ones, so that the
+    /// tokenizer sees the context that decides spacing. Those up to `read_offset` are already
+    /// part of `decoded`.
+    prefix_offset: usize,
+    read_offset: usize,
+    resume_from: Option<Arc<PartialParse>>,
+}
+
+impl Session {
+    /// Opens a session with a single empty beam of id 0.
+    pub(crate) fn new(tokenizer: String, mode: Mode, db_id: Option<&str>) -> Self {
+        Self {
+            tokenizer,
+            mode,
+            prefix: db_id.map(|db_id| format!("{db_id} | ")).unwrap_or_default(),
+            beams: HashMap::from([(0, Beam::default())]),
+            next_beam_id: 1,
+        }
+    }
+
+    /// Copies a beam into a new one, returning the id of the copy.
+    pub(crate) fn fork(&mut self, beam_id: u32) -> Result<u32, ApiError> {
+        let beam = self.beam(beam_id)?.clone();
+        let forked_id = self.next_beam_id;
+        self.next_beam_id += 1;
+        self.beams.insert(forked_id, beam);
+        Ok(forked_id)
+    }
+
+    pub(crate) fn drop_beam(&mut self, beam_id: u32) -> Result<(), ApiError> {
+        self.beams
+            .remove(&beam_id)
+            .map(|_| ())
+            .ok_or(ApiError::UnknownBeam(beam_id))
+    }
+
+    /// Appends each token to its beam and reports whether the beam is still a valid QPL
+    /// prefix, according to `feed`.
+    ///
+    /// Beams are only updated once every token has been decoded, so a failing request leaves
+    /// the session as it was.
+    pub(crate) fn feed(
+        &mut self,
+        tokens: &[BeamToken],
+        tokenizer: &Tokenizer,
+        feed: impl Fn(&str, Option<&PartialParse>) -> (FeedResult, Option<PartialParse>) + Sync,
+    ) -> Result<Vec<BeamFeedResult>, ApiError> {
+        let mut seen = HashSet::new();
+        let mut fed = Vec::with_capacity(tokens.len());
+        for &BeamToken { beam_id, token } in tokens {
+            if !seen.insert(beam_id) {
+                return Err(ApiError::DuplicateBeam(beam_id));
+            }
+            fed.push((beam_id, token, self.beam(beam_id)?.clone()));
+        }
+
+        let prefix = &self.prefix;
+        let fed = fed
+            .into_par_iter()
+            .map(|(beam_id, token, mut beam)| {
+                beam.push(token, tokenizer)?;
+                let text = format!("{prefix}{}", beam.decoded);
+                let (feed_result, partial_parse) = feed(&text, beam.resume_from.as_deref());
+                if !matches!(feed_result, FeedResult::Failure) {
+                    if let Some(partial_parse) = partial_parse {
+                        beam.resume_from = Some(Arc::new(partial_parse));
+                    }
+                }
+                Ok((beam_id, beam, feed_result))
+            })
+            .collect::<Result<Vec<_>, ApiError>>()?;
+
+        let mut results = Vec::with_capacity(fed.len());
+        for (beam_id, beam, feed_result) in fed {
+            self.beams.insert(beam_id, beam);
+            results.push(BeamFeedResult {
+                beam_id,
+                feed_result,
+            });
+        }
+        Ok(results)
+    }
+
+    fn beam(&self, beam_id: u32) -> Result<&Beam, ApiError> {
+        self.beams
+            .get(&beam_id)
+            .ok_or(ApiError::UnknownBeam(beam_id))
+    }
+}
+
+impl Beam {
+    fn push(&mut self, token: u32, tokenizer: &Tokenizer) -> Result<(), ApiError> {
+        let decode = |ids: &[u32]| {
+            tokenizer
+                .decode(ids, false)
+                .map_err(|e| ApiError::Decode(e.to_string()))
+        };
+        self.input_ids.push(token);
+        let prefix_text = decode(&self.input_ids[self.prefix_offset..self.read_offset])?;
+        let new_text = decode(&self.input_ids[self.prefix_offset..])?;
+        match new_text.strip_prefix(&prefix_text) {
+            // Byte-fallback tokens can end in the middle of a character, wait for the rest.
+            Some(suffix) if suffix.is_empty() || suffix.ends_with('\u{FFFD}') => return Ok(()),
+            Some(suffix) => self.decoded.push_str(suffix),
+            // The new token changed how the context decodes, so start over from the full text.
+            None => self.decoded = decode(&self.input_ids)?,
+        }
+        self.prefix_offset = self.read_offset;
+        self.read_offset = self.input_ids.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_session_forks_and_drops_beams() {
+        let mut session = Session::new("default".to_owned(), Mode::default(), None);
+        assert_eq!(session.fork(0).unwrap(), 1);
+        assert_eq!(session.fork(1).unwrap(), 2);
+        session.drop_beam(1).unwrap();
+        assert!(matches!(session.fork(1), Err(ApiError::UnknownBeam(1))));
+        assert!(matches!(
+            session.drop_beam(1),
+            Err(ApiError::UnknownBeam(1))
+        ));
+        assert_eq!(session.fork(2).unwrap(), 3);
+    }
+
+    fn session() -> Session {
+        Session::new("default".to_owned(), Mode::default(), None)
+    }
+
+    #[test]
+    fn test_sessions_open_and_close() {
+        let sessions = Sessions::default();
+        let first = sessions.open(session()).unwrap();
+        let second = sessions.open(session()).unwrap();
+        assert_ne!(first, second);
+        sessions.close(first).unwrap();
+        assert!(sessions.get(first).is_err());
+        assert!(sessions.get(second).is_ok());
+        assert!(matches!(
+            sessions.close(first),
+            Err(ApiError::UnknownSession(_))
+        ));
+    }
+
+    #[test]
+    fn test_sessions_expire_and_are_limited() {
+        let sessions = Sessions::new(Duration::ZERO, 1);
+        let id = sessions.open(session()).unwrap();
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(matches!(sessions.get(id), Err(ApiError::UnknownSession(_))));
+
+        let sessions = Sessions::new(Duration::from_secs(60), 1);
+        let id = sessions.open(session()).unwrap();
+        assert!(matches!(
+            sessions.open(session()),
+            Err(ApiError::TooManySessions(1))
+        ));
+        sessions.close(id).unwrap();
+        assert!(sessions.open(session()).is_ok());
+    }
+}
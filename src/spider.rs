@@ -0,0 +1,186 @@
+use crate::domain::{ColumnType, SqlSchema};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// A database entry of a Spider-style `tables.json`.
+#[derive(Debug, Deserialize)]
+pub struct SpiderSchema {
+    db_id: String,
+    table_names_original: Vec<String>,
+    column_names_original: Vec<(i64, String)>,
+    column_types: Vec<ColumnType>,
+    #[serde(default)]
+    foreign_keys: Vec<(ColumnIds, ColumnIds)>,
+    #[serde(default)]
+    primary_keys: Vec<ColumnIds>,
+}
+
+/// Composite keys list their columns, single-column keys are a bare index.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ColumnIds {
+    Single(usize),
+    Composite(Vec<usize>),
+}
+
+impl ColumnIds {
+    fn to_vec(&self) -> Vec<usize> {
+        match self {
+            ColumnIds::Single(id) => vec![*id],
+            ColumnIds::Composite(ids) => ids.clone(),
+        }
+    }
+}
+
+impl TryFrom<SpiderSchema> for SqlSchema {
+    type Error = String;
+
+    fn try_from(spider: SpiderSchema) -> Result<Self, Self::Error> {
+        let SpiderSchema {
+            db_id,
+            table_names_original: table_names,
+            column_names_original,
+            column_types: spider_column_types,
+            foreign_keys: spider_foreign_keys,
+            primary_keys: spider_primary_keys,
+        } = spider;
+
+        if spider_column_types.len() != column_names_original.len() {
+            return Err(format!(
+                "{db_id}: {} column types for {} columns",
+                spider_column_types.len(),
+                column_names_original.len()
+            ));
+        }
+
+        // Spider lists `*` as a column of no table, which `SqlSchema` leaves out.
+        let mut column_ids = vec![None; column_names_original.len()];
+        let mut column_names = vec![];
+        let mut column_types = vec![];
+        let mut column_to_table = vec![];
+        let mut table_to_columns: HashMap<String, Vec<usize>> = table_names
+            .iter()
+            .map(|table| (table.clone(), vec![]))
+            .collect();
+        for (i, ((table_idx, name), typ)) in column_names_original
+            .into_iter()
+            .zip(spider_column_types)
+            .enumerate()
+        {
+            let Ok(t) = usize::try_from(table_idx) else {
+                continue;
+            };
+            let Some(table) = table_names.get(t) else {
+                return Err(format!("{db_id}: column {name} has unknown table {t}"));
+            };
+            let c = column_names.len();
+            column_ids[i] = Some(c);
+            column_names.push(name);
+            column_types.push(typ);
+            column_to_table.push(t);
+            table_to_columns.get_mut(table).unwrap().push(c);
+        }
+
+        let column_id = |id: usize| {
+            column_ids
+                .get(id)
+                .copied()
+                .flatten()
+                .ok_or_else(|| format!("{db_id}: key references unknown column {id}"))
+        };
+        let mut foreign_keys = vec![];
+        for (from, to) in spider_foreign_keys {
+            let (from, to) = (from.to_vec(), to.to_vec());
+            if from.len() != to.len() {
+                return Err(format!("{db_id}: foreign key {from:?} -> {to:?} is uneven"));
+            }
+            for (a, b) in from.into_iter().zip(to) {
+                foreign_keys.push((column_id(a)?, column_id(b)?));
+            }
+        }
+        let mut primary_keys = vec![];
+        for key in spider_primary_keys {
+            for id in key.to_vec() {
+                primary_keys.push(column_id(id)?);
+            }
+        }
+
+        Ok(SqlSchema {
+            db_id,
+            table_names,
+            column_names,
+            column_types,
+            column_to_table,
+            table_to_columns,
+            foreign_keys,
+            primary_keys,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schemas::concert_singer;
+
+    const CONCERT_SINGER: &str = r#"{
+        "db_id": "concert_singer",
+        "table_names_original": ["stadium", "singer", "concert", "singer_in_concert"],
+        "table_names": ["stadium", "singer", "concert", "singer in concert"],
+        "column_names_original": [
+            [-1, "*"], [0, "Stadium_ID"], [0, "Location"], [0, "Name"], [0, "Capacity"],
+            [0, "Highest"], [0, "Lowest"], [0, "Average"], [1, "Singer_ID"], [1, "Name"],
+            [1, "Country"], [1, "Song_Name"], [1, "Song_release_year"], [1, "Age"],
+            [1, "Is_male"], [2, "concert_ID"], [2, "concert_Name"], [2, "Theme"],
+            [2, "Stadium_ID"], [2, "Year"], [3, "concert_ID"], [3, "Singer_ID"]
+        ],
+        "column_types": [
+            "text", "number", "text", "text", "number", "number", "number", "number", "number",
+            "text", "text", "text", "text", "number", "others", "number", "text", "text",
+            "number", "number", "number", "number"
+        ],
+        "foreign_keys": [[18, 1], [21, 8], [20, 15]],
+        "primary_keys": [1, 8, 15, 20]
+    }"#;
+
+    #[test]
+    fn test_spider_schema_converts_to_sql_schema() {
+        let spider: SpiderSchema = serde_json::from_str(CONCERT_SINGER).unwrap();
+        let schema = SqlSchema::try_from(spider).unwrap();
+        assert_eq!(schema, concert_singer());
+    }
+
+    #[test]
+    fn test_spider_schema_composite_keys() {
+        let spider: SpiderSchema = serde_json::from_str(
+            r#"{
+                "db_id": "db",
+                "table_names_original": ["a", "b"],
+                "column_names_original": [[-1, "*"], [0, "x"], [0, "y"], [1, "x"], [1, "y"]],
+                "column_types": ["text", "number", "number", "number", "number"],
+                "foreign_keys": [[[3, 4], [1, 2]]],
+                "primary_keys": [[1, 2]]
+            }"#,
+        )
+        .unwrap();
+        let schema = SqlSchema::try_from(spider).unwrap();
+        assert_eq!(schema.foreign_keys, vec![(2, 0), (3, 1)]);
+        assert_eq!(schema.primary_keys, vec![0, 1]);
+        assert_eq!(schema.table_to_columns["b"], vec![2, 3]);
+    }
+
+    #[test]
+    fn test_spider_schema_rejects_unknown_key_column() {
+        let spider: SpiderSchema = serde_json::from_str(
+            r#"{
+                "db_id": "db",
+                "table_names_original": ["a"],
+                "column_names_original": [[-1, "*"], [0, "x"]],
+                "column_types": ["text", "number"],
+                "primary_keys": [0]
+            }"#,
+        )
+        .unwrap();
+        assert!(SqlSchema::try_from(spider).is_err());
+    }
+}
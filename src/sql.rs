@@ -0,0 +1,513 @@
+//! Compiles a parsed QPL into an equivalent SQLite query, so that generated QPL can be run
+//! against the Spider databases and compared with the gold SQL. [`from_sql`] goes the other
+//! way, for the `SELECT`s of Spider.
+//!
+//! Every line but the last becomes a common table expression named after the line, as in
+//! `"#2"`, and the last line is the main query.
+
+mod convert;
+
+pub use convert::{from_sql, ConvertError};
+
+use crate::domain::{
+    Comparable, Comparison, ExceptOperator, Expression, Line, Operation, OutputColumn, Predicate,
+    Qpl,
+};
+use std::{collections::HashSet, fmt};
+
+#[derive(Debug, PartialEq)]
+pub enum SqlError {
+    EmptyQpl,
+    InputCount {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// An `Except` outputting columns of the input whose rows it excludes.
+    ExcludedOutputs {
+        line: usize,
+    },
+}
+
+impl fmt::Display for SqlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SqlError::EmptyQpl => write!(f, "A QPL without lines has no SQL"),
+            SqlError::InputCount {
+                line,
+                expected,
+                found,
+            } => write!(f, "Line #{line} has {found} inputs instead of {expected}"),
+            SqlError::ExcludedOutputs { line } => write!(
+                f,
+                "Line #{line} outputs columns of the input whose rows it excludes"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SqlError {}
+
+/// SQLite query returning the rows of the last line of `qpl`.
+pub fn to_sql(qpl: &Qpl) -> Result<String, SqlError> {
+    let Some((last, lines)) = qpl.split_last() else {
+        return Err(SqlError::EmptyQpl);
+    };
+    let ctes = lines
+        .iter()
+        .map(|line| Ok(format!("{} AS ({})", line_table(line.idx), select(line)?)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let query = select(last)?;
+    if ctes.is_empty() {
+        Ok(query)
+    } else {
+        Ok(format!("WITH {} {query}", ctes.join(", ")))
+    }
+}
+
+pub(crate) fn select(line: &Line) -> Result<String, SqlError> {
+    let outputs = line.operation.outputs();
+    let sql = match &line.operation {
+        Operation::Scan {
+            table,
+            predicate,
+            is_distinct,
+            ..
+        } => format!(
+            "{} FROM {}{}",
+            columns(*is_distinct, outputs),
+            quote(table),
+            where_clause(predicate.as_ref())
+        ),
+        Operation::Filter {
+            input,
+            predicate,
+            is_distinct,
+            ..
+        } => format!(
+            "{} FROM {}{}",
+            columns(*is_distinct, outputs),
+            line_table(*input),
+            where_clause(predicate.as_ref())
+        ),
+        Operation::Aggregate {
+            input, group_by, ..
+        } => {
+            let group_by = if group_by.is_empty() {
+                String::new()
+            } else {
+                let group_by = group_by.iter().map(|c| quote(c)).collect::<Vec<_>>();
+                format!(" GROUP BY {}", group_by.join(", "))
+            };
+            format!(
+                "{} FROM {}{group_by}",
+                columns(false, outputs),
+                line_table(*input)
+            )
+        }
+        Operation::Top { input, rows, .. } => format!(
+            "{} FROM {} LIMIT {rows}",
+            columns(false, outputs),
+            line_table(*input)
+        ),
+        Operation::Sort {
+            input,
+            order_by,
+            is_distinct,
+            ..
+        } => format!(
+            "{} FROM {} ORDER BY {}",
+            columns(*is_distinct, outputs),
+            line_table(*input),
+            order_by_clause(order_by)
+        ),
+        Operation::TopSort {
+            input,
+            rows,
+            order_by,
+            with_ties: false,
+            ..
+        } => format!(
+            "{} FROM {} ORDER BY {} LIMIT {rows}",
+            columns(false, outputs),
+            line_table(*input),
+            order_by_clause(order_by)
+        ),
+        // SQLite has no `FETCH FIRST ... WITH TIES`, rows tied with the last one are kept by
+        // ranking them instead.
+        Operation::TopSort {
+            input,
+            rows,
+            order_by,
+            with_ties: true,
+            ..
+        } => {
+            let order_by = order_by_clause(order_by);
+            format!(
+                "{} FROM (SELECT *, RANK() OVER (ORDER BY {order_by}) AS \"#rank\" FROM {}) WHERE \"#rank\" <= {rows} ORDER BY {order_by}",
+                columns(false, outputs),
+                line_table(*input)
+            )
+        }
+        Operation::Join {
+            inputs,
+            predicate,
+            is_distinct,
+            ..
+        } => {
+            let [lhs, rhs] = two_inputs(line.idx, inputs)?;
+            let join = match predicate {
+                Some(predicate) => format!("JOIN {} ON {}", line_table(rhs), condition(predicate)),
+                None => format!("CROSS JOIN {}", line_table(rhs)),
+            };
+            format!(
+                "{} FROM {} {join}",
+                columns(*is_distinct, outputs),
+                line_table(lhs)
+            )
+        }
+        // With a predicate, the rows of one input that match some row of the other, or the
+        // distinct matching pairs when columns of both are output. Otherwise the rows both
+        // inputs have.
+        Operation::Intersect {
+            inputs,
+            predicate,
+            is_distinct,
+            ..
+        } => {
+            let inputs = two_inputs(line.idx, inputs)?;
+            match (predicate, sides(inputs, outputs)) {
+                (Some(predicate), Some([outer, inner])) => format!(
+                    "{} FROM {} WHERE EXISTS (SELECT 1 FROM {} WHERE {})",
+                    columns(*is_distinct, outputs),
+                    line_table(outer),
+                    line_table(inner),
+                    condition(predicate)
+                ),
+                (Some(predicate), None) => format!(
+                    "{} FROM {} JOIN {} ON {}",
+                    columns(true, outputs),
+                    line_table(inputs[0]),
+                    line_table(inputs[1]),
+                    condition(predicate)
+                ),
+                (None, _) => set_operation("INTERSECT", inputs[0], inputs[1], outputs),
+            }
+        }
+        // The rows of the first input that match no row of the second, either on the predicate
+        // or on the value of the except column. Unlike for `Intersect`, the inputs do not swap.
+        Operation::Except {
+            inputs,
+            operator,
+            is_distinct,
+            ..
+        } => {
+            let [outer, inner] = two_inputs(line.idx, inputs)?;
+            if outputs_from(inner, outputs) {
+                return Err(SqlError::ExcludedOutputs { line: line.idx });
+            }
+            let anti_join = match operator {
+                ExceptOperator::Predicate(predicate) => format!(
+                    "NOT EXISTS (SELECT 1 FROM {} WHERE {})",
+                    line_table(inner),
+                    condition(predicate)
+                ),
+                ExceptOperator::ExceptColum(_, column) => format!(
+                    "{}.{} NOT IN (SELECT {} FROM {})",
+                    line_table(outer),
+                    quote(column),
+                    quote(column),
+                    line_table(inner)
+                ),
+            };
+            format!(
+                "{} FROM {} WHERE {anti_join}",
+                columns(*is_distinct, outputs),
+                line_table(outer)
+            )
+        }
+        Operation::Union { inputs, .. } => {
+            let [lhs, rhs] = two_inputs(line.idx, inputs)?;
+            set_operation("UNION", lhs, rhs, outputs)
+        }
+    };
+    Ok(sql)
+}
+
+fn two_inputs(line: usize, inputs: &[usize]) -> Result<[usize; 2], SqlError> {
+    match inputs {
+        [lhs, rhs] => Ok([*lhs, *rhs]),
+        _ => Err(SqlError::InputCount {
+            line,
+            expected: 2,
+            found: inputs.len(),
+        }),
+    }
+}
+
+/// Inputs of an `Intersect` semi join, the one the outputs come from first, or `None` when they
+/// come from both.
+fn sides([lhs, rhs]: [usize; 2], outputs: &[OutputColumn]) -> Option<[usize; 2]> {
+    match (outputs_from(lhs, outputs), outputs_from(rhs, outputs)) {
+        (true, true) => None,
+        (false, true) => Some([rhs, lhs]),
+        _ => Some([lhs, rhs]),
+    }
+}
+
+/// Whether any of `outputs` is a column of the input `side`.
+fn outputs_from(side: usize, outputs: &[OutputColumn]) -> bool {
+    outputs
+        .iter()
+        .any(|out| matches!(out, OutputColumn::Indexed { input, .. } if *input == side))
+}
+
+/// Set operation on the columns both inputs share the names of.
+fn set_operation(op: &str, lhs: usize, rhs: usize, outputs: &[OutputColumn]) -> String {
+    let mut seen = HashSet::new();
+    let names = outputs
+        .iter()
+        .map(|out| out.name())
+        .filter(|name| seen.insert(*name))
+        .map(quote)
+        .collect::<Vec<_>>();
+    let names = names.join(", ");
+    format!(
+        "SELECT {names} FROM {} {op} SELECT {names} FROM {}",
+        line_table(lhs),
+        line_table(rhs)
+    )
+}
+
+fn columns(is_distinct: bool, outputs: &[OutputColumn]) -> String {
+    let columns = outputs.iter().map(column).collect::<Vec<_>>();
+    let distinct = if is_distinct { "DISTINCT " } else { "" };
+    format!("SELECT {distinct}{}", columns.join(", "))
+}
+
+fn column(output: &OutputColumn) -> String {
+    match output {
+        OutputColumn::One => "1 AS \"One\"".to_owned(),
+        OutputColumn::CountStar => "COUNT(*) AS \"Count_Star\"".to_owned(),
+        OutputColumn::Column { name, alias: None } => quote(name),
+        OutputColumn::Column {
+            name,
+            alias: Some(alias),
+        } => format!("{} AS {}", quote(name), quote(alias)),
+        OutputColumn::Indexed { input, name } => format!("{}.{}", line_table(*input), quote(name)),
+        OutputColumn::Aggregate {
+            agg,
+            is_distinct,
+            column,
+            alias,
+        } => {
+            let agg = agg.to_string().to_uppercase();
+            let distinct = if *is_distinct { "DISTINCT " } else { "" };
+            format!("{agg}({distinct}{}) AS {}", quote(column), quote(alias))
+        }
+        OutputColumn::Expression { expression, alias } => {
+            format!("{} AS {}", arithmetic(expression), quote(alias))
+        }
+    }
+}
+
+fn where_clause(predicate: Option<&Predicate>) -> String {
+    match predicate {
+        Some(predicate) => format!(" WHERE {}", condition(predicate)),
+        None => String::new(),
+    }
+}
+
+/// Nested predicates are all parenthesized, so that SQL groups them the way the QPL tree does.
+fn condition(predicate: &Predicate) -> String {
+    let operand = |predicate: &Predicate| match predicate {
+        Predicate::Single { .. } => condition(predicate),
+        _ => format!("({})", condition(predicate)),
+    };
+    match predicate {
+        Predicate::Single { comparison } => match comparison {
+            Comparison::In(lhs, values) => format!("{} IN ({})", value(lhs), values_list(values)),
+            Comparison::NotIn(lhs, values) => {
+                format!("{} NOT IN ({})", value(lhs), values_list(values))
+            }
+            Comparison::Between(lhs, low, high) => {
+                format!("{} BETWEEN {} AND {}", value(lhs), value(low), value(high))
+            }
+            comparison => {
+                let (op, lhs, rhs) = comparison.parts().expect("comparison of two values");
+                format!("{} {op} {}", value(lhs), value(rhs))
+            }
+        },
+        Predicate::And { lhs, rhs } => format!("{} AND {}", operand(lhs), operand(rhs)),
+        Predicate::Or { lhs, rhs } => format!("{} OR {}", operand(lhs), operand(rhs)),
+    }
+}
+
+fn values_list(values: &[Comparable]) -> String {
+    values.iter().map(value).collect::<Vec<_>>().join(", ")
+}
+
+fn value(comparable: &Comparable) -> String {
+    match comparable {
+        Comparable::Number(n) => n.to_string(),
+        Comparable::Str(s) => format!("'{}'", s.replace('\'', "''")),
+        Comparable::Boolean(b) => u8::from(*b).to_string(),
+        Comparable::Null => "NULL".to_owned(),
+        Comparable::Column(column) => quote(column),
+        Comparable::IndexedColumn(input, column) => {
+            format!("{}.{}", line_table(*input), quote(column))
+        }
+        Comparable::Expression(expression) => arithmetic(expression),
+    }
+}
+
+/// Nested operations are all parenthesized, as in conditions, and negation too so that a
+/// negative number never starts a `--` comment.
+fn arithmetic(expression: &Expression) -> String {
+    let operand = |comparable: &Comparable| match comparable {
+        Comparable::Expression(expression) if matches!(**expression, Expression::Binary(..)) => {
+            format!("({})", arithmetic(expression))
+        }
+        comparable => value(comparable),
+    };
+    match expression {
+        Expression::Negate(comparable) => format!("-({})", value(comparable)),
+        Expression::Binary(op, lhs, rhs) => format!("{} {op} {}", operand(lhs), operand(rhs)),
+        Expression::Function(function, argument) => format!("{function}({})", value(argument)),
+    }
+}
+
+fn order_by_clause(order_by: &[String]) -> String {
+    order_by
+        .iter()
+        .map(|by| match by.rsplit_once(' ') {
+            Some((column, dir)) => format!("{} {dir}", quote(column)),
+            None => quote(by),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Name of the common table expression holding the rows of line `idx`.
+pub(crate) fn line_table(idx: usize) -> String {
+    quote(&format!("#{idx}"))
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, schemas::concert_singer, Mode};
+
+    fn sql(qpl: &str) -> String {
+        let parsed = parse(qpl, &concert_singer(), Mode::ParseWithGuards).unwrap();
+        to_sql(&parsed.qpl).unwrap()
+    }
+
+    #[test]
+    fn test_single_line_is_the_main_query() {
+        assert_eq!(
+            sql("#1 = Scan Table [ singer ] Predicate [ Country = 'france' AND Age > 20 OR Age < 10 ] Distinct [ true ] Output [ Name , Age AS Years ]"),
+            "SELECT DISTINCT \"Name\", \"Age\" AS \"Years\" FROM \"singer\" WHERE (\"Country\" = 'france' AND \"Age\" > 20) OR \"Age\" < 10"
+        );
+    }
+
+    #[test]
+    fn test_in_and_between_keep_their_sql_form() {
+        assert_eq!(
+            sql("#1 = Scan Table [ singer ] Predicate [ Country NOT IN ( 'France' , 'Spain' ) AND Age BETWEEN 20 AND 30 ] Output [ Name ]"),
+            "SELECT \"Name\" FROM \"singer\" WHERE \"Country\" NOT IN ('France', 'Spain') AND \"Age\" BETWEEN 20 AND 30"
+        );
+    }
+
+    #[test]
+    fn test_arithmetic_is_parenthesized() {
+        assert_eq!(
+            sql("#1 = Scan Table [ stadium ] Predicate [ ( Highest - Lowest ) * 2 > - Average ] Output [ Name , ROUND(Capacity / 3) AS Third ]"),
+            "SELECT \"Name\", ROUND(\"Capacity\" / 3) AS \"Third\" FROM \"stadium\" WHERE (\"Highest\" - \"Lowest\") * 2 > -(\"Average\")"
+        );
+    }
+
+    #[test]
+    fn test_previous_lines_become_ctes() {
+        assert_eq!(
+            sql("#1 = Scan Table [ stadium ] Output [ Stadium_ID , Capacity , Name ] ; #2 = Scan Table [ concert ] Predicate [ Year >= 2014 ] Output [ Stadium_ID , Year ] ; #3 = Aggregate [ #2 ] GroupBy [ Stadium_ID ] Output [ Stadium_ID , countstar AS Count_Star ] ; #4 = Join [ #1 , #3 ] Predicate [ #3.Stadium_ID = #1.Stadium_ID ] Output [ #1.Name , #3.Count_Star , #1.Capacity ] ; #5 = TopSort [ #4 ] Rows [ 1 ] OrderBy [ Count_Star DESC ] Output [ Capacity , Count_Star , Name ]"),
+            [
+                "WITH \"#1\" AS (SELECT \"Stadium_ID\", \"Capacity\", \"Name\" FROM \"stadium\"),",
+                "\"#2\" AS (SELECT \"Stadium_ID\", \"Year\" FROM \"concert\" WHERE \"Year\" >= 2014),",
+                "\"#3\" AS (SELECT \"Stadium_ID\", COUNT(*) AS \"Count_Star\" FROM \"#2\" GROUP BY \"Stadium_ID\"),",
+                "\"#4\" AS (SELECT \"#1\".\"Name\", \"#3\".\"Count_Star\", \"#1\".\"Capacity\" FROM \"#1\" JOIN \"#3\" ON \"#3\".\"Stadium_ID\" = \"#1\".\"Stadium_ID\")",
+                "SELECT \"Capacity\", \"Count_Star\", \"Name\" FROM \"#4\" ORDER BY \"Count_Star\" DESC LIMIT 1",
+            ]
+            .join(" ")
+        );
+    }
+
+    #[test]
+    fn test_except_and_intersect_become_anti_and_semi_joins() {
+        let except = sql("#1 = Scan Table [ stadium ] Output [ Stadium_ID , Name ] ; #2 = Scan Table [ concert ] Output [ Stadium_ID ] ; #3 = Except [ #1 , #2 ] Predicate [ #2.Stadium_ID IS NULL OR #1.Stadium_ID = #2.Stadium_ID ] Output [ #1.Name ]");
+        assert!(except.ends_with("SELECT \"#1\".\"Name\" FROM \"#1\" WHERE NOT EXISTS (SELECT 1 FROM \"#2\" WHERE \"#2\".\"Stadium_ID\" IS NULL OR \"#1\".\"Stadium_ID\" = \"#2\".\"Stadium_ID\")"));
+
+        let intersect = sql("#1 = Scan Table [ singer ] Output [ Name ] ; #2 = Scan Table [ singer ] Predicate [ Age > 30 ] Output [ Name ] ; #3 = Intersect [ #2 , #1 ] Predicate [ #2.Name = #1.Name ] Output [ #1.Name ]");
+        assert!(intersect.ends_with("SELECT \"#1\".\"Name\" FROM \"#1\" WHERE EXISTS (SELECT 1 FROM \"#2\" WHERE \"#2\".\"Name\" = \"#1\".\"Name\")"));
+
+        let union = sql("#1 = Scan Table [ singer ] Output [ Name ] ; #2 = Scan Table [ stadium ] Output [ Name ] ; #3 = Union [ #1 , #2 ] Output [ #1.Name , #2.Name ]");
+        assert!(union.ends_with("SELECT \"Name\" FROM \"#1\" UNION SELECT \"Name\" FROM \"#2\""));
+    }
+
+    #[test]
+    fn test_intersect_outputting_both_inputs_joins_them() {
+        let intersect = sql("#1 = Scan Table [ singer ] Output [ Name , Age ] ; #2 = Scan Table [ singer ] Output [ Name , Country ] ; #3 = Intersect [ #1 , #2 ] Predicate [ #1.Name = #2.Name ] Output [ #1.Age , #2.Country ]");
+        assert!(intersect.ends_with("SELECT DISTINCT \"#1\".\"Age\", \"#2\".\"Country\" FROM \"#1\" JOIN \"#2\" ON \"#1\".\"Name\" = \"#2\".\"Name\""));
+
+        let qpl = "#1 = Scan Table [ singer ] Output [ Name , Age ] ; #2 = Scan Table [ singer ] Output [ Name , Country ] ; #3 = Except [ #1 , #2 ] Predicate [ #1.Name = #2.Name ] Output [ #1.Age , #2.Country ]";
+        let parsed = parse(qpl, &concert_singer(), Mode::ParseWithGuards).unwrap();
+        assert_eq!(
+            to_sql(&parsed.qpl),
+            Err(SqlError::ExcludedOutputs { line: 3 })
+        );
+    }
+
+    #[test]
+    fn test_except_outputting_only_the_second_input_is_rejected() {
+        let qpl = "#1 = Scan Table [ singer ] Output [ Name , Age ] ; #2 = Scan Table [ singer ] Output [ Name , Country ] ; #3 = Except [ #1 , #2 ] Predicate [ #1.Name = #2.Name ] Output [ #2.Country ]";
+        let parsed = parse(qpl, &concert_singer(), Mode::ParseWithGuards).unwrap();
+        assert_eq!(
+            to_sql(&parsed.qpl),
+            Err(SqlError::ExcludedOutputs { line: 3 })
+        );
+    }
+
+    #[test]
+    fn test_top_sort_with_ties_ranks_rows() {
+        let sql = sql("#1 = Scan Table [ singer ] Output [ Name , Age ] ; #2 = TopSort [ #1 ] Rows [ 3 ] OrderBy [ Age DESC ] WithTies [ true ] Output [ Name , Age ]");
+        assert!(sql.ends_with("SELECT \"Name\", \"Age\" FROM (SELECT *, RANK() OVER (ORDER BY \"Age\" DESC) AS \"#rank\" FROM \"#1\") WHERE \"#rank\" <= 3 ORDER BY \"Age\" DESC"));
+    }
+
+    #[test]
+    fn test_quoted_names_round_trip() {
+        let mut schema = concert_singer();
+        schema.column_names[9] = "Home Town".to_owned();
+        schema.column_names[12] = "Order".to_owned();
+        let qpl = "#1 = Scan Table [ singer ] Predicate [ \"Home Town\" = 'Paris' AND \"Order\" > 1 ] Output [ \"Home Town\" , \"Order\" ] ; #2 = Aggregate [ #1 ] GroupBy [ \"Home Town\" ] Output [ \"Home Town\" , MAX(\"Order\") AS Max_Order , MIN(\"Home Town\") AS \"Min_Home Town\" ] ; #3 = Sort [ #2 ] OrderBy [ \"Min_Home Town\" DESC ] Output [ \"Home Town\" , Max_Order ]";
+        let parsed = parse(qpl, &schema, Mode::ParseWithGuardsAndTypeChecks).unwrap();
+        assert_eq!(parsed.qpl.to_string(), qpl);
+        assert_eq!(
+            to_sql(&parsed.qpl).unwrap(),
+            [
+                "WITH \"#1\" AS (SELECT \"Home Town\", \"Order\" FROM \"singer\" WHERE \"Home Town\" = 'Paris' AND \"Order\" > 1),",
+                "\"#2\" AS (SELECT \"Home Town\", MAX(\"Order\") AS \"Max_Order\", MIN(\"Home Town\") AS \"Min_Home Town\" FROM \"#1\" GROUP BY \"Home Town\")",
+                "SELECT \"Home Town\", \"Max_Order\" FROM \"#2\" ORDER BY \"Min_Home Town\" DESC",
+            ]
+            .join(" ")
+        );
+    }
+
+    #[test]
+    fn test_empty_qpl_has_no_sql() {
+        assert_eq!(to_sql(&Qpl::default()), Err(SqlError::EmptyQpl));
+    }
+}
@@ -0,0 +1,1467 @@
+//! Converts the subset of SQLite `SELECT`s found in Spider into QPL, so that training data
+//! comes out of the same grammar it is checked with.
+//!
+//! Each table of the `FROM` clause is scanned with the conditions on it alone, then the tables
+//! are joined left to right on the remaining conditions. `IN` and `NOT IN` subqueries become
+//! an `Intersect` or an `Except` with the subquery, comparisons with a scalar subquery a `Join`
+//! with it. Grouping and aggregates become an `Aggregate`, `HAVING` a `Filter` of its output,
+//! and `ORDER BY` and `LIMIT` a `Sort`, `Top` or `TopSort`. Each line only outputs the columns
+//! later lines use.
+
+use crate::{
+    domain::{
+        Agg, Comparable, Comparison, ExceptOperator, Line, Operation, OutputColumn, Predicate, Qpl,
+        SqlSchema,
+    },
+    parse, Mode, ParseError,
+};
+use sqlparser::{
+    ast::{
+        BinaryOperator, Distinct, DuplicateTreatment, Expr, Function, FunctionArg, FunctionArgExpr,
+        FunctionArguments, GroupByExpr, Ident, JoinConstraint, JoinOperator, OrderByExpr, Query,
+        Select, SelectItem, SetExpr, SetOperator, SetQuantifier, Statement, TableFactor,
+        TableWithJoins, UnaryOperator, Value,
+    },
+    dialect::SQLiteDialect,
+    parser::Parser,
+};
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum ConvertError {
+    /// The SQL does not parse.
+    Syntax(String),
+    /// The SQL uses something outside of the subset QPL can express.
+    Unsupported(String),
+    UnknownTable(String),
+    UnknownColumn(String),
+    /// A column that more than one table of the query has, or that two tables bring to the
+    /// same line.
+    AmbiguousColumn(String),
+    /// The QPL the SQL converts to is rejected by the guards.
+    Invalid(ParseError),
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConvertError::Syntax(e) => write!(f, "Invalid SQL: {e}"),
+            ConvertError::Unsupported(what) => write!(f, "QPL has no equivalent of {what}"),
+            ConvertError::UnknownTable(table) => write!(f, "Unknown table {table}"),
+            ConvertError::UnknownColumn(column) => write!(f, "Unknown column {column}"),
+            ConvertError::AmbiguousColumn(column) => write!(f, "Ambiguous column {column}"),
+            ConvertError::Invalid(e) => write!(f, "Converted QPL is invalid: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ConvertError {}
+
+/// QPL equivalent to the SQLite `SELECT` in `sql`, checked against `schema` with guards.
+pub fn from_sql(sql: &str, schema: &SqlSchema) -> Result<Qpl, ConvertError> {
+    let statements = Parser::parse_sql(&SQLiteDialect {}, sql)
+        .map_err(|e| ConvertError::Syntax(e.to_string()))?;
+    let [Statement::Query(query)] = statements.as_slice() else {
+        return Err(unsupported("anything but a single SELECT"));
+    };
+    let mut converter = Converter {
+        schema,
+        lines: vec![],
+    };
+    converter.query(query)?;
+    let qpl = Qpl(converter.lines);
+    let parsed =
+        parse(&qpl.to_string(), schema, Mode::ParseWithGuards).map_err(ConvertError::Invalid)?;
+    if parsed.qpl != qpl {
+        return Err(unsupported(&format!("{qpl}, which reads back differently")));
+    }
+    Ok(qpl)
+}
+
+fn unsupported(what: &str) -> ConvertError {
+    ConvertError::Unsupported(what.to_owned())
+}
+
+struct Converter<'s> {
+    schema: &'s SqlSchema,
+    lines: Vec<Line>,
+}
+
+impl Converter<'_> {
+    fn push(&mut self, operation: Operation) -> usize {
+        let idx = self.lines.len() + 1;
+        self.lines.push(Line { idx, operation });
+        idx
+    }
+
+    /// Names of the columns line `idx` outputs, leaving out `1 AS One`.
+    fn names(&self, idx: usize) -> Vec<String> {
+        self.lines[idx - 1]
+            .operation
+            .outputs()
+            .iter()
+            .filter(|output| **output != OutputColumn::One)
+            .map(|output| output.name().to_owned())
+            .collect()
+    }
+
+    fn query(&mut self, query: &Query) -> Result<usize, ConvertError> {
+        if query.with.is_some()
+            || query.offset.is_some()
+            || query.fetch.is_some()
+            || !query.limit_by.is_empty()
+        {
+            return Err(unsupported(&query.to_string()));
+        }
+        let order_by = query
+            .order_by
+            .as_ref()
+            .map_or(&[][..], |order_by| &order_by.exprs);
+        let limit = query.limit.as_ref().map(rows).transpose()?;
+        if let SetExpr::Select(select) = query.body.as_ref() {
+            return self.select(select, order_by, limit);
+        }
+        let idx = self.set_expr(&query.body)?;
+        let names = self.names(idx);
+        let order_by = order_by
+            .iter()
+            .map(|order| {
+                let name = match &order.expr {
+                    Expr::Identifier(ident) => names
+                        .iter()
+                        .find(|name| name.eq_ignore_ascii_case(&ident.value)),
+                    _ => None,
+                };
+                let name = name.ok_or_else(|| ConvertError::UnknownColumn(order.to_string()))?;
+                Ok(order_by_entry(name, order))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let outputs = names.iter().map(|name| named_output(name)).collect();
+        Ok(self.order_and_limit(idx, order_by, limit, outputs))
+    }
+
+    fn set_expr(&mut self, body: &SetExpr) -> Result<usize, ConvertError> {
+        match body {
+            SetExpr::Select(select) => self.select(select, &[], None),
+            SetExpr::Query(query) => self.query(query),
+            SetExpr::SetOperation {
+                op,
+                set_quantifier,
+                left,
+                right,
+            } => self.set_operation(*op, *set_quantifier, left, right),
+            _ => Err(unsupported(&body.to_string())),
+        }
+    }
+
+    /// Set operation on the columns of both sides, in order. The output keeps the names of
+    /// the left side.
+    fn set_operation(
+        &mut self,
+        op: SetOperator,
+        quantifier: SetQuantifier,
+        left: &SetExpr,
+        right: &SetExpr,
+    ) -> Result<usize, ConvertError> {
+        if !matches!(quantifier, SetQuantifier::None | SetQuantifier::Distinct) {
+            return Err(unsupported(&format!("{op} {quantifier}")));
+        }
+        let left = self.set_expr(left)?;
+        let right = self.set_expr(right)?;
+        let (left_names, right_names) = (self.names(left), self.names(right));
+        if left_names.is_empty() || left_names.len() != right_names.len() {
+            return Err(unsupported(&format!(
+                "{op} of different numbers of columns"
+            )));
+        }
+        let is_same = left_names == right_names;
+        let inputs = vec![left, right];
+        let outputs = left_names
+            .iter()
+            .map(|name| OutputColumn::Indexed {
+                input: left,
+                name: name.clone(),
+            })
+            .collect();
+        let equalities = left_names
+            .iter()
+            .zip(&right_names)
+            .map(|(l, r)| Predicate::Single {
+                comparison: Comparison::Equal(
+                    Comparable::IndexedColumn(left, l.clone()),
+                    Comparable::IndexedColumn(right, r.clone()),
+                ),
+            })
+            .reduce(|lhs, rhs| Predicate::And {
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            });
+        let operation = match op {
+            SetOperator::Union if is_same => Operation::Union { inputs, outputs },
+            SetOperator::Intersect => Operation::Intersect {
+                inputs,
+                predicate: equalities,
+                is_distinct: true,
+                outputs,
+            },
+            SetOperator::Except => Operation::Except {
+                inputs,
+                operator: ExceptOperator::Predicate(equalities.expect("columns were checked")),
+                is_distinct: true,
+                outputs,
+            },
+            _ => return Err(unsupported(&format!("{op} of differently named columns"))),
+        };
+        Ok(self.push(operation))
+    }
+
+    fn select(
+        &mut self,
+        select: &Select,
+        order_by: &[OrderByExpr],
+        limit: Option<usize>,
+    ) -> Result<usize, ConvertError> {
+        let is_distinct = match &select.distinct {
+            None => false,
+            Some(Distinct::Distinct) => true,
+            Some(Distinct::On(_)) => return Err(unsupported("DISTINCT ON")),
+        };
+        if select.top.is_some() || select.into.is_some() || select.qualify.is_some() {
+            return Err(unsupported(&select.to_string()));
+        }
+        let (mut scope, on) = Scope::new(self.schema, &select.from)?;
+        let finals = scope.projection(&select.projection)?;
+        let group_by = match &select.group_by {
+            GroupByExpr::Expressions(exprs, modifiers) if modifiers.is_empty() => exprs
+                .iter()
+                .map(|expr| scope.column(expr))
+                .collect::<Result<Vec<_>, _>>()?,
+            group_by => return Err(unsupported(&group_by.to_string())),
+        };
+        let having = select
+            .having
+            .as_ref()
+            .map(|expr| scope.cond(expr))
+            .transpose()?
+            .and_then(|cond| conjunction(vec![cond]));
+        let order = order_by
+            .iter()
+            .map(|order| Ok((scope.item(&order.expr)?, order)))
+            .collect::<Result<Vec<_>, ConvertError>>()?;
+        let mut conds = vec![];
+        let mut links = vec![];
+        for conjunct in on
+            .into_iter()
+            .chain(select.selection.as_ref())
+            .flat_map(conjuncts)
+        {
+            match scope.link(conjunct)? {
+                Some(link) => links.push(link),
+                None => conds.push(scope.cond(conjunct)?),
+            }
+        }
+
+        let post_items = dedup(
+            finals
+                .iter()
+                .chain(order.iter().map(|(item, _)| item))
+                .chain(having.iter().flat_map(Cond::items))
+                .cloned(),
+        );
+        let is_aggregated = !group_by.is_empty()
+            || post_items
+                .iter()
+                .any(|item| !matches!(item, Item::Column(_)));
+        let is_sorted = !order.is_empty() || limit.is_some();
+        // Columns left once the tables are joined and filtered, for the lines after.
+        let kept = if is_aggregated || having.is_some() || is_sorted {
+            dedup(
+                group_by
+                    .iter()
+                    .chain(post_items.iter().filter_map(Item::column))
+                    .cloned(),
+            )
+        } else {
+            finals.iter().filter_map(Item::column).cloned().collect()
+        };
+        let (own, cross): (Vec<_>, Vec<_>) = conds.into_iter().partition(Cond::is_on_one_table);
+        let link_columns = links
+            .iter()
+            .map(|link| link.column.clone())
+            .collect::<Vec<_>>();
+
+        let tables = scope.tables.len();
+        let mut at = vec![0; tables];
+        for (t, table) in scope.tables.iter().enumerate() {
+            let conds = own
+                .iter()
+                .filter(|cond| cond.last_table() == Some(t))
+                .cloned()
+                .collect();
+            let predicate = conjunction(conds)
+                .map(|cond| cond.predicate(&|item| Comparable::Column(item.name())))
+                .transpose()?;
+            let columns = if tables == 1 && links.is_empty() {
+                kept.clone()
+            } else {
+                dedup(
+                    kept.iter()
+                        .chain(cross.iter().flat_map(Cond::columns))
+                        .chain(&link_columns)
+                        .filter(|column| column.table == t)
+                        .cloned(),
+                )
+            };
+            let outputs = if columns.is_empty() {
+                vec![OutputColumn::One]
+            } else {
+                columns
+                    .iter()
+                    .map(|column| named_output(&column.name))
+                    .collect()
+            };
+            at[t] = self.push(Operation::Scan {
+                table: table.name.clone(),
+                predicate,
+                is_distinct: false,
+                outputs,
+            });
+        }
+        for t in 1..tables {
+            let conds = cross
+                .iter()
+                .filter(|cond| cond.last_table() == Some(t))
+                .cloned()
+                .collect();
+            let predicate = conjunction(conds)
+                .map(|cond| cond.predicate(&|item| indexed(item, &at)))
+                .transpose()?;
+            let columns = if t + 1 == tables && links.is_empty() {
+                kept.clone()
+            } else {
+                dedup(
+                    kept.iter()
+                        .chain(
+                            cross
+                                .iter()
+                                .filter(|cond| cond.last_table() > Some(t))
+                                .flat_map(Cond::columns),
+                        )
+                        .chain(&link_columns)
+                        .filter(|column| column.table <= t)
+                        .cloned(),
+                )
+            };
+            let outputs = self.indexed_outputs(&columns, &at, at[0])?;
+            let idx = self.push(Operation::Join {
+                inputs: vec![at[0], at[t]],
+                predicate,
+                is_distinct: false,
+                outputs,
+            });
+            at[..=t].fill(idx);
+        }
+
+        let mut current = at[0];
+        for (l, link) in links.iter().enumerate() {
+            let subquery = self.query(link.query)?;
+            let [column] = self.names(subquery).try_into().map_err(|_| {
+                unsupported(&format!("{}, which is not a single column", link.query))
+            })?;
+            let columns = if l + 1 == links.len() {
+                kept.clone()
+            } else {
+                dedup(kept.iter().chain(&link_columns[l + 1..]).cloned())
+            };
+            let outputs = self.indexed_outputs(&columns, &vec![current; tables], current)?;
+            let lhs = Comparable::IndexedColumn(current, link.column.name.clone());
+            let rhs = Comparable::IndexedColumn(subquery, column);
+            let inputs = vec![current, subquery];
+            let operation = match link.kind {
+                LinkKind::In => Operation::Intersect {
+                    inputs,
+                    predicate: Some(Predicate::Single {
+                        comparison: Comparison::Equal(lhs, rhs),
+                    }),
+                    is_distinct: false,
+                    outputs,
+                },
+                // Like SQL, keeps no row when the subquery has a NULL.
+                LinkKind::NotIn => Operation::Except {
+                    inputs,
+                    operator: ExceptOperator::Predicate(Predicate::Or {
+                        lhs: Box::new(Predicate::Single {
+                            comparison: Comparison::Is(rhs.clone(), Comparable::Null),
+                        }),
+                        rhs: Box::new(Predicate::Single {
+                            comparison: Comparison::Equal(lhs, rhs),
+                        }),
+                    }),
+                    is_distinct: false,
+                    outputs,
+                },
+                LinkKind::Compare(op) => Operation::Join {
+                    inputs,
+                    predicate: Some(Predicate::Single {
+                        comparison: Comparison::from_string(op, lhs, rhs)
+                            .ok_or_else(|| unsupported(op))?,
+                    }),
+                    is_distinct: false,
+                    outputs,
+                },
+            };
+            current = self.push(operation);
+        }
+
+        let final_names = finals.iter().map(Item::name).collect::<Vec<_>>();
+        if is_aggregated {
+            let items = if having.is_none() && !is_sorted {
+                &finals
+            } else {
+                &post_items
+            };
+            current = self.push(Operation::Aggregate {
+                input: current,
+                group_by: group_by.iter().map(|column| column.name.clone()).collect(),
+                outputs: items.iter().map(Item::aggregate_output).collect(),
+            });
+        }
+        if let Some(having) = having {
+            let names = if is_sorted {
+                dedup(
+                    finals
+                        .iter()
+                        .chain(order.iter().map(|(item, _)| item))
+                        .map(Item::name),
+                )
+            } else {
+                final_names.clone()
+            };
+            current = self.push(Operation::Filter {
+                input: current,
+                predicate: Some(having.predicate(&|item| Comparable::Column(item.name()))?),
+                is_distinct: false,
+                outputs: names.iter().map(|name| named_output(name)).collect(),
+            });
+        }
+        // Rows are made distinct before they are counted, but may as well be after sorting.
+        if is_distinct && limit.is_some() {
+            current = self.distinct(current);
+        }
+        let order_by = order
+            .iter()
+            .map(|(item, order)| order_by_entry(&item.name(), order))
+            .collect();
+        let outputs = final_names.iter().map(|name| named_output(name)).collect();
+        current = self.order_and_limit(current, order_by, limit, outputs);
+        if is_distinct && limit.is_none() {
+            current = self.distinct(current);
+        }
+        Ok(current)
+    }
+
+    /// Outputs of a line with several inputs, where `at` tells which input each table's
+    /// columns come from. QPL has no `1 AS One` for these lines, so without columns to keep
+    /// they keep the first one of `fallback`.
+    fn indexed_outputs(
+        &self,
+        columns: &[ColumnRef],
+        at: &[usize],
+        fallback: usize,
+    ) -> Result<Vec<OutputColumn>, ConvertError> {
+        for (i, column) in columns.iter().enumerate() {
+            let is_ambiguous = columns[..i]
+                .iter()
+                .any(|other| other != column && other.name.eq_ignore_ascii_case(&column.name));
+            if is_ambiguous {
+                return Err(ConvertError::AmbiguousColumn(column.name.clone()));
+            }
+        }
+        if columns.is_empty() {
+            let name = self
+                .names(fallback)
+                .into_iter()
+                .next()
+                .ok_or_else(|| unsupported("a join of lines without columns"))?;
+            return Ok(vec![OutputColumn::Indexed {
+                input: fallback,
+                name,
+            }]);
+        }
+        Ok(columns
+            .iter()
+            .map(|column| OutputColumn::Indexed {
+                input: at[column.table],
+                name: column.name.clone(),
+            })
+            .collect())
+    }
+
+    fn order_and_limit(
+        &mut self,
+        input: usize,
+        order_by: Vec<String>,
+        limit: Option<usize>,
+        outputs: Vec<OutputColumn>,
+    ) -> usize {
+        let operation = match (order_by.is_empty(), limit) {
+            (true, None) => return input,
+            (true, Some(rows)) => Operation::Top {
+                input,
+                rows,
+                outputs,
+            },
+            (false, None) => Operation::Sort {
+                input,
+                order_by,
+                is_distinct: false,
+                outputs,
+            },
+            (false, Some(rows)) => Operation::TopSort {
+                input,
+                rows,
+                order_by,
+                with_ties: false,
+                outputs,
+            },
+        };
+        self.push(operation)
+    }
+
+    /// Makes the rows of line `idx`, the last one, distinct, with a `Filter` when its operator
+    /// has no `Distinct`.
+    fn distinct(&mut self, idx: usize) -> usize {
+        match &mut self.lines[idx - 1].operation {
+            Operation::Scan { is_distinct, .. }
+            | Operation::Filter { is_distinct, .. }
+            | Operation::Join { is_distinct, .. }
+            | Operation::Intersect { is_distinct, .. }
+            | Operation::Except { is_distinct, .. }
+            | Operation::Sort { is_distinct, .. } => {
+                *is_distinct = true;
+                idx
+            }
+            _ => {
+                let outputs = self
+                    .names(idx)
+                    .iter()
+                    .map(|name| named_output(name))
+                    .collect();
+                self.push(Operation::Filter {
+                    input: idx,
+                    predicate: None,
+                    is_distinct: true,
+                    outputs,
+                })
+            }
+        }
+    }
+}
+
+fn indexed(item: &Item, at: &[usize]) -> Comparable {
+    match item {
+        Item::Column(column) => Comparable::IndexedColumn(at[column.table], column.name.clone()),
+        _ => Comparable::Column(item.name()),
+    }
+}
+
+/// `name` followed by the direction of `order`, as in `Name DESC`.
+fn order_by_entry(name: &str, order: &OrderByExpr) -> String {
+    let direction = if order.asc == Some(false) {
+        "DESC"
+    } else {
+        "ASC"
+    };
+    format!("{name} {direction}")
+}
+
+/// Column of one of the tables of a `FROM` clause, by position in the clause.
+#[derive(Clone, Debug, PartialEq)]
+struct ColumnRef {
+    table: usize,
+    name: String,
+}
+
+/// Expression of a select list, `ORDER BY` or `HAVING`.
+#[derive(Clone, Debug, PartialEq)]
+enum Item {
+    Column(ColumnRef),
+    CountStar,
+    Aggregate {
+        agg: Agg,
+        is_distinct: bool,
+        column: ColumnRef,
+    },
+}
+
+impl Item {
+    /// Name of the item in the output of an `Aggregate`, following QPL's aliasing convention.
+    fn name(&self) -> String {
+        match self {
+            Item::Column(column) => column.name.clone(),
+            Item::CountStar => "Count_Star".to_owned(),
+            Item::Aggregate {
+                agg,
+                is_distinct,
+                column,
+            } => {
+                let distinct = if *is_distinct { "Dist_" } else { "" };
+                format!("{agg}_{distinct}{}", column.name)
+            }
+        }
+    }
+
+    fn column(&self) -> Option<&ColumnRef> {
+        match self {
+            Item::Column(column) | Item::Aggregate { column, .. } => Some(column),
+            Item::CountStar => None,
+        }
+    }
+
+    fn aggregate_output(&self) -> OutputColumn {
+        match self {
+            Item::Column(column) => named_output(&column.name),
+            Item::CountStar => OutputColumn::CountStar,
+            Item::Aggregate {
+                agg,
+                is_distinct,
+                column,
+            } => OutputColumn::Aggregate {
+                agg: *agg,
+                is_distinct: *is_distinct,
+                column: column.name.clone(),
+                alias: self.name(),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Item(Item),
+    Value(Comparable),
+}
+
+/// Condition of a `WHERE`, `ON` or `HAVING` clause.
+#[derive(Clone, Debug)]
+enum Cond {
+    Compare(&'static str, Operand, Operand),
+    /// `IN` a list of values, or `NOT IN` it when negated.
+    In(bool, Operand, Vec<Operand>),
+    Between(Operand, Operand, Operand),
+    And(Box<Cond>, Box<Cond>),
+    Or(Box<Cond>, Box<Cond>),
+}
+
+impl Cond {
+    fn conjuncts(self) -> Vec<Cond> {
+        match self {
+            Cond::And(lhs, rhs) => {
+                let mut conds = lhs.conjuncts();
+                conds.extend(rhs.conjuncts());
+                conds
+            }
+            cond => vec![cond],
+        }
+    }
+
+    fn items(&self) -> Vec<&Item> {
+        match self {
+            Cond::And(lhs, rhs) | Cond::Or(lhs, rhs) => {
+                let mut items = lhs.items();
+                items.extend(rhs.items());
+                items
+            }
+            cond => cond
+                .operands()
+                .into_iter()
+                .filter_map(|operand| match operand {
+                    Operand::Item(item) => Some(item),
+                    Operand::Value(_) => None,
+                })
+                .collect(),
+        }
+    }
+
+    /// Operands of a condition that is no `AND` or `OR`.
+    fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Cond::Compare(_, lhs, rhs) => vec![lhs, rhs],
+            Cond::In(_, lhs, values) => [lhs].into_iter().chain(values).collect(),
+            Cond::Between(lhs, low, high) => vec![lhs, low, high],
+            Cond::And(..) | Cond::Or(..) => vec![],
+        }
+    }
+
+    fn columns(&self) -> Vec<&ColumnRef> {
+        self.items().into_iter().filter_map(Item::column).collect()
+    }
+
+    /// Position in the `FROM` clause of the last table the condition needs, if any.
+    fn last_table(&self) -> Option<usize> {
+        self.columns().iter().map(|column| column.table).max()
+    }
+
+    fn is_on_one_table(&self) -> bool {
+        let columns = self.columns();
+        columns
+            .iter()
+            .all(|column| column.table == columns[0].table)
+    }
+
+    /// The predicate that reads as the condition, with `item` giving each column's spelling.
+    fn predicate(&self, item: &impl Fn(&Item) -> Comparable) -> Result<Predicate, ConvertError> {
+        let comparable = |operand: &Operand| match operand {
+            Operand::Item(i) => item(i),
+            Operand::Value(value) => value.clone(),
+        };
+        let predicate = match self {
+            Cond::Compare(op, lhs, rhs) => Predicate::Single {
+                comparison: Comparison::from_string(op, comparable(lhs), comparable(rhs))
+                    .ok_or_else(|| unsupported(op))?,
+            },
+            Cond::In(negated, lhs, values) => {
+                let (lhs, values) = (comparable(lhs), values.iter().map(comparable).collect());
+                let comparison = if *negated {
+                    Comparison::NotIn(lhs, values)
+                } else {
+                    Comparison::In(lhs, values)
+                };
+                Predicate::Single { comparison }
+            }
+            Cond::Between(lhs, low, high) => Predicate::Single {
+                comparison: Comparison::Between(comparable(lhs), comparable(low), comparable(high)),
+            },
+            Cond::And(lhs, rhs) => Predicate::And {
+                lhs: Box::new(lhs.predicate(item)?),
+                rhs: Box::new(rhs.predicate(item)?),
+            },
+            Cond::Or(lhs, rhs) => Predicate::Or {
+                lhs: Box::new(lhs.predicate(item)?),
+                rhs: Box::new(rhs.predicate(item)?),
+            },
+        };
+        Ok(predicate)
+    }
+}
+
+/// Conjuncts ANDed together, left to right.
+fn conjunction(conds: Vec<Cond>) -> Option<Cond> {
+    conds
+        .into_iter()
+        .flat_map(Cond::conjuncts)
+        .reduce(|lhs, rhs| Cond::And(Box::new(lhs), Box::new(rhs)))
+}
+
+/// A `WHERE` conjunct comparing a column with a subquery.
+struct Link<'q> {
+    column: ColumnRef,
+    kind: LinkKind,
+    query: &'q Query,
+}
+
+enum LinkKind {
+    In,
+    NotIn,
+    Compare(&'static str),
+}
+
+/// Tables of a `FROM` clause, along with the aliases of the select list.
+struct Scope<'s> {
+    schema: &'s SqlSchema,
+    tables: Vec<ScopeTable>,
+    aliases: Vec<(String, Item)>,
+}
+
+struct ScopeTable {
+    name: String,
+    alias: Option<String>,
+}
+
+impl ScopeTable {
+    fn is_named(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self
+                .alias
+                .as_ref()
+                .is_some_and(|alias| alias.eq_ignore_ascii_case(name))
+    }
+}
+
+impl<'s> Scope<'s> {
+    /// Scope of the tables of `from`, along with the `ON` conditions of their joins.
+    fn new<'q>(
+        schema: &'s SqlSchema,
+        from: &'q [TableWithJoins],
+    ) -> Result<(Self, Vec<&'q Expr>), ConvertError> {
+        let mut tables = vec![];
+        let mut on = vec![];
+        for table in from {
+            tables.push(scope_table(schema, &table.relation)?);
+            for join in &table.joins {
+                tables.push(scope_table(schema, &join.relation)?);
+                match &join.join_operator {
+                    JoinOperator::Inner(JoinConstraint::On(expr)) => on.push(expr),
+                    JoinOperator::Inner(JoinConstraint::None) | JoinOperator::CrossJoin => {}
+                    _ => return Err(unsupported("joins other than inner joins with ON")),
+                }
+            }
+        }
+        if tables.is_empty() {
+            return Err(unsupported("SELECT without FROM"));
+        }
+        let scope = Self {
+            schema,
+            tables,
+            aliases: vec![],
+        };
+        Ok((scope, on))
+    }
+
+    fn columns<'t>(&self, table: &'t ScopeTable) -> impl Iterator<Item = &'s String> + 't
+    where
+        's: 't,
+    {
+        let schema = self.schema;
+        schema
+            .table_to_columns
+            .get(&table.name)
+            .into_iter()
+            .flatten()
+            .map(move |&column| &schema.column_names[column])
+    }
+
+    /// The column named `column` of the table named `table`, or of whichever table has one.
+    fn find(
+        &self,
+        table: Option<&Ident>,
+        column: &Ident,
+    ) -> Result<Option<ColumnRef>, ConvertError> {
+        let tables = self
+            .tables
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| table.is_none_or(|table| t.is_named(&table.value)))
+            .collect::<Vec<_>>();
+        if let (Some(table), true) = (table, tables.is_empty()) {
+            return Err(ConvertError::UnknownTable(table.value.clone()));
+        }
+        let mut found = tables.into_iter().filter_map(|(i, t)| {
+            let name = self
+                .columns(t)
+                .find(|name| name.eq_ignore_ascii_case(&column.value))?;
+            Some(ColumnRef {
+                table: i,
+                name: name.clone(),
+            })
+        });
+        let first = found.next();
+        if found.next().is_some() {
+            return Err(ConvertError::AmbiguousColumn(column.value.clone()));
+        }
+        Ok(first)
+    }
+
+    fn projection(&mut self, projection: &[SelectItem]) -> Result<Vec<Item>, ConvertError> {
+        let mut items = vec![];
+        for select_item in projection {
+            match select_item {
+                SelectItem::UnnamedExpr(expr) => items.push(self.item(expr)?),
+                SelectItem::ExprWithAlias { expr, alias } => {
+                    let item = self.item(expr)?;
+                    self.aliases.push((alias.value.clone(), item.clone()));
+                    items.push(item);
+                }
+                SelectItem::Wildcard(_) => items.extend(self.wildcard(None)),
+                SelectItem::QualifiedWildcard(name, _) => match name.0.as_slice() {
+                    [table] if self.tables.iter().any(|t| t.is_named(&table.value)) => {
+                        items.extend(self.wildcard(Some(&table.value)))
+                    }
+                    _ => return Err(ConvertError::UnknownTable(name.to_string())),
+                },
+            }
+        }
+        Ok(items)
+    }
+
+    fn wildcard(&self, table: Option<&str>) -> Vec<Item> {
+        self.tables
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| table.is_none_or(|table| t.is_named(table)))
+            .flat_map(|(i, t)| {
+                self.columns(t).map(move |name| {
+                    Item::Column(ColumnRef {
+                        table: i,
+                        name: name.clone(),
+                    })
+                })
+            })
+            .collect()
+    }
+
+    fn column(&self, expr: &Expr) -> Result<ColumnRef, ConvertError> {
+        match self.item(expr)? {
+            Item::Column(column) => Ok(column),
+            _ => Err(unsupported(&format!("{expr} where a column is expected"))),
+        }
+    }
+
+    fn item(&self, expr: &Expr) -> Result<Item, ConvertError> {
+        match expr {
+            Expr::Nested(expr) => self.item(expr),
+            Expr::Identifier(ident) => {
+                if let Some(column) = self.find(None, ident)? {
+                    return Ok(Item::Column(column));
+                }
+                self.aliases
+                    .iter()
+                    .find(|(alias, _)| alias.eq_ignore_ascii_case(&ident.value))
+                    .map(|(_, item)| item.clone())
+                    .ok_or_else(|| ConvertError::UnknownColumn(ident.value.clone()))
+            }
+            Expr::CompoundIdentifier(idents) => match idents.as_slice() {
+                [table, column] => self
+                    .find(Some(table), column)?
+                    .map(Item::Column)
+                    .ok_or_else(|| ConvertError::UnknownColumn(expr.to_string())),
+                _ => Err(unsupported(&expr.to_string())),
+            },
+            Expr::Function(function) => self.aggregate(function),
+            _ => Err(unsupported(&expr.to_string())),
+        }
+    }
+
+    fn aggregate(&self, function: &Function) -> Result<Item, ConvertError> {
+        let agg = match function.name.to_string().to_lowercase().as_str() {
+            "count" => Agg::Count,
+            "sum" => Agg::Sum,
+            "min" => Agg::Min,
+            "max" => Agg::Max,
+            "avg" => Agg::Average,
+            _ => return Err(unsupported(&function.to_string())),
+        };
+        let FunctionArguments::List(list) = &function.args else {
+            return Err(unsupported(&function.to_string()));
+        };
+        if function.filter.is_some() || function.over.is_some() || !list.clauses.is_empty() {
+            return Err(unsupported(&function.to_string()));
+        }
+        let is_distinct = matches!(list.duplicate_treatment, Some(DuplicateTreatment::Distinct));
+        match list.args.as_slice() {
+            [FunctionArg::Unnamed(FunctionArgExpr::Wildcard)]
+                if agg == Agg::Count && !is_distinct =>
+            {
+                Ok(Item::CountStar)
+            }
+            [FunctionArg::Unnamed(FunctionArgExpr::Expr(expr))] => Ok(Item::Aggregate {
+                agg,
+                is_distinct,
+                column: self.column(expr)?,
+            }),
+            _ => Err(unsupported(&function.to_string())),
+        }
+    }
+
+    fn operand(&self, expr: &Expr) -> Result<Operand, ConvertError> {
+        if let Some(value) = literal(expr)? {
+            return Ok(Operand::Value(value));
+        }
+        match (expr, self.item(expr)) {
+            (_, Ok(item)) => Ok(Operand::Item(item)),
+            // SQLite reads a double-quoted word naming no column as a string, as Spider does.
+            (
+                Expr::Identifier(Ident {
+                    value,
+                    quote_style: Some('"'),
+                    ..
+                }),
+                Err(ConvertError::UnknownColumn(_)),
+            ) => Ok(Operand::Value(string(value)?)),
+            (_, Err(e)) => Err(e),
+        }
+    }
+
+    fn cond(&self, expr: &Expr) -> Result<Cond, ConvertError> {
+        match expr {
+            Expr::Nested(expr) => self.cond(expr),
+            Expr::BinaryOp {
+                left,
+                op: BinaryOperator::And,
+                right,
+            } => Ok(Cond::And(
+                Box::new(self.cond(left)?),
+                Box::new(self.cond(right)?),
+            )),
+            Expr::BinaryOp {
+                left,
+                op: BinaryOperator::Or,
+                right,
+            } => Ok(Cond::Or(
+                Box::new(self.cond(left)?),
+                Box::new(self.cond(right)?),
+            )),
+            Expr::BinaryOp { left, op, right } => match comparison_op(op) {
+                Some(op) => self.compare(op, left, right),
+                None => Err(unsupported(&expr.to_string())),
+            },
+            Expr::IsNull(expr) => Ok(Cond::Compare(
+                "IS",
+                self.operand(expr)?,
+                Operand::Value(Comparable::Null),
+            )),
+            Expr::IsNotNull(expr) => Ok(Cond::Compare(
+                "IS NOT",
+                self.operand(expr)?,
+                Operand::Value(Comparable::Null),
+            )),
+            Expr::Like {
+                negated,
+                any: false,
+                expr,
+                pattern,
+                escape_char: None,
+            } => {
+                let op = if *negated { "NOT LIKE" } else { "LIKE" };
+                self.compare(op, expr, pattern)
+            }
+            Expr::Between {
+                expr,
+                negated: false,
+                low,
+                high,
+            } => Ok(Cond::Between(
+                self.compared(expr)?,
+                self.operand(low)?,
+                self.operand(high)?,
+            )),
+            Expr::InList {
+                expr,
+                list,
+                negated,
+            } if !list.is_empty() => Ok(Cond::In(
+                *negated,
+                self.compared(expr)?,
+                list.iter()
+                    .map(|value| self.operand(value))
+                    .collect::<Result<_, _>>()?,
+            )),
+            _ => Err(unsupported(&expr.to_string())),
+        }
+    }
+
+    /// Comparison with the column first, as QPL writes them.
+    fn compare(&self, op: &'static str, lhs: &Expr, rhs: &Expr) -> Result<Cond, ConvertError> {
+        match (self.operand(lhs)?, self.operand(rhs)?) {
+            (Operand::Value(_), Operand::Value(_)) => Err(unsupported(&format!(
+                "{lhs} {op} {rhs}, which compares no column"
+            ))),
+            (lhs @ Operand::Value(_), rhs) => Ok(Cond::Compare(flipped(op)?, rhs, lhs)),
+            (lhs, rhs) => Ok(Cond::Compare(op, lhs, rhs)),
+        }
+    }
+
+    /// Operand that QPL writes first, which has to be a column or an aggregate.
+    fn compared(&self, expr: &Expr) -> Result<Operand, ConvertError> {
+        match self.operand(expr)? {
+            Operand::Value(_) => Err(unsupported(&format!("{expr}, which is no column"))),
+            operand => Ok(operand),
+        }
+    }
+
+    fn link<'q>(&self, expr: &'q Expr) -> Result<Option<Link<'q>>, ConvertError> {
+        let link = match expr {
+            Expr::Nested(expr) => return self.link(expr),
+            Expr::InSubquery {
+                expr,
+                subquery,
+                negated,
+            } => Link {
+                column: self.column(expr)?,
+                kind: if *negated {
+                    LinkKind::NotIn
+                } else {
+                    LinkKind::In
+                },
+                query: subquery,
+            },
+            Expr::BinaryOp { left, op, right } => {
+                let Some(op) = comparison_op(op) else {
+                    return Ok(None);
+                };
+                match (subquery(left), subquery(right)) {
+                    (None, Some(query)) => Link {
+                        column: self.column(left)?,
+                        kind: LinkKind::Compare(op),
+                        query,
+                    },
+                    (Some(query), None) => Link {
+                        column: self.column(right)?,
+                        kind: LinkKind::Compare(flipped(op)?),
+                        query,
+                    },
+                    _ => return Ok(None),
+                }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(link))
+    }
+}
+
+fn scope_table(schema: &SqlSchema, factor: &TableFactor) -> Result<ScopeTable, ConvertError> {
+    let TableFactor::Table {
+        name,
+        alias,
+        args: None,
+        ..
+    } = factor
+    else {
+        return Err(unsupported(&format!("{factor} in FROM")));
+    };
+    let [table] = name.0.as_slice() else {
+        return Err(ConvertError::UnknownTable(name.to_string()));
+    };
+    let name = schema
+        .table_names
+        .iter()
+        .find(|name| name.eq_ignore_ascii_case(&table.value))
+        .ok_or_else(|| ConvertError::UnknownTable(table.value.clone()))?;
+    Ok(ScopeTable {
+        name: name.clone(),
+        alias: alias.as_ref().map(|alias| alias.name.value.clone()),
+    })
+}
+
+fn subquery(expr: &Expr) -> Option<&Query> {
+    match expr {
+        Expr::Subquery(query) => Some(query),
+        Expr::Nested(expr) => subquery(expr),
+        _ => None,
+    }
+}
+
+/// The operands of `AND`s, nested or not.
+fn conjuncts(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::Nested(nested)
+            if matches!(
+                **nested,
+                Expr::BinaryOp {
+                    op: BinaryOperator::And,
+                    ..
+                }
+            ) =>
+        {
+            conjuncts(nested)
+        }
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => {
+            let mut exprs = conjuncts(left);
+            exprs.extend(conjuncts(right));
+            exprs
+        }
+        _ => vec![expr],
+    }
+}
+
+fn comparison_op(op: &BinaryOperator) -> Option<&'static str> {
+    let op = match op {
+        BinaryOperator::Eq => "=",
+        BinaryOperator::NotEq => "<>",
+        BinaryOperator::Gt => ">",
+        BinaryOperator::GtEq => ">=",
+        BinaryOperator::Lt => "<",
+        BinaryOperator::LtEq => "<=",
+        _ => return None,
+    };
+    Some(op)
+}
+
+/// The operator comparing the same operands the other way around.
+fn flipped(op: &'static str) -> Result<&'static str, ConvertError> {
+    match op {
+        "=" | "<>" => Ok(op),
+        ">" => Ok("<"),
+        ">=" => Ok("<="),
+        "<" => Ok(">"),
+        "<=" => Ok(">="),
+        _ => Err(unsupported(&format!("a value on the left of {op}"))),
+    }
+}
+
+fn literal(expr: &Expr) -> Result<Option<Comparable>, ConvertError> {
+    let value = match expr {
+        Expr::Nested(expr) => return literal(expr),
+        Expr::Value(Value::Number(n, _)) => Comparable::Number(number(n)?),
+        Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => match expr.as_ref() {
+            Expr::Value(Value::Number(n, _)) => Comparable::Number(-number(n)?),
+            _ => return Ok(None),
+        },
+        Expr::Value(Value::SingleQuotedString(s) | Value::DoubleQuotedString(s)) => string(s)?,
+        // QPL reads `1` and `0` as numbers, booleans included.
+        Expr::Value(Value::Boolean(b)) => Comparable::Number(f64::from(u8::from(*b))),
+        Expr::Value(Value::Null) => Comparable::Null,
+        _ => return Ok(None),
+    };
+    Ok(Some(value))
+}
+
+fn number(n: &str) -> Result<f64, ConvertError> {
+    n.parse()
+        .ok()
+        .filter(|n: &f64| n.is_finite())
+        .ok_or_else(|| unsupported(n))
+}
+
+fn string(s: &str) -> Result<Comparable, ConvertError> {
+    if s.contains('\'') {
+        return Err(unsupported(&format!(
+            "the quote in {s}, which QPL strings cannot hold"
+        )));
+    }
+    Ok(Comparable::Str(s.to_owned()))
+}
+
+fn rows(expr: &Expr) -> Result<usize, ConvertError> {
+    match expr {
+        Expr::Value(Value::Number(n, _)) => n.parse().map_err(|_| unsupported(n)),
+        _ => Err(unsupported(&format!("LIMIT {expr}"))),
+    }
+}
+
+fn named_output(name: &str) -> OutputColumn {
+    OutputColumn::Column {
+        name: name.to_owned(),
+        alias: None,
+    }
+}
+
+/// `items` without repetitions, in order of first appearance.
+fn dedup<T: PartialEq>(items: impl IntoIterator<Item = T>) -> Vec<T> {
+    let mut unique = vec![];
+    for item in items {
+        if !unique.contains(&item) {
+            unique.push(item);
+        }
+    }
+    unique
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_prefixed, schemas::concert_singer};
+    use std::collections::HashMap;
+
+    /// Converts `sql` and checks that the QPL validates with its `<db_id> |` prefix.
+    fn convert(sql: &str) -> String {
+        let schema = concert_singer();
+        let qpl = from_sql(sql, &schema).unwrap();
+        let prefixed = format!("{} | {qpl}", schema.db_id);
+        let schemas = HashMap::from([(schema.db_id.clone(), schema)]);
+        parse_prefixed(&prefixed, &schemas, Mode::ParseWithGuards).unwrap();
+        qpl.to_string()
+    }
+
+    fn convert_err(sql: &str) -> ConvertError {
+        from_sql(sql, &concert_singer()).unwrap_err()
+    }
+
+    #[test]
+    fn test_scans_keep_their_conditions() {
+        assert_eq!(
+            convert("SELECT count(*) FROM singer"),
+            "#1 = Scan Table [ singer ] Output [ 1 AS One ] ; \
+             #2 = Aggregate [ #1 ] Output [ countstar AS Count_Star ]"
+        );
+        assert_eq!(
+            convert("SELECT DISTINCT country FROM singer WHERE 20 < age"),
+            "#1 = Scan Table [ singer ] Predicate [ Age > 20 ] Distinct [ true ] Output [ Country ]"
+        );
+        // Double-quoted words naming no column are strings.
+        assert_eq!(
+            convert(
+                "SELECT name FROM singer \
+                 WHERE age BETWEEN 20 AND 30 AND (country = \"France\" OR country = 'Spain')"
+            ),
+            "#1 = Scan Table [ singer ] Predicate [ Age BETWEEN 20 AND 30 \
+             AND ( Country = 'France' OR Country = 'Spain' ) ] Output [ Name ]"
+        );
+        assert_eq!(
+            convert("SELECT name FROM singer WHERE country NOT IN ('France', 'Spain')"),
+            "#1 = Scan Table [ singer ] Predicate [ Country NOT IN ( 'France' , 'Spain' ) ] \
+             Output [ Name ]"
+        );
+        assert_eq!(
+            convert("SELECT name FROM singer WHERE age > 20 OR (age < 10 AND age > 5)"),
+            "#1 = Scan Table [ singer ] Predicate [ Age > 20 OR Age < 10 AND Age > 5 ] \
+             Output [ Name ]"
+        );
+    }
+
+    #[test]
+    fn test_joins_only_output_used_columns() {
+        assert_eq!(
+            convert(
+                "SELECT T2.name FROM singer_in_concert AS T1 \
+                 JOIN singer AS T2 ON T1.singer_id = T2.singer_id \
+                 JOIN concert AS T3 ON T1.concert_id = T3.concert_id WHERE T3.year = 2014"
+            ),
+            "#1 = Scan Table [ singer_in_concert ] Output [ Singer_ID , concert_ID ] ; \
+             #2 = Scan Table [ singer ] Output [ Name , Singer_ID ] ; \
+             #3 = Scan Table [ concert ] Predicate [ Year = 2014 ] Output [ concert_ID ] ; \
+             #4 = Join [ #1 , #2 ] Predicate [ #1.Singer_ID = #2.Singer_ID ] \
+             Output [ #2.Name , #1.concert_ID ] ; \
+             #5 = Join [ #4 , #3 ] Predicate [ #4.concert_ID = #3.concert_ID ] Output [ #4.Name ]"
+        );
+    }
+
+    #[test]
+    fn test_group_by_having_order_and_limit() {
+        assert_eq!(
+            convert(
+                "SELECT T1.name FROM stadium AS T1 JOIN concert AS T2 \
+                 ON T1.stadium_id = T2.stadium_id GROUP BY T2.stadium_id HAVING count(*) > 1"
+            ),
+            "#1 = Scan Table [ stadium ] Output [ Name , Stadium_ID ] ; \
+             #2 = Scan Table [ concert ] Output [ Stadium_ID ] ; \
+             #3 = Join [ #1 , #2 ] Predicate [ #1.Stadium_ID = #2.Stadium_ID ] \
+             Output [ #2.Stadium_ID , #1.Name ] ; \
+             #4 = Aggregate [ #3 ] GroupBy [ Stadium_ID ] Output [ Name , countstar AS Count_Star ] ; \
+             #5 = Filter [ #4 ] Predicate [ Count_Star > 1 ] Output [ Name ]"
+        );
+        assert_eq!(
+            convert("SELECT year FROM concert GROUP BY year ORDER BY count(*) DESC LIMIT 1"),
+            "#1 = Scan Table [ concert ] Output [ Year ] ; \
+             #2 = Aggregate [ #1 ] GroupBy [ Year ] Output [ Year , countstar AS Count_Star ] ; \
+             #3 = TopSort [ #2 ] Rows [ 1 ] OrderBy [ Count_Star DESC ] Output [ Year ]"
+        );
+        assert_eq!(
+            convert("SELECT name, age FROM singer ORDER BY age"),
+            "#1 = Scan Table [ singer ] Output [ Name , Age ] ; \
+             #2 = Sort [ #1 ] OrderBy [ Age ASC ] Output [ Name , Age ]"
+        );
+        assert_eq!(
+            convert("SELECT DISTINCT count(DISTINCT country) FROM singer LIMIT 3"),
+            "#1 = Scan Table [ singer ] Output [ Country ] ; \
+             #2 = Aggregate [ #1 ] Output [ COUNT(DISTINCT Country) AS Count_Dist_Country ] ; \
+             #3 = Filter [ #2 ] Distinct [ true ] Output [ Count_Dist_Country ] ; \
+             #4 = Top [ #3 ] Rows [ 3 ] Output [ Count_Dist_Country ]"
+        );
+    }
+
+    #[test]
+    fn test_set_operations() {
+        assert_eq!(
+            convert(
+                "SELECT country FROM singer WHERE age > 40 \
+                 INTERSECT SELECT country FROM singer WHERE age < 30"
+            ),
+            "#1 = Scan Table [ singer ] Predicate [ Age > 40 ] Output [ Country ] ; \
+             #2 = Scan Table [ singer ] Predicate [ Age < 30 ] Output [ Country ] ; \
+             #3 = Intersect [ #1 , #2 ] Predicate [ #1.Country = #2.Country ] Distinct [ true ] \
+             Output [ #1.Country ]"
+        );
+        assert_eq!(
+            convert(
+                "SELECT country, age FROM singer \
+                 INTERSECT SELECT country, age FROM singer WHERE age < 30"
+            ),
+            "#1 = Scan Table [ singer ] Output [ Country , Age ] ; \
+             #2 = Scan Table [ singer ] Predicate [ Age < 30 ] Output [ Country , Age ] ; \
+             #3 = Intersect [ #1 , #2 ] Predicate [ #1.Country = #2.Country AND #1.Age = #2.Age ] \
+             Distinct [ true ] Output [ #1.Country , #1.Age ]"
+        );
+        assert_eq!(
+            convert(
+                "SELECT name, age FROM singer EXCEPT SELECT name, age FROM singer WHERE age > 40"
+            ),
+            "#1 = Scan Table [ singer ] Output [ Name , Age ] ; \
+             #2 = Scan Table [ singer ] Predicate [ Age > 40 ] Output [ Name , Age ] ; \
+             #3 = Except [ #1 , #2 ] Predicate [ #1.Name = #2.Name AND #1.Age = #2.Age ] \
+             Distinct [ true ] Output [ #1.Name , #1.Age ]"
+        );
+        assert_eq!(
+            convert(
+                "SELECT name FROM singer WHERE country = 'France' \
+                 UNION SELECT name FROM singer WHERE age > 40 ORDER BY name LIMIT 2"
+            ),
+            "#1 = Scan Table [ singer ] Predicate [ Country = 'France' ] Output [ Name ] ; \
+             #2 = Scan Table [ singer ] Predicate [ Age > 40 ] Output [ Name ] ; \
+             #3 = Union [ #1 , #2 ] Output [ #1.Name ] ; \
+             #4 = TopSort [ #3 ] Rows [ 2 ] OrderBy [ Name ASC ] Output [ Name ]"
+        );
+    }
+
+    #[test]
+    fn test_subqueries() {
+        assert_eq!(
+            convert("SELECT name FROM stadium WHERE stadium_id NOT IN (SELECT stadium_id FROM concert)"),
+            "#1 = Scan Table [ stadium ] Output [ Name , Stadium_ID ] ; \
+             #2 = Scan Table [ concert ] Output [ Stadium_ID ] ; \
+             #3 = Except [ #1 , #2 ] Predicate [ #2.Stadium_ID IS NULL OR #1.Stadium_ID = #2.Stadium_ID ] \
+             Output [ #1.Name ]"
+        );
+        assert_eq!(
+            convert(
+                "SELECT name FROM stadium WHERE stadium_id IN (SELECT stadium_id FROM concert)"
+            ),
+            "#1 = Scan Table [ stadium ] Output [ Name , Stadium_ID ] ; \
+             #2 = Scan Table [ concert ] Output [ Stadium_ID ] ; \
+             #3 = Intersect [ #1 , #2 ] Predicate [ #1.Stadium_ID = #2.Stadium_ID ] \
+             Output [ #1.Name ]"
+        );
+        assert_eq!(
+            convert("SELECT song_name FROM singer WHERE age > (SELECT avg(age) FROM singer)"),
+            "#1 = Scan Table [ singer ] Output [ Song_Name , Age ] ; \
+             #2 = Scan Table [ singer ] Output [ Age ] ; \
+             #3 = Aggregate [ #2 ] Output [ AVG(Age) AS Avg_Age ] ; \
+             #4 = Join [ #1 , #3 ] Predicate [ #1.Age > #3.Avg_Age ] Output [ #1.Song_Name ]"
+        );
+    }
+
+    #[test]
+    fn test_unsupported_sql_is_reported() {
+        assert!(matches!(
+            convert_err("SELECT FROM"),
+            ConvertError::Syntax(_)
+        ));
+        assert_eq!(
+            convert_err("SELECT name FROM band"),
+            ConvertError::UnknownTable("band".to_owned())
+        );
+        assert_eq!(
+            convert_err("SELECT nickname FROM singer"),
+            ConvertError::UnknownColumn("nickname".to_owned())
+        );
+        assert_eq!(
+            convert_err(
+                "SELECT stadium_id FROM stadium AS T1 JOIN concert AS T2 \
+                 ON T1.stadium_id = T2.stadium_id"
+            ),
+            ConvertError::AmbiguousColumn("stadium_id".to_owned())
+        );
+        assert!(matches!(
+            convert_err("SELECT T1.name FROM stadium AS T1 LEFT JOIN concert AS T2 ON T1.stadium_id = T2.stadium_id"),
+            ConvertError::Unsupported(_)
+        ));
+        assert!(matches!(
+            convert_err("SELECT name FROM singer WHERE country = 'Côte d''Ivoire'"),
+            ConvertError::Unsupported(_)
+        ));
+        assert!(matches!(
+            convert_err(
+                "SELECT name FROM singer WHERE age > 20 \
+                 OR singer_id IN (SELECT singer_id FROM singer_in_concert)"
+            ),
+            ConvertError::Unsupported(_)
+        ));
+    }
+}
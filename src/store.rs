@@ -0,0 +1,155 @@
+use rusty_picard::domain::SqlSchema;
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+const SCHEMAS_DIR: &str = "schemas";
+const TOKENIZERS_DIR: &str = "tokenizers";
+/// Where the single tokenizer used to be stored, loaded as the default tokenizer.
+const LEGACY_TOKENIZER_FILE: &str = "tokenizer.json";
+
+/// Directory where registered schemas and tokenizers are kept across restarts.
+///
+/// Each schema is stored as `schemas/<db_id>.json`, each tokenizer as `tokenizers/<name>.json`.
+#[derive(Debug)]
+pub(crate) struct StateDir {
+    root: PathBuf,
+}
+
+impl StateDir {
+    pub(crate) fn open(root: impl Into<PathBuf>) -> io::Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(root.join(SCHEMAS_DIR))?;
+        fs::create_dir_all(root.join(TOKENIZERS_DIR))?;
+        Ok(Self { root })
+    }
+
+    pub(crate) fn load_schemas(&self) -> io::Result<Vec<SqlSchema>> {
+        let mut schemas = vec![];
+        for path in json_files(&self.root.join(SCHEMAS_DIR))? {
+            let schema = serde_json::from_slice(&fs::read(&path)?)
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+            schemas.push(schema);
+        }
+        Ok(schemas)
+    }
+
+    pub(crate) fn save_schema(&self, schema: &SqlSchema) -> io::Result<()> {
+        let contents = serde_json::to_vec(schema)?;
+        write_atomically(&self.file_path(SCHEMAS_DIR, &schema.db_id)?, &contents)
+    }
+
+    pub(crate) fn remove_schema(&self, db_id: &str) -> io::Result<()> {
+        match fs::remove_file(self.file_path(SCHEMAS_DIR, db_id)?) {
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Returns the persisted tokenizers as `(name, repr)` pairs.
+    pub(crate) fn load_tokenizers(&self, default_name: &str) -> io::Result<Vec<(String, String)>> {
+        let mut tokenizers = vec![];
+        match fs::read_to_string(self.root.join(LEGACY_TOKENIZER_FILE)) {
+            Ok(repr) => tokenizers.push((default_name.to_owned(), repr)),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        for path in json_files(&self.root.join(TOKENIZERS_DIR))? {
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            tokenizers.retain(|(n, _)| *n != name);
+            tokenizers.push((name, fs::read_to_string(&path)?));
+        }
+        Ok(tokenizers)
+    }
+
+    pub(crate) fn save_tokenizer(&self, name: &str, repr: &str) -> io::Result<()> {
+        write_atomically(&self.file_path(TOKENIZERS_DIR, name)?, repr.as_bytes())
+    }
+
+    fn file_path(&self, dir: &str, name: &str) -> io::Result<PathBuf> {
+        check_name(name).map_err(|reason| io::Error::new(ErrorKind::InvalidInput, reason))?;
+        Ok(self.root.join(dir).join(format!("{name}.json")))
+    }
+}
+
+/// Checks that `name` can be stored as a file of its own, so that callers can reject it
+/// before changing anything.
+pub(crate) fn check_name(name: &str) -> Result<(), String> {
+    let is_file_name = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if is_file_name {
+        Ok(())
+    } else {
+        Err(format!("{name:?} cannot be used as a file name"))
+    }
+}
+
+fn json_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+/// Writes through a temporary file so a crash never leaves a truncated file behind.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schemas::concert_singer;
+
+    #[test]
+    fn test_state_dir_round_trips_schemas_and_tokenizer() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateDir::open(dir.path()).unwrap();
+        assert!(store.load_tokenizers("default").unwrap().is_empty());
+
+        store.save_schema(&concert_singer()).unwrap();
+        store.save_tokenizer("t5", "{}").unwrap();
+
+        let store = StateDir::open(dir.path()).unwrap();
+        assert_eq!(store.load_schemas().unwrap(), vec![concert_singer()]);
+        assert_eq!(
+            store.load_tokenizers("default").unwrap(),
+            vec![("t5".to_owned(), "{}".to_owned())]
+        );
+
+        store.remove_schema("concert_singer").unwrap();
+        assert!(store.load_schemas().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_state_dir_rejects_path_like_db_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = StateDir::open(dir.path()).unwrap();
+        let mut schema = concert_singer();
+        schema.db_id = "../concert_singer".to_owned();
+        assert!(store.save_schema(&schema).is_err());
+        assert!(store.save_tokenizer("a/b", "{}").is_err());
+    }
+
+    #[test]
+    fn test_state_dir_loads_legacy_tokenizer_as_default() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(LEGACY_TOKENIZER_FILE), "{}").unwrap();
+        let store = StateDir::open(dir.path()).unwrap();
+        assert_eq!(
+            store.load_tokenizers("default").unwrap(),
+            vec![("default".to_owned(), "{}".to_owned())]
+        );
+    }
+}
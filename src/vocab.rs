@@ -0,0 +1,154 @@
+use crate::api::FeedResult;
+use rayon::prelude::*;
+use rusty_picard::decoding::EOS;
+use std::collections::BTreeMap;
+use tokenizers::Tokenizer;
+
+/// Prefix tree over the surface strings of a tokenizer's vocabulary.
+///
+/// Tokens ending with the end-of-sequence marker are kept aside, since they complete the
+/// QPL instead of extending it.
+#[derive(Debug, Default)]
+pub(crate) struct VocabTrie {
+    root: TrieNode,
+    eos_tokens: Vec<(u32, String)>,
+}
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    children: BTreeMap<char, TrieNode>,
+    token_ids: Vec<u32>,
+}
+
+impl VocabTrie {
+    pub(crate) fn from_tokenizer(tokenizer: &Tokenizer) -> Self {
+        // Decoding a token on its own may drop its leading space, so decode it after an
+        // anchor token and keep only what it appended.
+        let anchor = tokenizer.token_to_id("a");
+        let anchor_text = anchor
+            .and_then(|anchor| tokenizer.decode(&[anchor], false).ok())
+            .unwrap_or_default();
+        let surfaces = (0..tokenizer.get_vocab_size(true) as u32).filter_map(|id| {
+            let surface = match anchor {
+                Some(anchor) => tokenizer
+                    .decode(&[anchor, id], false)
+                    .ok()?
+                    .strip_prefix(&anchor_text)?
+                    .to_owned(),
+                None => tokenizer.decode(&[id], false).ok()?,
+            };
+            Some((id, surface))
+        });
+        Self::from_surfaces(surfaces)
+    }
+
+    pub(crate) fn from_surfaces(surfaces: impl IntoIterator<Item = (u32, String)>) -> Self {
+        let mut trie = Self::default();
+        for (id, surface) in surfaces {
+            if surface.is_empty() {
+                continue;
+            }
+            if surface.ends_with(EOS) {
+                trie.eos_tokens.push((id, surface));
+                continue;
+            }
+            let node = surface.chars().fold(&mut trie.root, |node, c| {
+                node.children.entry(c).or_default()
+            });
+            node.token_ids.push(id);
+        }
+        trie
+    }
+
+    /// Returns, in ascending order, the ids of every token that keeps `decoded` a valid QPL
+    /// prefix or completes it, according to `feed`.
+    ///
+    /// A prefix that fails cannot be extended into a valid one, so every token below it in
+    /// the trie is skipped without being fed.
+    pub(crate) fn valid_tokens(
+        &self,
+        decoded: &str,
+        feed: impl Fn(&str) -> FeedResult + Sync,
+    ) -> Vec<u32> {
+        let mut valid = self
+            .root
+            .children
+            .par_iter()
+            .flat_map_iter(|(c, child)| {
+                let mut text = format!("{decoded}{c}");
+                let mut valid = vec![];
+                child.walk(&mut text, &feed, &mut valid);
+                valid
+            })
+            .collect::<Vec<_>>();
+        valid.extend(
+            self.eos_tokens
+                .iter()
+                .filter(|(_, surface)| {
+                    !matches!(feed(&format!("{decoded}{surface}")), FeedResult::Failure)
+                })
+                .map(|(id, _)| *id),
+        );
+        valid.sort_unstable();
+        valid
+    }
+}
+
+impl TrieNode {
+    fn walk(&self, text: &mut String, feed: &impl Fn(&str) -> FeedResult, valid: &mut Vec<u32>) {
+        // Nodes on a single-child chain hold no token, so the check can wait for the next one.
+        let needs_check = !self.token_ids.is_empty() || self.children.len() != 1;
+        if needs_check && matches!(feed(text), FeedResult::Failure) {
+            return;
+        }
+        valid.extend_from_slice(&self.token_ids);
+        for (c, child) in &self.children {
+            text.push(*c);
+            child.walk(text, feed, valid);
+            text.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn trie() -> VocabTrie {
+        VocabTrie::from_surfaces(
+            [" Scan", " S", " Sort", " Top", "can", "</s>", " ;</s>"]
+                .into_iter()
+                .zip(0..)
+                .map(|(surface, id)| (id, surface.to_owned())),
+        )
+    }
+
+    fn feed(text: &str) -> FeedResult {
+        const QPL: &str = "#1 = Scan";
+        match text.strip_suffix(EOS) {
+            Some(text) if text == QPL => FeedResult::Complete,
+            Some(_) => FeedResult::Failure,
+            None if QPL.starts_with(text) => FeedResult::Partial,
+            None => FeedResult::Failure,
+        }
+    }
+
+    #[test]
+    fn test_valid_tokens_keep_prefix_valid() {
+        assert_eq!(trie().valid_tokens("#1 =", feed), vec![0, 1]);
+        assert_eq!(trie().valid_tokens("#1 = S", feed), vec![4]);
+        assert_eq!(trie().valid_tokens("#1 = Scan", feed), vec![5]);
+    }
+
+    #[test]
+    fn test_valid_tokens_skip_failed_prefixes() {
+        let calls = AtomicUsize::new(0);
+        trie().valid_tokens("#1 =", |text| {
+            calls.fetch_add(1, Ordering::Relaxed);
+            feed(text)
+        });
+        // " ", " S", " Scan", " Sort", " Top", "can" and the two end-of-sequence tokens
+        assert_eq!(calls.into_inner(), 8);
+    }
+}